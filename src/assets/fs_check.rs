@@ -5,10 +5,9 @@ use std::fmt::{Display, Formatter};
 use std::path::PathBuf;
 use std::sync::Arc;
 
-use crate::Config;
-use crate::assets::util::join_monster_and_form;
 use crate::cache::CacheBehaviour;
 use crate::cache::ScCache;
+use crate::data_root::DataRoot;
 use crate::datafiles::local_credits_file::{LocalCreditRow, get_credits};
 use crate::datafiles::tracker::MapImpl;
 use crate::datafiles::{DataReadError, DataReadResult};
@@ -29,8 +28,8 @@ impl Display for AssetCategory {
 }
 
 enum FileLookup<'a, I: Iterator<Item = &'a String> + Clone> {
-    Sprite(I, i32, &'a [i32]),
-    Portrait(I, i32, &'a [i32]),
+    Sprite(&'a DataRoot, I, i32, &'a [i32]),
+    Portrait(&'a DataRoot, I, i32, &'a [i32]),
 }
 
 impl<'a, C> FileLookup<'a, C>
@@ -43,22 +42,18 @@ where
 
     fn all(&self) -> C {
         match self {
-            FileLookup::Sprite(all, _, _) => all.clone(),
-            FileLookup::Portrait(all, _, _) => all.clone(),
+            FileLookup::Sprite(_, all, _, _) => all.clone(),
+            FileLookup::Portrait(_, all, _, _) => all.clone(),
         }
     }
 
     fn path(&self, act: &str) -> PathBuf {
         match self {
-            FileLookup::Sprite(_, mon, path) => {
-                let joined_p = join_monster_and_form(*mon, path, '/');
-                PathBuf::from(Config::Workdir.get())
-                    .join(format!("spritecollab/sprite/{}/{}-Anim.png", joined_p, act))
-            }
-            FileLookup::Portrait(_, mon, path) => {
-                let joined_p = join_monster_and_form(*mon, path, '/');
-                PathBuf::from(Config::Workdir.get())
-                    .join(format!("spritecollab/portrait/{}/{}.png", joined_p, act))
+            FileLookup::Sprite(root, _, mon, path) => root
+                .sprite_dir(*mon, path)
+                .join(format!("{}-Anim.png", act)),
+            FileLookup::Portrait(root, _, mon, path) => {
+                root.portrait_dir(*mon, path).join(format!("{}.png", act))
             }
         }
     }
@@ -81,14 +76,22 @@ impl FileLookupCache {
         I: Iterator<Item = &'a String> + Send + Sync + Clone,
     {
         let data = match lookup {
-            FileLookup::Sprite(_, mon, pat) => {
+            FileLookup::Sprite(root, _, mon, pat) => {
                 cache
-                    .cached(format!("spr_files|{}/{:?}", mon, pat), || lookup.lookup())
+                    .cached(
+                        format!("spr_files|{}/{:?}", mon, pat),
+                        root.commit(),
+                        || lookup.lookup(),
+                    )
                     .await
             }
-            FileLookup::Portrait(_, mon, pat) => {
+            FileLookup::Portrait(root, _, mon, pat) => {
                 cache
-                    .cached(format!("prt_files|{}/{:?}", mon, pat), || lookup.lookup())
+                    .cached(
+                        format!("prt_files|{}/{:?}", mon, pat),
+                        root.commit(),
+                        || lookup.lookup(),
+                    )
                     .await
             }
         }?;
@@ -116,13 +119,14 @@ impl FileLookupCache {
 
 pub async fn iter_existing_sprite_files<C: ScCache + Send + Sync>(
     cache: &C,
+    root: &DataRoot,
     sprite_files: &MapImpl<String, bool>,
     monster_idx: i32,
     form_path: &[i32],
 ) -> Result<impl IntoIterator<Item = (String, bool)>, C::Error> {
     let mut lookup_cache = FileLookupCache::new(
         cache,
-        FileLookup::Sprite(sprite_files.keys(), monster_idx, form_path),
+        FileLookup::Sprite(root, sprite_files.keys(), monster_idx, form_path),
     )
     .await?;
     Ok(sprite_files
@@ -133,6 +137,7 @@ pub async fn iter_existing_sprite_files<C: ScCache + Send + Sync>(
 
 pub async fn get_existing_sprite_file<C: ScCache + Send + Sync>(
     cache: &C,
+    root: &DataRoot,
     sprite_files: &MapImpl<String, bool>,
     action: &str,
     monster_idx: i32,
@@ -140,7 +145,7 @@ pub async fn get_existing_sprite_file<C: ScCache + Send + Sync>(
 ) -> Result<Option<bool>, C::Error> {
     let lookup_cache = FileLookupCache::new(
         cache,
-        FileLookup::Sprite(sprite_files.keys(), monster_idx, form_path),
+        FileLookup::Sprite(root, sprite_files.keys(), monster_idx, form_path),
     )
     .await?;
     Ok(sprite_files
@@ -150,6 +155,7 @@ pub async fn get_existing_sprite_file<C: ScCache + Send + Sync>(
 
 pub async fn iter_existing_portrait_files<C: ScCache + Send + Sync>(
     cache: &C,
+    root: &DataRoot,
     portrait_files: &MapImpl<String, bool>,
     flipped: bool,
     monster_idx: i32,
@@ -157,7 +163,7 @@ pub async fn iter_existing_portrait_files<C: ScCache + Send + Sync>(
 ) -> Result<impl IntoIterator<Item = (String, bool)>, C::Error> {
     let mut lookup_cache = FileLookupCache::new(
         cache,
-        FileLookup::Portrait(portrait_files.keys(), monster_idx, form_path),
+        FileLookup::Portrait(root, portrait_files.keys(), monster_idx, form_path),
     )
     .await?;
     Ok(portrait_files
@@ -175,6 +181,7 @@ pub async fn iter_existing_portrait_files<C: ScCache + Send + Sync>(
 
 pub async fn get_existing_portrait_file<C: ScCache + Send + Sync>(
     cache: &C,
+    root: &DataRoot,
     portrait_files: &MapImpl<String, bool>,
     emotion: &str,
     flipped: bool,
@@ -183,7 +190,7 @@ pub async fn get_existing_portrait_file<C: ScCache + Send + Sync>(
 ) -> Result<Option<bool>, C::Error> {
     let lookup_cache = FileLookupCache::new(
         cache,
-        FileLookup::Portrait(portrait_files.keys(), monster_idx, form_path),
+        FileLookup::Portrait(root, portrait_files.keys(), monster_idx, form_path),
     )
     .await?;
     let emotion = if flipped {
@@ -198,6 +205,7 @@ pub async fn get_existing_portrait_file<C: ScCache + Send + Sync>(
 
 pub async fn get_local_credits_file<C: ScCache + Send + Sync>(
     cache: &C,
+    root: &DataRoot,
     asset_type: AssetCategory,
     monster_idx: i32,
     form_path: &[i32],
@@ -205,14 +213,13 @@ pub async fn get_local_credits_file<C: ScCache + Send + Sync>(
     let content_result: DataReadResult<Option<Vec<u8>>> = cache
         .cached_may_fail(
             format!("credits_{}|{}/{:?}", asset_type, monster_idx, form_path),
+            root.commit(),
             || async {
-                let joined_p = join_monster_and_form(monster_idx, form_path, '/');
                 let path = match asset_type {
-                    AssetCategory::Sprite => PathBuf::from(Config::Workdir.get())
-                        .join(format!("spritecollab/sprite/{}/credits.txt", joined_p)),
-                    AssetCategory::Portrait => PathBuf::from(Config::Workdir.get())
-                        .join(format!("spritecollab/portrait/{}/credits.txt", joined_p)),
-                };
+                    AssetCategory::Sprite => root.sprite_dir(monster_idx, form_path),
+                    AssetCategory::Portrait => root.portrait_dir(monster_idx, form_path),
+                }
+                .join("credits.txt");
                 if path.exists() {
                     Ok(CacheBehaviour::Cache(Some(tokio::fs::read(path).await?)))
                 } else {
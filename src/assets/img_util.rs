@@ -1,6 +1,9 @@
 use std::io::Cursor;
 
 use image::{Rgba, RgbaImage};
+use indexmap::IndexMap;
+
+use crate::assets::palette::count_colors;
 
 pub fn to_png(img: RgbaImage) -> Result<Vec<u8>, anyhow::Error> {
     let mut png = Vec::new();
@@ -9,16 +12,9 @@ pub fn to_png(img: RgbaImage) -> Result<Vec<u8>, anyhow::Error> {
 }
 
 pub fn add_palette_to(img: &mut RgbaImage) {
-    let mut palette: Vec<Rgba<u8>> = Vec::with_capacity(32);
-    for px in img.pixels() {
-        if px.0[3] == 0 {
-            continue;
-        }
-        if !palette.contains(px) {
-            palette.push(*px);
-        }
-    }
-    for (x, px) in palette.into_iter().enumerate() {
-        img.put_pixel(x as u32, 0, px);
+    let mut palette = IndexMap::with_capacity(32);
+    count_colors(img, &mut palette);
+    for (x, px) in palette.into_keys().enumerate() {
+        img.put_pixel(x as u32, 0, Rgba(px));
     }
 }
@@ -1,33 +1,37 @@
 use std::error::Error;
 use std::fmt::Debug;
 use std::io::{Cursor, Write};
-use std::path::{Path, PathBuf};
+use std::path::Path;
 use std::sync::Arc;
 
 use http_body_util::combinators::BoxBody;
-use http_body_util::{BodyExt, Full};
-use hyper::body::{Body, Bytes};
+use http_body_util::{BodyExt, Full, Limited};
+use hyper::body::{Body, Bytes, Incoming};
 use hyper::http::HeaderValue;
-use hyper::{Method, Response, StatusCode};
+use hyper::{Method, Request, Response, StatusCode};
 use log::warn;
 use tokio::fs;
 use zip::ZipWriter;
 
-use crate::assets::portrait_sheets::{
-    PortraitSheetEmotions, make_portrait_recolor_sheet, make_portrait_sheet,
+use crate::SpriteCollab;
+use crate::assets::recolor::{
+    RecolorError, derive_recolor_mapping, make_recolored_portraits_zip, make_recolored_sprites_zip,
 };
-use crate::assets::sprite_sheets::make_sprite_recolor_sheet;
+use crate::assets::rendered::{RenderData, RenderedAsset};
 use crate::assets::url::{AssetType, match_url};
-use crate::assets::util::{force_non_shiny_group, join_monster_and_form};
 use crate::cache::CacheBehaviour;
 use crate::cache::ScCache;
+use crate::config::Config;
 use crate::datafiles::tracker::{FormMatch, MonsterFormCollector};
-use crate::{Config, SpriteCollab};
 
 pub mod fs_check;
 mod img_util;
+pub mod palette;
+pub mod pinned;
 mod portrait_sheets;
-mod sprite_sheets;
+mod recolor;
+pub mod rendered;
+pub mod sprite_sheets;
 pub mod url;
 pub mod util;
 
@@ -44,63 +48,109 @@ where
     }))
 }
 
+/// Reads the request body. Returns an error response if it is too large
+/// (`SCSRV_MAX_UPLOAD_SIZE`) or can't be read.
+pub async fn read_body(body: Incoming) -> Result<Bytes, Response<AssetBody>> {
+    let max_size = Config::max_upload_size();
+    match Limited::new(body, max_size).collect().await {
+        Ok(collected) => Ok(collected.to_bytes()),
+        Err(e) => Err(make_bad_request_response(format!(
+            "Failed reading request body (max. {} bytes): {}",
+            max_size, e
+        ))
+        .map(make_box_body)),
+    }
+}
+
 pub async fn match_and_process_assets_path(
-    method: &Method,
-    path: &str,
+    req: Request<Incoming>,
     sprite_collab: Arc<SpriteCollab>,
 ) -> Option<Response<AssetBody>> {
-    if method != Method::GET {
+    let (parts, body) = req.into_parts();
+    let method = parts.method;
+    let path = parts.uri.path();
+    if method != Method::GET && method != Method::POST {
         return None;
     }
     if let Some((monster_idx, form_path, asset_type)) = match_url(path) {
-        let portrait_tile_x;
-        let portrait_size;
-        let emotions_incl_flipped;
-        let tracker;
-        {
-            let data = sprite_collab.data();
-            portrait_tile_x = data.sprite_config.portrait_tile_x;
-            portrait_size = data.sprite_config.portrait_size;
-            emotions_incl_flipped = data
-                .sprite_config
-                .emotions
-                .iter()
-                .cloned()
-                .chain(
-                    data.sprite_config
-                        .emotions
-                        .iter()
-                        .map(|e| format!("{}^", e)),
-                )
-                .collect::<Vec<_>>();
-            tracker = data.tracker.clone();
-        }
-        let collector = MonsterFormCollector::collect(&tracker, monster_idx)?;
-        let (form_path, _, group) = match asset_type {
-            AssetType::PortraitRecolorSheet => collector.find_form(
-                force_non_shiny_group(&form_path)
-                    .into_iter()
-                    .map(FormMatch::Exact),
-            )?,
-            AssetType::SpriteRecolorSheet => collector.find_form(
-                force_non_shiny_group(&form_path)
-                    .into_iter()
-                    .map(FormMatch::Exact),
-            )?,
-            _ => collector.find_form(form_path.into_iter().map(FormMatch::Exact))?,
+        let rendered = RenderedAsset::from_asset_type(&asset_type);
+        let render_data = RenderData::new(&sprite_collab);
+        let collector = MonsterFormCollector::collect(&render_data.tracker, monster_idx)?;
+        let (form_path, _, group) = match rendered {
+            Some(asset) => asset.find_form(&collector, form_path.into())?,
+            None => collector.find_form(form_path.into_iter().map(FormMatch::Exact))?,
         };
 
-        let joined_p = join_monster_and_form(monster_idx, &form_path, '/');
-        let portrait_base_path = PathBuf::from(Config::Workdir.get())
-            .join(format!("spritecollab/portrait/{}", joined_p));
-        let sprite_base_path =
-            PathBuf::from(Config::Workdir.get()).join(format!("spritecollab/sprite/{}", joined_p));
+        let portrait_base_path = render_data.portrait_dir(monster_idx, &form_path);
+        let sprite_base_path = render_data.sprite_dir(monster_idx, &form_path);
+
+        if method == Method::POST {
+            // Applying edited recolor sheets.
+            let original_sheet = match rendered {
+                Some(
+                    asset @ (RenderedAsset::PortraitRecolorSheet
+                    | RenderedAsset::SpriteRecolorSheet),
+                ) => {
+                    render_data
+                        .render(&sprite_collab, asset, monster_idx, &form_path, group)
+                        .await
+                }
+                _ => return None,
+            };
+            let original_sheet = match original_sheet {
+                Ok(Ok(v)) => v,
+                Ok(Err(e)) => return Some(make_err_response(e, path).map(make_box_body)),
+                Err(e) => return Some(make_err_response(e, path).map(make_box_body)),
+            };
+            let edited_sheet = match read_body(body).await {
+                Ok(v) => v,
+                Err(response) => return Some(response),
+            };
+            let mapping = match derive_recolor_mapping(&original_sheet, &edited_sheet) {
+                Ok(v) => v,
+                Err(RecolorError::InvalidSheet(reason)) => {
+                    return Some(make_bad_request_response(reason).map(make_box_body));
+                }
+                Err(RecolorError::Other(e)) => {
+                    return Some(make_err_response(e, path).map(make_box_body));
+                }
+            };
+            let zip = match asset_type {
+                AssetType::PortraitRecolorSheet => {
+                    make_recolored_portraits_zip(group, &portrait_base_path, &mapping)
+                }
+                _ => make_recolored_sprites_zip(group, &sprite_base_path, &mapping),
+            };
+            return Some(process_nested_result(
+                Ok::<_, anyhow::Error>(zip.map(|buf| {
+                    ZipResponse(make_box_body(Full::new(Bytes::from(buf))), "recolor.zip")
+                })),
+                path,
+            ));
+        }
+
+        if let Some(asset) = rendered {
+            let result = render_data
+                .render(&sprite_collab, asset, monster_idx, &form_path, group)
+                .await;
+            let body = |buf: Vec<u8>| make_box_body(Full::new(Bytes::from(buf)));
+            return Some(match asset {
+                RenderedAsset::SpriteZip => process_nested_result(
+                    result.map(|r| r.map(|buf| ZipResponse(body(buf), "sprite.zip"))),
+                    path,
+                ),
+                _ => {
+                    process_nested_result(result.map(|r| r.map(|buf| PngResponse(body(buf)))), path)
+                }
+            });
+        }
 
         match asset_type {
             AssetType::PortraitCreditsTxt => Some(process_nested_result(
                 sprite_collab
                     .cached_may_fail(
                         format!("portrait_credits_txt|{}/{:?}", monster_idx, form_path),
+                        render_data.commit(),
                         || make_credits_txt(&portrait_base_path),
                     )
                     .await
@@ -111,86 +161,13 @@ pub async fn match_and_process_assets_path(
                 sprite_collab
                     .cached_may_fail(
                         format!("sprite_credits_txt|{}/{:?}", monster_idx, form_path),
+                        render_data.commit(),
                         || make_credits_txt(&sprite_base_path),
                     )
                     .await
                     .map(|r| r.map(make_box_body).map(Response::new)),
                 path,
             )),
-            AssetType::PortraitSheet => Some(process_nested_result(
-                sprite_collab
-                    .cached_may_fail(
-                        format!("portrait_sheet|{}/{:?}", monster_idx, form_path),
-                        || {
-                            make_portrait_sheet(
-                                group,
-                                PortraitSheetEmotions::new(emotions_incl_flipped, portrait_tile_x),
-                                &portrait_base_path,
-                                portrait_size,
-                            )
-                        },
-                    )
-                    .await
-                    .map(|r| {
-                        r.map(Bytes::from)
-                            .map(Full::new)
-                            .map(make_box_body)
-                            .map(PngResponse)
-                    }),
-                path,
-            )),
-            AssetType::PortraitRecolorSheet => Some(process_nested_result(
-                sprite_collab
-                    .cached_may_fail(
-                        format!("portrait_recolor_sheet|{}/{:?}", monster_idx, form_path),
-                        || {
-                            make_portrait_recolor_sheet(
-                                group,
-                                PortraitSheetEmotions::new(emotions_incl_flipped, portrait_tile_x),
-                                &portrait_base_path,
-                                portrait_size,
-                            )
-                        },
-                    )
-                    .await
-                    .map(|r| {
-                        r.map(Bytes::from)
-                            .map(Full::new)
-                            .map(make_box_body)
-                            .map(PngResponse)
-                    }),
-                path,
-            )),
-            AssetType::SpriteZip => Some(process_nested_result(
-                sprite_collab
-                    .cached_may_fail(
-                        format!("sprite_zip|{}/{:?}", monster_idx, form_path),
-                        || make_sprite_zip(&sprite_base_path),
-                    )
-                    .await
-                    .map(|r| {
-                        r.map(Bytes::from)
-                            .map(Full::new)
-                            .map(make_box_body)
-                            .map(ZipResponse)
-                    }),
-                path,
-            )),
-            AssetType::SpriteRecolorSheet => Some(process_nested_result(
-                sprite_collab
-                    .cached_may_fail(
-                        format!("sprite_recolor_sheet|{}/{:?}", monster_idx, form_path),
-                        || make_sprite_recolor_sheet(&sprite_base_path),
-                    )
-                    .await
-                    .map(|r| {
-                        r.map(Bytes::from)
-                            .map(Full::new)
-                            .map(make_box_body)
-                            .map(PngResponse)
-                    }),
-                path,
-            )),
             _ => None,
         }
     } else {
@@ -269,7 +246,16 @@ pub fn make_err_response<E: Debug>(err: E, request_path: &str) -> Response<Strin
         )))
 }
 
-struct ZipResponse(AssetBody);
+pub fn make_bad_request_response<S: AsRef<str>>(reason: S) -> Response<String> {
+    Response::builder()
+        .status(StatusCode::BAD_REQUEST)
+        .header("Content-Type", "text/plain; charset=utf-8")
+        .body(reason.as_ref().to_string())
+        .unwrap_or_else(|_| Response::new(String::from("Bad Request")))
+}
+
+/// A ZIP archive response with the file name to use for downloads.
+struct ZipResponse(AssetBody, &'static str);
 
 impl TryInto<Response<AssetBody>> for ZipResponse {
     type Error = anyhow::Error;
@@ -280,7 +266,7 @@ impl TryInto<Response<AssetBody>> for ZipResponse {
         headers.insert("Content-Type", HeaderValue::from_str("application/zip")?);
         headers.insert(
             "Content-Disposition",
-            HeaderValue::from_str("attachment; filename=sprite.zip")?,
+            HeaderValue::from_str(&format!("attachment; filename={}", self.1))?,
         );
         Ok(resp)
     }
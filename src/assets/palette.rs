@@ -0,0 +1,287 @@
+//! Palette analysis of portraits and sprites. Used to check that shiny forms are strict
+//! 1:1 recolors of their base forms, like SpriteBot requires them to be.
+
+use std::path::{Path, PathBuf};
+
+use image::RgbaImage;
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+
+use crate::datafiles::tracker::Group;
+
+/// A single RGBA colour. Fully transparent pixels are always normalized to `[0, 0, 0, 0]`.
+pub type Color = [u8; 4];
+
+const TRANSPARENT: Color = [0, 0, 0, 0];
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ColorCollision {
+    /// The colour of the base form.
+    pub base: Color,
+    /// All colours of the shiny form the base colour was mapped to.
+    pub shiny: Vec<Color>,
+    /// Files in which the collision occurred.
+    pub files: Vec<String>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ShapeMismatch {
+    /// File name (emotion or sprite sheet) the mismatch was found in.
+    pub file: String,
+    pub reason: String,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct PaletteCheckReport {
+    /// The derived base -> shiny colour mapping. For collisions, the most common mapping is used.
+    pub mapping: Vec<(Color, Color)>,
+    pub collisions: Vec<ColorCollision>,
+    pub shape_mismatches: Vec<ShapeMismatch>,
+    /// Names of all files that were compared.
+    pub compared_files: Vec<String>,
+}
+
+impl PaletteCheckReport {
+    pub fn is_consistent(&self) -> bool {
+        self.collisions.is_empty() && self.shape_mismatches.is_empty()
+    }
+}
+
+/// Collects the colour correspondence of pairs of images.
+#[derive(Default)]
+pub struct PaletteComparison {
+    // base colour -> (shiny colour -> (pixel count, files))
+    mapping: IndexMap<Color, IndexMap<Color, (usize, Vec<String>)>>,
+    shape_mismatches: Vec<ShapeMismatch>,
+    compared_files: Vec<String>,
+}
+
+impl PaletteComparison {
+    /// Compares two images pixel by pixel and records the colour correspondence.
+    pub fn compare(&mut self, file: &str, base: &RgbaImage, shiny: &RgbaImage) {
+        self.compared_files.push(file.to_string());
+        if base.dimensions() != shiny.dimensions() {
+            self.shape_mismatch(
+                file,
+                format!(
+                    "Image dimensions differ: {}x{} (base) vs. {}x{} (shiny).",
+                    base.width(),
+                    base.height(),
+                    shiny.width(),
+                    shiny.height()
+                ),
+            );
+            return;
+        }
+        let mut mismatched_pixels = 0;
+        let mut first_mismatch = None;
+        for (x, y, base_px) in base.enumerate_pixels() {
+            let base_px = normalize(base_px.0);
+            let shiny_px = normalize(shiny.get_pixel(x, y).0);
+            if (base_px == TRANSPARENT) != (shiny_px == TRANSPARENT) {
+                mismatched_pixels += 1;
+                first_mismatch.get_or_insert((x, y));
+                continue;
+            }
+            if base_px == TRANSPARENT {
+                continue;
+            }
+            let (count, files) = self
+                .mapping
+                .entry(base_px)
+                .or_default()
+                .entry(shiny_px)
+                .or_default();
+            *count += 1;
+            if files.last().map(|f| f != file).unwrap_or(true) {
+                files.push(file.to_string());
+            }
+        }
+        if let Some((x, y)) = first_mismatch {
+            self.shape_mismatch(
+                file,
+                format!(
+                    "{} pixel(s) are transparent in only one of the two images (first at {}, {}).",
+                    mismatched_pixels, x, y
+                ),
+            );
+        }
+    }
+
+    pub fn shape_mismatch(&mut self, file: &str, reason: String) {
+        self.shape_mismatches.push(ShapeMismatch {
+            file: file.to_string(),
+            reason,
+        });
+    }
+
+    pub fn finish(self) -> PaletteCheckReport {
+        let mut mapping = Vec::with_capacity(self.mapping.len());
+        let mut collisions = Vec::new();
+        for (base, targets) in self.mapping {
+            if let Some((most_common, _)) = targets.iter().max_by_key(|(_, (count, _))| *count) {
+                mapping.push((base, *most_common));
+            }
+            if targets.len() > 1 {
+                let mut files: Vec<String> = Vec::new();
+                for (_, (_, target_files)) in &targets {
+                    for file in target_files {
+                        if !files.contains(file) {
+                            files.push(file.clone());
+                        }
+                    }
+                }
+                collisions.push(ColorCollision {
+                    base,
+                    shiny: targets.keys().copied().collect(),
+                    files,
+                });
+            }
+        }
+        PaletteCheckReport {
+            mapping,
+            collisions,
+            shape_mismatches: self.shape_mismatches,
+            compared_files: self.compared_files,
+        }
+    }
+}
+
+/// Counts the pixels of each distinct (non-transparent) colour of an image.
+pub fn count_colors(img: &RgbaImage, counts: &mut IndexMap<Color, u64>) {
+    for px in img.pixels() {
+        if px.0[3] == 0 {
+            continue;
+        }
+        *counts.entry(px.0).or_default() += 1;
+    }
+}
+
+/// Collects the palette of all given images, sorted by how often each colour is used.
+/// Files that don't exist are skipped. The images are decoded on the blocking thread pool.
+pub async fn collect_palette(files: Vec<PathBuf>) -> Result<Vec<(Color, u64)>, anyhow::Error> {
+    tokio::task::spawn_blocking(move || {
+        let mut counts = IndexMap::new();
+        for file in files {
+            if file.exists() {
+                count_colors(&image::open(&file)?.to_rgba8(), &mut counts);
+            }
+        }
+        counts.sort_by(|_, count1, _, count2| count2.cmp(count1));
+        Ok(counts.into_iter().collect())
+    })
+    .await?
+}
+
+/// Collects the palette of all portraits of a group.
+pub async fn portrait_palette(
+    group: &Group,
+    portrait_path: &Path,
+) -> Result<Vec<(Color, u64)>, anyhow::Error> {
+    collect_palette(
+        group
+            .portrait_files
+            .keys()
+            .map(|emotion| portrait_path.join(format!("{}.png", emotion)))
+            .collect(),
+    )
+    .await
+}
+
+/// Collects the palette of all sprite sheets of a group.
+pub async fn sprite_palette(
+    group: &Group,
+    sprite_path: &Path,
+) -> Result<Vec<(Color, u64)>, anyhow::Error> {
+    collect_palette(
+        group
+            .sprite_files
+            .keys()
+            .map(|action| sprite_path.join(format!("{}-Anim.png", action)))
+            .collect(),
+    )
+    .await
+}
+
+/// Compares all portraits of the shiny group with the portraits of the base group.
+pub async fn check_portrait_palette(
+    base_group: &Group,
+    base_path: &Path,
+    shiny_group: &Group,
+    shiny_path: &Path,
+) -> Result<PaletteCheckReport, anyhow::Error> {
+    check_files(
+        base_group.portrait_files.keys(),
+        shiny_group.portrait_files.keys(),
+        |emotion| base_path.join(format!("{}.png", emotion)),
+        |emotion| shiny_path.join(format!("{}.png", emotion)),
+    )
+    .await
+}
+
+/// Compares all sprite sheets of the shiny group with the sprite sheets of the base group.
+pub async fn check_sprite_palette(
+    base_group: &Group,
+    base_path: &Path,
+    shiny_group: &Group,
+    shiny_path: &Path,
+) -> Result<PaletteCheckReport, anyhow::Error> {
+    check_files(
+        base_group.sprite_files.keys(),
+        shiny_group.sprite_files.keys(),
+        |action| base_path.join(format!("{}-Anim.png", action)),
+        |action| shiny_path.join(format!("{}-Anim.png", action)),
+    )
+    .await
+}
+
+/// Compares the files on the blocking thread pool.
+async fn check_files<'a, I, FB, FS>(
+    base_names: I,
+    shiny_names: I,
+    base_file: FB,
+    shiny_file: FS,
+) -> Result<PaletteCheckReport, anyhow::Error>
+where
+    I: Iterator<Item = &'a String>,
+    FB: Fn(&str) -> PathBuf,
+    FS: Fn(&str) -> PathBuf,
+{
+    let base_names: Vec<&String> = base_names.collect();
+    // Name, base file (if the base form has the entry) and shiny file.
+    let files: Vec<(String, Option<PathBuf>, PathBuf)> = shiny_names
+        .map(|name| {
+            let base_path = base_names.contains(&name).then(|| base_file(name));
+            (name.clone(), base_path, shiny_file(name))
+        })
+        .collect();
+    let report = tokio::task::spawn_blocking(move || {
+        let mut comparison = PaletteComparison::default();
+        for (name, base_path, shiny_path) in files {
+            // Copies and not yet submitted entries have no files.
+            if !shiny_path.exists() {
+                continue;
+            }
+            let Some(base_path) = base_path.filter(|path| path.exists()) else {
+                comparison.shape_mismatch(&name, "The base form has no counterpart.".to_string());
+                continue;
+            };
+            match (image::open(&base_path), image::open(&shiny_path)) {
+                (Ok(base), Ok(shiny)) => {
+                    comparison.compare(&name, &base.to_rgba8(), &shiny.to_rgba8())
+                }
+                (Err(e), _) | (_, Err(e)) => {
+                    comparison.shape_mismatch(&name, format!("Failed to read image: {}", e))
+                }
+            }
+        }
+        comparison.finish()
+    })
+    .await?;
+    Ok(report)
+}
+
+#[inline]
+fn normalize(px: Color) -> Color {
+    if px[3] == 0 { TRANSPARENT } else { px }
+}
@@ -0,0 +1,105 @@
+//! Commit-pinned asset URLs: `/c/<commit>/<path in the SpriteCollab repository>`, prefixed
+//! with the dataset if several datasets are served.
+//!
+//! The files are read straight from the Git object database, so any commit that was ever fetched
+//! can be served. Since the content behind such a URL never changes, responses are marked as
+//! immutable.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use git2::{ErrorCode, Oid, Repository};
+use http_body_util::Full;
+use hyper::body::Bytes;
+use hyper::{Response, StatusCode};
+use log::warn;
+
+use crate::assets::{AssetBody, make_box_body, make_err_response};
+use crate::config::Config;
+use crate::sprite_collab::{SpriteCollab, repo_path};
+
+pub const PINNED_PREFIX: &str = "/c/";
+/// Only files in these directories of the repository are served.
+const SERVED_DIRS: [&str; 2] = ["portrait/", "sprite/"];
+
+/// Serves a file of a pinned URL. Returns `None` if the URL or file doesn't exist.
+pub async fn match_and_process_pinned_path(
+    path: &str,
+    sprite_collab: Arc<SpriteCollab>,
+) -> Option<Response<AssetBody>> {
+    // There are no commits to pin to in data directory mode.
+    if sprite_collab.dataset().data_dir().is_some() {
+        return None;
+    }
+    let (commit, file_path) = path.strip_prefix(PINNED_PREFIX)?.split_once('/')?;
+    // Only full commit hashes are immutable, abbreviated ones could become ambiguous.
+    if commit.len() != 40 {
+        return None;
+    }
+    let commit = Oid::from_str(commit).ok()?;
+    // Only `^` needs to be decoded, file names don't contain any other special characters.
+    let file_path = file_path.replace("%5E", "^").replace("%5e", "^");
+    if !SERVED_DIRS.iter().any(|dir| file_path.starts_with(dir))
+        || file_path
+            .split('/')
+            .any(|part| part.is_empty() || part == "..")
+    {
+        return None;
+    }
+
+    let request_path = path.to_string();
+    let repo_path = repo_path(sprite_collab.dataset());
+    let result =
+        tokio::task::spawn_blocking(move || read_blob(&repo_path, commit, Path::new(&file_path)))
+            .await;
+    match result {
+        Ok(Ok(Some((blob_id, content)))) => Some(make_blob_response(path, blob_id, content)),
+        Ok(Ok(None)) => None,
+        Ok(Err(e)) => Some(make_err_response(e, &request_path).map(make_box_body)),
+        Err(e) => Some(make_err_response(e, &request_path).map(make_box_body)),
+    }
+}
+
+/// Reads a file at the given commit. Returns `None` if the commit or file doesn't exist.
+fn read_blob(
+    repo_path: &PathBuf,
+    commit: Oid,
+    path: &Path,
+) -> Result<Option<(Oid, Vec<u8>)>, git2::Error> {
+    let repo = Repository::open(repo_path)?;
+    let commit = match repo.find_commit(commit) {
+        Ok(v) => v,
+        Err(e) if e.code() == ErrorCode::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let entry = match commit.tree()?.get_path(path) {
+        Ok(v) => v,
+        Err(e) if e.code() == ErrorCode::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    match entry.to_object(&repo)?.into_blob() {
+        Ok(blob) => Ok(Some((blob.id(), blob.content().to_vec()))),
+        // A directory.
+        Err(_) => Ok(None),
+    }
+}
+
+fn make_blob_response(path: &str, blob_id: Oid, content: Vec<u8>) -> Response<AssetBody> {
+    let content_type = match path.rsplit_once('.').map(|(_, ext)| ext) {
+        Some("png") => "image/png",
+        Some("xml") => "application/xml",
+        Some("txt") => "text/plain; charset=utf-8",
+        _ => "application/octet-stream",
+    };
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", content_type)
+        .header("Cache-Control", "public, max-age=31536000, immutable")
+        .header("ETag", format!("\"{}\"", blob_id))
+        .header("Access-Control-Allow-Origin", Config::cors_origin())
+        .body(make_box_body(Full::new(Bytes::from(content))))
+        .unwrap_or_else(|e| {
+            warn!("Failed building response for '{}': {}", path, e);
+            make_err_response(e, path).map(make_box_body)
+        })
+}
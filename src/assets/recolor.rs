@@ -0,0 +1,120 @@
+//! Applies edited recolor sheets to the portraits and sprites of a form, like SpriteBot does
+//! when artists submit shinies.
+
+use std::collections::HashMap;
+use std::io::{Cursor, Write};
+use std::path::Path;
+
+use image::{Rgba, RgbaImage};
+use thiserror::Error;
+use zip::ZipWriter;
+
+use crate::assets::img_util::to_png;
+use crate::assets::palette::{Color, PaletteComparison};
+use crate::datafiles::tracker::Group;
+
+#[derive(Error, Debug)]
+pub enum RecolorError {
+    /// The submitted sheet can not be applied. This is the fault of the client.
+    #[error("Invalid recolor sheet: {0}")]
+    InvalidSheet(String),
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+/// Derives the colour mapping between a recolor sheet as generated by this server and an edited
+/// version of it. Both the palette row and all other pixels are used.
+pub fn derive_recolor_mapping(
+    original_png: &[u8],
+    edited_png: &[u8],
+) -> Result<HashMap<Color, Color>, RecolorError> {
+    let original = image::load_from_memory(original_png)
+        .map_err(anyhow::Error::from)?
+        .to_rgba8();
+    let edited = image::load_from_memory(edited_png)
+        .map_err(|e| RecolorError::InvalidSheet(format!("Failed to read the image: {}", e)))?
+        .to_rgba8();
+
+    let mut comparison = PaletteComparison::default();
+    comparison.compare("recolor sheet", &original, &edited);
+    let report = comparison.finish();
+    if let Some(mismatch) = report.shape_mismatches.first() {
+        return Err(RecolorError::InvalidSheet(mismatch.reason.clone()));
+    }
+    if !report.collisions.is_empty() {
+        return Err(RecolorError::InvalidSheet(format!(
+            "{} colour(s) were recolored to more than one colour. The recolor must be a 1:1 colour mapping.",
+            report.collisions.len()
+        )));
+    }
+    Ok(report.mapping.into_iter().collect())
+}
+
+/// Recolors all existing portraits of the group and returns them as a ZIP archive.
+pub fn make_recolored_portraits_zip(
+    group: &Group,
+    portrait_base_path: &Path,
+    mapping: &HashMap<Color, Color>,
+) -> Result<Vec<u8>, anyhow::Error> {
+    make_recolored_zip(
+        group
+            .portrait_files
+            .keys()
+            .map(|emotion| format!("{}.png", emotion)),
+        portrait_base_path,
+        mapping,
+    )
+}
+
+/// Recolors all existing sprite sheets of the group and returns them as a ZIP archive.
+pub fn make_recolored_sprites_zip(
+    group: &Group,
+    sprite_base_path: &Path,
+    mapping: &HashMap<Color, Color>,
+) -> Result<Vec<u8>, anyhow::Error> {
+    make_recolored_zip(
+        group
+            .sprite_files
+            .keys()
+            .map(|action| format!("{}-Anim.png", action)),
+        sprite_base_path,
+        mapping,
+    )
+}
+
+fn make_recolored_zip<I: Iterator<Item = String>>(
+    file_names: I,
+    base_path: &Path,
+    mapping: &HashMap<Color, Color>,
+) -> Result<Vec<u8>, anyhow::Error> {
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = zip::write::SimpleFileOptions::default()
+        .compression_method(zip::CompressionMethod::Deflated);
+
+    for file_name in file_names {
+        let path = base_path.join(&file_name);
+        // Copies and entries without submissions have no files.
+        if !path.exists() {
+            continue;
+        }
+        let mut img = image::open(&path)?.to_rgba8();
+        apply_mapping(&mut img, mapping);
+        zip.start_file(file_name, options)?;
+        zip.write_all(&to_png(img)?)?;
+    }
+
+    Ok(zip.finish()?.into_inner())
+}
+
+/// Replaces all colours of the image according to the mapping. Colours that are not part of the
+/// mapping are kept as is.
+pub fn apply_mapping(img: &mut RgbaImage, mapping: &HashMap<Color, Color>) {
+    for px in img.pixels_mut() {
+        if px.0[3] == 0 {
+            continue;
+        }
+        if let Some(new) = mapping.get(&px.0) {
+            *px = Rgba(*new);
+        }
+    }
+}
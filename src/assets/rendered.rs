@@ -0,0 +1,180 @@
+//! Assets that are rendered from several files of a form: Sheets and ZIP archives. They are
+//! cached, keyed by the monster and form.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::SpriteCollab;
+use crate::assets::make_sprite_zip;
+use crate::assets::portrait_sheets::{
+    PortraitSheetEmotions, make_portrait_recolor_sheet, make_portrait_sheet,
+};
+use crate::assets::sprite_sheets::make_sprite_recolor_sheet;
+use crate::assets::url::AssetType;
+use crate::assets::util::force_non_shiny_group;
+use crate::cache::ScCache;
+use crate::data_root::DataRoot;
+use crate::datafiles::tracker::{FormMatch, Group, MonsterFormCollector, Tracker};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum RenderedAsset {
+    PortraitSheet,
+    PortraitRecolorSheet,
+    SpriteRecolorSheet,
+    SpriteZip,
+}
+
+impl RenderedAsset {
+    pub const ALL: [RenderedAsset; 4] = [
+        RenderedAsset::PortraitSheet,
+        RenderedAsset::PortraitRecolorSheet,
+        RenderedAsset::SpriteRecolorSheet,
+        RenderedAsset::SpriteZip,
+    ];
+
+    pub fn from_asset_type(asset_type: &AssetType) -> Option<Self> {
+        match asset_type {
+            AssetType::PortraitSheet => Some(RenderedAsset::PortraitSheet),
+            AssetType::PortraitRecolorSheet => Some(RenderedAsset::PortraitRecolorSheet),
+            AssetType::SpriteRecolorSheet => Some(RenderedAsset::SpriteRecolorSheet),
+            AssetType::SpriteZip => Some(RenderedAsset::SpriteZip),
+            _ => None,
+        }
+    }
+
+    /// Name of the asset, as used in cache keys and the configuration.
+    pub fn name(&self) -> &'static str {
+        match self {
+            RenderedAsset::PortraitSheet => "portrait_sheet",
+            RenderedAsset::PortraitRecolorSheet => "portrait_recolor_sheet",
+            RenderedAsset::SpriteRecolorSheet => "sprite_recolor_sheet",
+            RenderedAsset::SpriteZip => "sprite_zip",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|asset| asset.name() == name)
+    }
+
+    /// Finds the form the asset is rendered from. Recolor sheets are always made of the
+    /// non-shiny form.
+    pub fn find_form<'a>(
+        &self,
+        collector: &'a MonsterFormCollector<'a>,
+        form_path: Vec<i32>,
+    ) -> Option<(Vec<i32>, Vec<String>, &'a Group)> {
+        match self {
+            RenderedAsset::PortraitRecolorSheet | RenderedAsset::SpriteRecolorSheet => collector
+                .find_form(
+                    force_non_shiny_group(&form_path)
+                        .into_iter()
+                        .map(FormMatch::Exact),
+                ),
+            _ => collector.find_form(form_path.into_iter().map(FormMatch::Exact)),
+        }
+    }
+}
+
+/// The parts of the served data needed to render assets. Holds on to the snapshot, so it isn't
+/// removed while rendering.
+pub struct RenderData {
+    pub tracker: Arc<Tracker>,
+    root: Arc<DataRoot>,
+    emotions_incl_flipped: Vec<String>,
+    portrait_tile_x: i32,
+    portrait_size: i32,
+}
+
+impl RenderData {
+    pub fn new(sprite_collab: &SpriteCollab) -> Self {
+        let data = sprite_collab.data();
+        Self {
+            tracker: data.tracker.clone(),
+            root: data.root.clone(),
+            emotions_incl_flipped: data
+                .sprite_config
+                .emotions
+                .iter()
+                .cloned()
+                .chain(
+                    data.sprite_config
+                        .emotions
+                        .iter()
+                        .map(|e| format!("{}^", e)),
+                )
+                .collect(),
+            portrait_tile_x: data.sprite_config.portrait_tile_x,
+            portrait_size: data.sprite_config.portrait_size,
+        }
+    }
+
+    /// The commit the data is read from.
+    pub fn commit(&self) -> &str {
+        self.root.commit()
+    }
+
+    pub fn portrait_dir(&self, monster_idx: i32, form_path: &[i32]) -> PathBuf {
+        self.root.portrait_dir(monster_idx, form_path)
+    }
+
+    pub fn sprite_dir(&self, monster_idx: i32, form_path: &[i32]) -> PathBuf {
+        self.root.sprite_dir(monster_idx, form_path)
+    }
+
+    /// Renders the asset of the form found by [`RenderedAsset::find_form`], or takes it from
+    /// the cache.
+    pub async fn render(
+        &self,
+        sprite_collab: &SpriteCollab,
+        asset: RenderedAsset,
+        monster_idx: i32,
+        form_path: &[i32],
+        group: &Group,
+    ) -> Result<Result<Vec<u8>, anyhow::Error>, anyhow::Error> {
+        let cache_key = format!("{}|{}/{:?}", asset.name(), monster_idx, form_path);
+        let portrait_base_path = self.portrait_dir(monster_idx, form_path);
+        let sprite_base_path = self.sprite_dir(monster_idx, form_path);
+        let emotions =
+            || PortraitSheetEmotions::new(self.emotions_incl_flipped.clone(), self.portrait_tile_x);
+        match asset {
+            RenderedAsset::PortraitSheet => {
+                sprite_collab
+                    .cached_bytes_may_fail(cache_key, self.root.commit(), || {
+                        make_portrait_sheet(
+                            group,
+                            emotions(),
+                            &portrait_base_path,
+                            self.portrait_size,
+                        )
+                    })
+                    .await
+            }
+            RenderedAsset::PortraitRecolorSheet => {
+                sprite_collab
+                    .cached_bytes_may_fail(cache_key, self.root.commit(), || {
+                        make_portrait_recolor_sheet(
+                            group,
+                            emotions(),
+                            &portrait_base_path,
+                            self.portrait_size,
+                        )
+                    })
+                    .await
+            }
+            RenderedAsset::SpriteRecolorSheet => {
+                sprite_collab
+                    .cached_bytes_may_fail(cache_key, self.root.commit(), || {
+                        make_sprite_recolor_sheet(&sprite_base_path)
+                    })
+                    .await
+            }
+            RenderedAsset::SpriteZip => {
+                sprite_collab
+                    .cached_bytes_may_fail(cache_key, self.root.commit(), || {
+                        make_sprite_zip(&sprite_base_path)
+                    })
+                    .await
+            }
+        }
+    }
+}
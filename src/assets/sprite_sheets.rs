@@ -92,9 +92,6 @@ pub async fn make_sprite_recolor_sheet(
     sprite_base_path: &Path,
 ) -> Result<CacheBehaviour<Vec<u8>>, anyhow::Error> {
     let frames = get_sprite_frames(sprite_base_path).await?;
-    for (idx, (frame, _)) in frames.iter().enumerate() {
-        frame.save(format!("/workdir/{}.png", idx)).unwrap();
-    }
     let (frame_size_x, frame_size_y) = get_sprite_frame_size_from_frames(&frames);
 
     let max_size = (frames.len() as f64).sqrt().ceil() as u32;
@@ -413,6 +410,36 @@ fn get_offset_from_rgb(
     Ok(results)
 }
 
+/// Decodes the offsets of all frames of an offsets sheet. Fails if any frame contains more than
+/// one pixel for one of the offset markers.
+pub fn check_offsets_sheet(
+    offset_img: &DynamicImage,
+    frame_size_x: u32,
+    frame_size_y: u32,
+) -> Result<(), anyhow::Error> {
+    for yy in (0..offset_img.height()).step_by(frame_size_y as usize) {
+        for xx in (0..offset_img.width()).step_by(frame_size_x as usize) {
+            let tile_bounds = (
+                xx as i32,
+                yy as i32,
+                min(xx + frame_size_x, offset_img.width()) as i32,
+                min(yy + frame_size_y, offset_img.height()) as i32,
+            );
+            get_offset_from_rgb(offset_img, tile_bounds, true, true, true, true, false).map_err(
+                |e| {
+                    anyhow!(
+                        "Frame at column {}, row {}: {}",
+                        xx / frame_size_x,
+                        yy / frame_size_y,
+                        e
+                    )
+                },
+            )?;
+        }
+    }
+    Ok(())
+}
+
 fn get_sprite_frame_size_from_frames(frames: &[(DynamicImage, SpriteOffsets)]) -> (u32, u32) {
     let mut max_width = 0;
     let mut max_height = 0;
@@ -1,4 +1,4 @@
-use crate::Config;
+use crate::assets::pinned::PINNED_PREFIX;
 use crate::assets::util::{force_shiny_group, join_monster_and_form};
 use route_recognizer::Router;
 use std::collections::VecDeque;
@@ -19,13 +19,31 @@ pub enum AssetType<'a> {
     SpriteShadows(&'a str),
 }
 
+/// Where the URLs returned by [`get_url`] point to.
+#[derive(Clone, Debug)]
+pub struct UrlBase {
+    /// Public URL of this server, including the prefix of the dataset.
+    pub this_srv_url: String,
+    /// URL of the raw files of the repository (`SCSRV_GIT_ASSETS_URL`).
+    pub git_assets_url: String,
+    /// If set, files of the repository are linked with immutable URLs pinned to this commit,
+    /// served by this server.
+    pub pinned_commit: Option<String>,
+}
+
 pub fn get_url(
     asset_type: AssetType,
-    this_srv_url: &str,
+    base: &UrlBase,
     monster_id: i32,
     path_to_form: &[i32],
 ) -> String {
-    let assets_srv_url = Config::GitAssetsUrl.get();
+    let this_srv_url = &base.this_srv_url;
+    if let Some(file_path) = repo_file_path(&asset_type, monster_id, path_to_form) {
+        return match &base.pinned_commit {
+            Some(commit) => format!("{}{}{}/{}", this_srv_url, PINNED_PREFIX, commit, file_path),
+            None => format!("{}/{}", base.git_assets_url, file_path),
+        };
+    }
 
     match asset_type {
         AssetType::PortraitCreditsTxt => {
@@ -54,28 +72,6 @@ pub fn get_url(
                 this_srv_url, joined_f_dash
             )
         }
-        AssetType::Portrait(emotion) => {
-            let joined_f = join_monster_and_form(monster_id, path_to_form, '/');
-            format!(
-                "{}/portrait/{}/{}.png",
-                assets_srv_url,
-                joined_f,
-                up(emotion)
-            )
-        }
-        AssetType::PortraitFlipped(emotion) => {
-            let joined_f = join_monster_and_form(monster_id, path_to_form, '/');
-            format!(
-                "{}/portrait/{}/{}.png",
-                assets_srv_url,
-                joined_f,
-                up(emotion)
-            )
-        }
-        AssetType::SpriteAnimDataXml => {
-            let joined_f = join_monster_and_form(monster_id, path_to_form, '/');
-            format!("{}/sprite/{}/AnimData.xml", assets_srv_url, joined_f)
-        }
         AssetType::SpriteZip => {
             let joined_f = join_monster_and_form(monster_id, path_to_form, '/');
             format!("{}/assets/{}/sprites.zip", this_srv_url, joined_f)
@@ -88,34 +84,28 @@ pub fn get_url(
                 this_srv_url, joined_f_dash
             )
         }
-        AssetType::SpriteAnim(action) => {
-            let joined_f = join_monster_and_form(monster_id, path_to_form, '/');
-            format!(
-                "{}/sprite/{}/{}-Anim.png",
-                assets_srv_url,
-                joined_f,
-                up(action)
-            )
+        _ => unreachable!("files of the repository are handled above"),
+    }
+}
+
+/// Path of the asset inside the SpriteCollab repository. `None` for assets generated by this
+/// server.
+fn repo_file_path(asset_type: &AssetType, monster_id: i32, path_to_form: &[i32]) -> Option<String> {
+    let joined_f = join_monster_and_form(monster_id, path_to_form, '/');
+    Some(match asset_type {
+        AssetType::Portrait(emotion) | AssetType::PortraitFlipped(emotion) => {
+            format!("portrait/{}/{}.png", joined_f, up(emotion))
         }
+        AssetType::SpriteAnimDataXml => format!("sprite/{}/AnimData.xml", joined_f),
+        AssetType::SpriteAnim(action) => format!("sprite/{}/{}-Anim.png", joined_f, up(action)),
         AssetType::SpriteOffsets(action) => {
-            let joined_f = join_monster_and_form(monster_id, path_to_form, '/');
-            format!(
-                "{}/sprite/{}/{}-Offsets.png",
-                assets_srv_url,
-                joined_f,
-                up(action)
-            )
+            format!("sprite/{}/{}-Offsets.png", joined_f, up(action))
         }
         AssetType::SpriteShadows(action) => {
-            let joined_f = join_monster_and_form(monster_id, path_to_form, '/');
-            format!(
-                "{}/sprite/{}/{}-Shadow.png",
-                assets_srv_url,
-                joined_f,
-                up(action)
-            )
+            format!("sprite/{}/{}-Shadow.png", joined_f, up(action))
         }
-    }
+        _ => return None,
+    })
 }
 
 /// Matches a URL, if it matches returns a tuple of (monster id, form path, asset type)
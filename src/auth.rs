@@ -0,0 +1,86 @@
+//! Authentication of API users via bearer tokens.
+//!
+//! Users are configured in a JSON file (`SCSRV_AUTH_FILE`), containing a list of objects like:
+//!
+//! ```json
+//! [{"name": "Audino", "token": "...", "credit_id": "<@!123456>", "role": "reviewer"}]
+//! ```
+//!
+//! Roles are `submitter`, `reviewer` and `admin`. Each role includes the permissions of the
+//! roles before it.
+
+use std::fs::File;
+use std::io::BufReader;
+
+use hyper::http::HeaderValue;
+use log::{error, info};
+use serde::Deserialize;
+
+use crate::config::Config;
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Ord, PartialOrd)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Submitter,
+    Reviewer,
+    Admin,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct User {
+    /// Human-readable name, used in logs and as the author of actions.
+    pub name: String,
+    /// The credit ID (Discord ID or absentee ID) assets submitted by this user are credited to.
+    pub credit_id: String,
+    pub role: Role,
+    token: String,
+}
+
+impl User {
+    pub fn has_role(&self, role: Role) -> bool {
+        self.role >= role
+    }
+}
+
+#[derive(Default)]
+pub struct Users(Vec<User>);
+
+impl Users {
+    /// Loads the users from the configured auth file. If none is configured, no user can
+    /// authenticate.
+    pub fn load() -> Self {
+        match Config::AuthFile.get_or_none() {
+            Some(path) => {
+                let users: Vec<User> = File::open(&path)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|f| Ok(serde_json::from_reader(BufReader::new(f))?))
+                    .unwrap_or_else(|e| {
+                        error!("Failed reading auth file {}: {}", path, e);
+                        panic!("Invalid auth file.")
+                    });
+                info!("Loaded {} API users.", users.len());
+                Self(users)
+            }
+            None => Self::default(),
+        }
+    }
+
+    /// Returns the user for the value of an `Authorization: Bearer <token>` header.
+    pub fn authenticate(&self, header: Option<&HeaderValue>) -> Option<User> {
+        let token = header?.to_str().ok()?.strip_prefix("Bearer ")?.trim();
+        if token.is_empty() {
+            return None;
+        }
+        self.0
+            .iter()
+            .find(|user| constant_time_eq(user.token.as_bytes(), token.as_bytes()))
+            .cloned()
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
@@ -1,10 +1,22 @@
+use anyhow::anyhow;
 use async_trait::async_trait;
-use fred::types::Key;
+use fred::prelude::{ClientLike, Expiration, KeysInterface, LuaInterface, ReconnectPolicy};
+use fred::types::{Key, SetOptions, Value};
+use futures::StreamExt;
+use log::{debug, info, warn};
 use serde::Serialize;
 use serde::de::DeserializeOwned;
+use std::collections::HashMap;
 use std::convert::Infallible;
 use std::future::Future;
 use std::hint::unreachable_unchecked;
+use std::mem::take;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+use tokio::sync::OwnedMutexGuard;
+use tokio::time::sleep;
+
+use crate::config::Config;
 
 pub enum CacheBehaviour<T> {
     /// Cache this value.
@@ -18,8 +30,14 @@ pub enum CacheBehaviour<T> {
 pub trait ScCache: Send + Sync {
     type Error: Send + Sync;
 
-    /// Do a cache lookup, on miss, calculate the value.
-    async fn cached<S, Fn, Ft, T>(&self, cache_key: S, func: Fn) -> Result<T, Self::Error>
+    /// Do a cache lookup, on miss, calculate the value. The value must only depend on the data
+    /// of `commit`.
+    async fn cached<S, Fn, Ft, T>(
+        &self,
+        cache_key: S,
+        commit: &str,
+        func: Fn,
+    ) -> Result<T, Self::Error>
     where
         S: AsRef<str> + Into<Key> + Send + Sync,
         Fn: (FnOnce() -> Ft) + Send,
@@ -27,7 +45,7 @@ pub trait ScCache: Send + Sync {
         T: DeserializeOwned + Serialize + Send + Sync,
     {
         match self
-            .cached_may_fail(cache_key, || async {
+            .cached_may_fail(cache_key, commit, || async {
                 let r: Result<CacheBehaviour<T>, Infallible> = Ok(func().await);
                 r
             })
@@ -42,10 +60,12 @@ pub trait ScCache: Send + Sync {
     }
 
     /// Do a cache lookup, on miss, calculate the value. Calculating the value may fail,
-    /// in that case chain the error (= it has the same type as Self::Error).
+    /// in that case chain the error (= it has the same type as Self::Error). The value must only
+    /// depend on the data of `commit`.
     async fn cached_may_fail_chain<S, Fn, Ft, T>(
         &self,
         cache_key: S,
+        commit: &str,
         func: Fn,
     ) -> Result<T, Self::Error>
     where
@@ -54,17 +74,19 @@ pub trait ScCache: Send + Sync {
         Ft: Future<Output = Result<CacheBehaviour<T>, Self::Error>> + Send,
         T: DeserializeOwned + Serialize + Send + Sync,
     {
-        match self.cached_may_fail(cache_key, func).await {
+        match self.cached_may_fail(cache_key, commit, func).await {
             Ok(Ok(v)) => Ok(v),
             Ok(Err(e)) => Err(e),
             Err(e) => Err(e),
         }
     }
 
-    /// Do a cache lookup, on miss, calculate the value. Calculating the value may fail.
+    /// Do a cache lookup, on miss, calculate the value. Calculating the value may fail. The value
+    /// must only depend on the data of `commit`, it is cached for that commit.
     async fn cached_may_fail<S, Fn, Ft, T, E>(
         &self,
         cache_key: S,
+        commit: &str,
         func: Fn,
     ) -> Result<Result<T, E>, Self::Error>
     where
@@ -73,6 +95,21 @@ pub trait ScCache: Send + Sync {
         Ft: Future<Output = Result<CacheBehaviour<T>, E>> + Send,
         T: DeserializeOwned + Serialize + Send + Sync,
         E: Send;
+
+    /// Do a cache lookup, on miss, calculate the value. Calculating the value may fail. The bytes
+    /// are stored as they are instead of as JSON, use this for images and archives. The value
+    /// must only depend on the data of `commit`, it is also cached on disk for that commit.
+    async fn cached_bytes_may_fail<S, Fn, Ft, E>(
+        &self,
+        cache_key: S,
+        commit: &str,
+        func: Fn,
+    ) -> Result<Result<Vec<u8>, E>, Self::Error>
+    where
+        S: AsRef<str> + Into<Key> + Send + Sync,
+        Fn: (FnOnce() -> Ft) + Send,
+        Ft: Future<Output = Result<CacheBehaviour<Vec<u8>>, E>> + Send,
+        E: Send;
 }
 
 #[async_trait]
@@ -82,6 +119,7 @@ impl<B: ScCache> ScCache for &B {
     async fn cached_may_fail<S, Fn, Ft, T, E>(
         &self,
         cache_key: S,
+        commit: &str,
         func: Fn,
     ) -> Result<Result<T, E>, Self::Error>
     where
@@ -91,6 +129,398 @@ impl<B: ScCache> ScCache for &B {
         T: DeserializeOwned + Serialize + Send + Sync,
         E: Send,
     {
-        <B as ScCache>::cached_may_fail(self, cache_key, func).await
+        <B as ScCache>::cached_may_fail(self, cache_key, commit, func).await
+    }
+
+    async fn cached_bytes_may_fail<S, Fn, Ft, E>(
+        &self,
+        cache_key: S,
+        commit: &str,
+        func: Fn,
+    ) -> Result<Result<Vec<u8>, E>, Self::Error>
+    where
+        S: AsRef<str> + Into<Key> + Send + Sync,
+        Fn: (FnOnce() -> Ft) + Send,
+        Ft: Future<Output = Result<CacheBehaviour<Vec<u8>>, E>> + Send,
+        E: Send,
+    {
+        <B as ScCache>::cached_bytes_may_fail(self, cache_key, commit, func).await
+    }
+}
+
+/// Version of the format values are stored in. Part of every key, so servers using another
+/// format don't read each other's values. Values of older versions are removed on startup.
+const CACHE_VERSION: u32 = 2;
+/// The first byte of a stored value tells how the rest is encoded.
+const FORMAT_JSON: u8 = 0;
+const FORMAT_JSON_ZSTD: u8 = 1;
+const FORMAT_BYTES: u8 = 2;
+/// Category whose TTL and size limit apply to all categories without their own.
+const DEFAULT_CATEGORY: &str = "*";
+
+/// How long a Redis lock is held at most, in case the server holding it goes away.
+const LOCK_TTL: Duration = Duration::from_secs(60);
+/// How often a locked key is checked while waiting for another server.
+const LOCK_POLL: Duration = Duration::from_millis(100);
+/// Releases a Redis lock only if it is still held by the same owner.
+const UNLOCK_SCRIPT: &str = "if redis.call('get', KEYS[1]) == ARGV[1] then return redis.call('del', KEYS[1]) else return 0 end";
+
+/// Where cached values are stored.
+enum Store {
+    Redis(fred::clients::Client),
+    /// Used if no Redis server is configured. Meant for development, the values are only evicted
+    /// when they expire.
+    Memory(Mutex<HashMap<String, MemoryEntry>>),
+}
+
+struct MemoryEntry {
+    value: Vec<u8>,
+    expires: Option<Instant>,
+}
+
+/// How values are stored.
+pub struct CacheOptions {
+    /// Prefix of all keys, so other applications can use the same Redis server.
+    pub namespace: String,
+    /// Coordinate computing values with other servers through Redis.
+    pub distributed_lock: bool,
+    /// zstd level JSON values are compressed with. 0 disables compression.
+    pub compression: i32,
+    /// How long values are kept, by category.
+    pub ttls: HashMap<String, Duration>,
+    /// Maximum size of stored values in bytes, by category. Larger values aren't cached.
+    pub max_sizes: HashMap<String, usize>,
+}
+
+impl CacheOptions {
+    pub fn from_config() -> Self {
+        Self {
+            namespace: Config::RedisNamespace.get(),
+            distributed_lock: Config::cache_lock(),
+            compression: Config::cache_compression(),
+            ttls: Config::cache_ttls(),
+            max_sizes: Config::cache_max_sizes(),
+        }
+    }
+
+    fn ttl(&self, category: &str) -> Option<Duration> {
+        self.ttls
+            .get(category)
+            .or_else(|| self.ttls.get(DEFAULT_CATEGORY))
+            .copied()
+    }
+
+    fn max_size(&self, category: &str) -> Option<usize> {
+        self.max_sizes
+            .get(category)
+            .or_else(|| self.max_sizes.get(DEFAULT_CATEGORY))
+            .copied()
+    }
+}
+
+/// Escapes the characters with special meaning in `SCAN` patterns.
+fn escape_pattern(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Category of a cache key, for TTLs and size limits: The part before `|`, eg. `sprite_zip` for
+/// `sprite_zip|1/[]`.
+pub fn key_category(cache_key: &str) -> &str {
+    cache_key
+        .split('|')
+        .next()
+        .unwrap_or_default()
+        .trim_start_matches('/')
+}
+
+/// The cache shared by all datasets.
+///
+/// Concurrent misses of the same key are coalesced: Only one caller computes the value, the
+/// others wait for it with [`CacheBackend::lock`] and then read it from the cache. With
+/// `SCSRV_CACHE_LOCK`, this also applies across servers sharing the Redis server.
+pub struct CacheBackend {
+    store: Store,
+    /// Keys whose value is being computed.
+    in_flight: Mutex<HashMap<String, Weak<tokio::sync::Mutex<()>>>>,
+    options: CacheOptions,
+}
+
+/// Held while computing the value of a key. Release it with [`CacheBackend::unlock`].
+pub struct CacheLock {
+    _local: OwnedMutexGuard<()>,
+    /// Key and owner token of the Redis lock, if it was acquired.
+    redis: Option<(String, String)>,
+}
+
+impl CacheBackend {
+    /// Connects to Redis, if configured.
+    pub async fn connect(redis_config: Option<(String, u16)>, options: CacheOptions) -> Self {
+        let Some((redis_url, redis_port)) = redis_config else {
+            info!("No Redis server configured, caching in memory.");
+            return Self::new(Store::Memory(Mutex::new(HashMap::new())), options);
+        };
+        let config =
+            fred::prelude::Config::from_url(&format!("redis://{}:{}", redis_url, redis_port))
+                .expect("Invalid Redis config.");
+        let policy = ReconnectPolicy::new_linear(10, 10000, 1000);
+        let client = fred::clients::Client::new(config, None, None, Some(policy));
+        client.connect();
+        client
+            .wait_for_connect()
+            .await
+            .expect("Failed to connect to Redis.");
+        info!("Connected to Redis.");
+        Self::new(Store::Redis(client), options)
+    }
+
+    fn new(store: Store, options: CacheOptions) -> Self {
+        Self {
+            store,
+            in_flight: Mutex::new(HashMap::new()),
+            options,
+        }
+    }
+
+    /// The key in the store: Prefixed with the namespace and format version.
+    fn versioned(&self, key: &str) -> String {
+        format!("{}:v{}:{}", self.options.namespace, CACHE_VERSION, key)
+    }
+
+    pub async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, fred::prelude::Error> {
+        let key = self.versioned(key);
+        match &self.store {
+            Store::Redis(client) => client.get(key).await,
+            Store::Memory(values) => {
+                let mut values = values.lock().unwrap();
+                match values.get(&key) {
+                    Some(entry) if entry.expires.is_some_and(|e| e <= Instant::now()) => {
+                        values.remove(&key);
+                        Ok(None)
+                    }
+                    Some(entry) => Ok(Some(entry.value.clone())),
+                    None => Ok(None),
+                }
+            }
+        }
+    }
+
+    /// Stores the value with the TTL of its category, unless it's larger than the size limit of
+    /// the category.
+    pub async fn set(
+        &self,
+        key: &str,
+        category: &str,
+        value: Vec<u8>,
+    ) -> Result<(), fred::prelude::Error> {
+        if let Some(max_size) = self.options.max_size(category)
+            && value.len() > max_size
+        {
+            debug!(
+                "Not caching '{}', it has {} bytes, the limit is {}.",
+                key,
+                value.len(),
+                max_size
+            );
+            return Ok(());
+        }
+        let key = self.versioned(key);
+        let ttl = self.options.ttl(category);
+        match &self.store {
+            Store::Redis(client) => {
+                client
+                    .set(
+                        key,
+                        Value::Bytes(value.into()),
+                        ttl.map(|ttl| Expiration::PX(ttl.as_millis() as i64)),
+                        None,
+                        false,
+                    )
+                    .await
+            }
+            Store::Memory(values) => {
+                let expires = ttl.map(|ttl| Instant::now() + ttl);
+                values
+                    .lock()
+                    .unwrap()
+                    .insert(key, MemoryEntry { value, expires });
+                Ok(())
+            }
+        }
+    }
+
+    /// Encodes a value as JSON, compressed if enabled.
+    pub fn encode_json<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, anyhow::Error> {
+        let json = serde_json::to_vec(value)?;
+        if self.options.compression == 0 {
+            let mut encoded = Vec::with_capacity(json.len() + 1);
+            encoded.push(FORMAT_JSON);
+            encoded.extend_from_slice(&json);
+            return Ok(encoded);
+        }
+        let mut encoded = vec![FORMAT_JSON_ZSTD];
+        zstd::stream::copy_encode(json.as_slice(), &mut encoded, self.options.compression)?;
+        Ok(encoded)
+    }
+
+    pub fn decode_json<T: DeserializeOwned>(value: &[u8]) -> Result<T, anyhow::Error> {
+        match value.split_first() {
+            Some((&FORMAT_JSON, json)) => Ok(serde_json::from_slice(json)?),
+            Some((&FORMAT_JSON_ZSTD, compressed)) => Ok(serde_json::from_slice(
+                &zstd::stream::decode_all(compressed)?,
+            )?),
+            _ => Err(anyhow!("Cached value is not JSON.")),
+        }
+    }
+
+    /// Encodes bytes as they are. They are not compressed, images and archives already are.
+    pub fn encode_bytes(&self, value: &[u8]) -> Result<Vec<u8>, anyhow::Error> {
+        let mut encoded = Vec::with_capacity(value.len() + 1);
+        encoded.push(FORMAT_BYTES);
+        encoded.extend_from_slice(value);
+        Ok(encoded)
+    }
+
+    pub fn decode_bytes(value: &[u8]) -> Result<Vec<u8>, anyhow::Error> {
+        match value.split_first() {
+            Some((&FORMAT_BYTES, bytes)) => Ok(bytes.to_vec()),
+            _ => Err(anyhow!("Cached value is not binary.")),
+        }
+    }
+
+    /// Removes all values whose key starts with `prefix`, except those starting with `keep`.
+    /// Returns the number of removed values.
+    pub async fn delete_prefix(
+        &self,
+        prefix: &str,
+        keep: Option<&str>,
+    ) -> Result<usize, fred::prelude::Error> {
+        let prefix = self.versioned(prefix);
+        let keep = keep.map(|keep| self.versioned(keep));
+        self.delete_store_prefix(&prefix, keep.as_deref()).await
+    }
+
+    /// Removes the values stored by servers using an older [`CACHE_VERSION`] in the namespace.
+    /// Returns the number of removed values.
+    pub async fn delete_old_versions(&self) -> Result<usize, fred::prelude::Error> {
+        let mut count = 0;
+        for version in 1..CACHE_VERSION {
+            let prefix = format!("{}:v{}:", self.options.namespace, version);
+            count += self.delete_store_prefix(&prefix, None).await?;
+        }
+        Ok(count)
+    }
+
+    /// Like [`CacheBackend::delete_prefix`], but with the keys as stored.
+    async fn delete_store_prefix(
+        &self,
+        prefix: &str,
+        keep: Option<&str>,
+    ) -> Result<usize, fred::prelude::Error> {
+        let deleted =
+            |key: &str| key.starts_with(prefix) && !keep.is_some_and(|keep| key.starts_with(keep));
+        match &self.store {
+            Store::Redis(client) => {
+                let mut keys =
+                    client.scan_buffered(format!("{}*", escape_pattern(prefix)), Some(1000), None);
+                let mut batch = Vec::new();
+                let mut count = 0;
+                while let Some(key) = keys.next().await {
+                    let key = key?;
+                    if key.as_str().is_some_and(deleted) {
+                        batch.push(key);
+                    }
+                    if batch.len() >= 500 {
+                        count += batch.len();
+                        let _: i64 = client.unlink(take(&mut batch)).await?;
+                    }
+                }
+                if !batch.is_empty() {
+                    count += batch.len();
+                    let _: i64 = client.unlink(batch).await?;
+                }
+                Ok(count)
+            }
+            Store::Memory(values) => {
+                let mut values = values.lock().unwrap();
+                let before = values.len();
+                values.retain(|key, _| !deleted(key));
+                Ok(before - values.len())
+            }
+        }
+    }
+
+    /// Waits until no other caller is computing the value of the key, then locks it. The value
+    /// may have been cached in the meantime, so look it up again before computing it.
+    ///
+    /// If another server holds the Redis lock for longer than [`LOCK_TTL`], the key is computed
+    /// without it.
+    pub async fn lock(&self, key: &str) -> CacheLock {
+        let local = {
+            let mut in_flight = self.in_flight.lock().unwrap();
+            in_flight.retain(|_, lock| lock.strong_count() > 0);
+            match in_flight.get(key).and_then(Weak::upgrade) {
+                Some(lock) => lock,
+                None => {
+                    let lock = Arc::new(tokio::sync::Mutex::new(()));
+                    in_flight.insert(key.to_string(), Arc::downgrade(&lock));
+                    lock
+                }
+            }
+        };
+        let local = local.lock_owned().await;
+        let redis = match &self.store {
+            Store::Redis(client) if self.options.distributed_lock => {
+                let lock_key = self.versioned(&format!("lock:{}", key));
+                let token = format!("{:016x}", fastrand::u64(..));
+                let deadline = Instant::now() + LOCK_TTL;
+                loop {
+                    let acquired: Result<Option<String>, _> = client
+                        .set(
+                            &lock_key,
+                            token.as_str(),
+                            Some(Expiration::PX(LOCK_TTL.as_millis() as i64)),
+                            Some(SetOptions::NX),
+                            false,
+                        )
+                        .await;
+                    match acquired {
+                        Ok(Some(_)) => break Some((lock_key, token)),
+                        Ok(None) if Instant::now() < deadline => sleep(LOCK_POLL).await,
+                        Ok(None) => {
+                            warn!("Timed out waiting for the Redis lock of '{}'.", key);
+                            break None;
+                        }
+                        Err(err) => {
+                            warn!("Failed taking the Redis lock of '{}': {:?}", key, err);
+                            break None;
+                        }
+                    }
+                }
+            }
+            _ => None,
+        };
+        CacheLock {
+            _local: local,
+            redis,
+        }
+    }
+
+    /// Releases the lock taken with [`CacheBackend::lock`].
+    pub async fn unlock(&self, lock: CacheLock) {
+        if let (Store::Redis(client), Some((lock_key, token))) = (&self.store, &lock.redis) {
+            let r: Result<i64, _> = client
+                .eval(UNLOCK_SCRIPT, lock_key.as_str(), token.as_str())
+                .await;
+            if let Err(err) = r {
+                warn!("Failed releasing the Redis lock '{}': {:?}", lock_key, err);
+            }
+        }
     }
 }
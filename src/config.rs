@@ -1,62 +1,1021 @@
+//! Configuration of the server.
+//!
+//! Settings are read from environment variables (`SCSRV_<SETTING>`, also from `.env`) and from an
+//! optional TOML file (`SCSRV_CONFIG_FILE`, default `spritecollab-srv.toml`), with the same names
+//! in lowercase. Environment variables take precedence over the file. Settings of a dataset can be
+//! overridden with `SCSRV_<NAME>_<SETTING>` or in the `[datasets.<name>]` table of the file.
+//!
+//! `spritecollab-srv config check` prints the effective configuration and all validation errors.
+
 use dotenv::dotenv;
+use hyper::header::HeaderValue;
+use log::error;
+use std::collections::HashMap;
 use std::env::var;
+use std::fmt::{Display, Formatter};
+use std::fs::read_to_string;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::OnceLock;
+use std::time::Duration;
+use strum::{EnumIter, IntoEnumIterator};
+use toml::{Table, Value};
+
+use crate::assets::rendered::RenderedAsset;
+use crate::warmup::WarmupPriority;
+
+const DEFAULT_CONFIG_FILE: &str = "spritecollab-srv.toml";
+/// Names that can't be used for datasets, because they are routes of this server.
+const RESERVED_DATASET_NAMES: [&str; 6] = ["assets", "c", "graphql", "hooks", "ready", "validate"];
+const DATASETS_DIR: &str = "datasets";
+/// The config file, if one is used, or the error reading it.
+static FILE: OnceLock<Result<Option<ConfigFile>, String>> = OnceLock::new();
 
 #[allow(dead_code)] // discord feature
+#[derive(Clone, Copy, Debug, Eq, PartialEq, EnumIter)]
 pub enum Config {
     Address,
+    Bind,
+    Port,
+    CorsOrigin,
+    MaxUploadSize,
+    TlsCert,
+    TlsKey,
+    UnixSocket,
+    ShutdownDelay,
+    DrainTimeout,
     GitRepo,
     GitAssetsUrl,
     Workdir,
+    CacheBackend,
+    CacheLock,
+    CacheCompression,
+    CacheTtl,
+    CacheMaxSize,
     RedisHost,
     RedisPort,
+    RedisNamespace,
+    AuthFile,
+    GitAuthorName,
+    GitAuthorEmail,
+    SubmissionsPush,
+    WebhookUrls,
+    WebhookSecret,
+    GitHookSecret,
+    RefreshInterval,
+    StatsInterval,
+    WarmupWorkers,
+    WarmupPriority,
+    WarmupAssets,
+    DiskCacheSize,
+    MaxRollback,
+    GitBranch,
+    GitDepth,
+    GitUsername,
+    GitToken,
+    GitSshKey,
+    GitSshKeyPassphrase,
+    TrustPolicy,
+    TrustedSshKeys,
+    TrustedGpgKeyring,
+    DataDir,
+    Datasets,
+}
+
+/// Where the value of a setting comes from.
+#[derive(Clone, Debug)]
+pub enum Source {
+    Env(String),
+    File(String),
+    Default,
+}
+
+impl Display for Source {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Source::Env(name) => write!(f, "env {}", name),
+            Source::File(key) => write!(f, "file {}", key),
+            Source::Default => write!(f, "default"),
+        }
+    }
 }
 
 impl Config {
+    /// Loads `.env` and the config file.
     pub fn init() {
         dotenv().ok();
+        FILE.get_or_init(ConfigFile::load);
     }
 
-    /// Makes sure all required config values are set and panics otherwise.
-    pub fn check() {
-        Self::Address.get();
-        Self::GitRepo.get();
-        Self::GitAssetsUrl.get();
-        Self::Workdir.get();
-        Self::RedisHost.get();
-        Self::RedisPort.get();
+    /// Logs all errors in the configuration. Returns whether the configuration is valid.
+    pub fn check() -> bool {
+        let errors = Self::validate();
+        for e in &errors {
+            error!("Invalid configuration: {}", e);
+        }
+        errors.is_empty()
     }
 
-    pub fn get(&self) -> String {
-        match self {
-            Config::Address => var("SCSRV_ADDRESS").expect("SCSRV_ADDRESS not set"),
-            Config::GitRepo => var("SCSRV_GIT_REPO").expect("SCSRV_GIT_REPO not set"),
-            Config::GitAssetsUrl => {
-                var("SCSRV_GIT_ASSETS_URL").expect("SCSRV_GIT_ASSETS_URL not set")
+    /// Returns all errors in the configuration.
+    pub fn validate() -> Vec<String> {
+        let mut errors = Vec::new();
+        match FILE.get() {
+            Some(Err(e)) => errors.push(e.clone()),
+            Some(Ok(Some(file))) => errors.extend(file.unknown_keys()),
+            _ => {}
+        }
+        for config in Self::iter().filter(Config::is_global) {
+            config.validate_value(config.lookup(), &mut errors);
+        }
+        for config in [Config::Address, Config::Workdir] {
+            if config.lookup().is_none() {
+                errors.push(format!("{} is not set", config.env_name()));
             }
-            Config::Workdir => var("SCSRV_WORKDIR").expect("SCSRV_WORKDIR is not set"),
-            Config::RedisHost => var("SCSRV_REDIS_HOST").expect("SCSRV_REDIS_HOST is not set"),
-            Config::RedisPort => var("SCSRV_REDIS_PORT").expect("SCSRV_REDIS_PORT is not set"),
         }
+        if Self::TlsCert.lookup().is_some() != Self::TlsKey.lookup().is_some() {
+            errors.push(format!(
+                "{} and {} must be set together",
+                Self::TlsCert.env_name(),
+                Self::TlsKey.env_name()
+            ));
+        }
+        if Self::CacheBackend.get_or_none().as_deref() == Some("redis")
+            && Self::RedisHost.get_or_none().is_none()
+        {
+            errors.push(format!(
+                "{} is redis, but {} is not set",
+                Self::CacheBackend.env_name(),
+                Self::RedisHost.env_name()
+            ));
+        }
+        match Dataset::try_all() {
+            Ok(datasets) => {
+                for dataset in datasets {
+                    dataset.validate(&mut errors);
+                }
+            }
+            Err(e) => errors.push(e),
+        }
+        errors
     }
 
-    pub fn get_or_none(&self) -> Option<String> {
+    /// Name of the setting, without the `SCSRV_` prefix.
+    fn key(&self) -> &'static str {
         match self {
-            Config::Address => var("SCSRV_ADDRESS").ok(),
-            Config::GitRepo => var("SCSRV_GIT_REPO").ok(),
-            Config::GitAssetsUrl => var("SCSRV_GIT_ASSETS_URL").ok(),
-            Config::Workdir => var("SCSRV_WORKDIR").ok(),
-            Config::RedisHost => var("SCSRV_REDIS_HOST").ok(),
-            Config::RedisPort => var("SCSRV_REDIS_PORT").ok(),
+            Config::Address => "ADDRESS",
+            Config::Bind => "BIND",
+            Config::Port => "PORT",
+            Config::CorsOrigin => "CORS_ORIGIN",
+            Config::MaxUploadSize => "MAX_UPLOAD_SIZE",
+            Config::TlsCert => "TLS_CERT",
+            Config::TlsKey => "TLS_KEY",
+            Config::UnixSocket => "UNIX_SOCKET",
+            Config::ShutdownDelay => "SHUTDOWN_DELAY",
+            Config::DrainTimeout => "DRAIN_TIMEOUT",
+            Config::GitRepo => "GIT_REPO",
+            Config::GitAssetsUrl => "GIT_ASSETS_URL",
+            Config::Workdir => "WORKDIR",
+            Config::CacheBackend => "CACHE_BACKEND",
+            Config::CacheLock => "CACHE_LOCK",
+            Config::CacheCompression => "CACHE_COMPRESSION",
+            Config::CacheTtl => "CACHE_TTL",
+            Config::CacheMaxSize => "CACHE_MAX_SIZE",
+            Config::RedisHost => "REDIS_HOST",
+            Config::RedisPort => "REDIS_PORT",
+            Config::RedisNamespace => "REDIS_NAMESPACE",
+            Config::AuthFile => "AUTH_FILE",
+            Config::GitAuthorName => "GIT_AUTHOR_NAME",
+            Config::GitAuthorEmail => "GIT_AUTHOR_EMAIL",
+            Config::SubmissionsPush => "SUBMISSIONS_PUSH",
+            Config::WebhookUrls => "WEBHOOK_URLS",
+            Config::WebhookSecret => "WEBHOOK_SECRET",
+            Config::GitHookSecret => "GIT_HOOK_SECRET",
+            Config::RefreshInterval => "REFRESH_INTERVAL",
+            Config::StatsInterval => "STATS_INTERVAL",
+            Config::WarmupWorkers => "WARMUP_WORKERS",
+            Config::WarmupPriority => "WARMUP_PRIORITY",
+            Config::WarmupAssets => "WARMUP_ASSETS",
+            Config::DiskCacheSize => "DISK_CACHE_SIZE",
+            Config::MaxRollback => "MAX_ROLLBACK",
+            Config::GitBranch => "GIT_BRANCH",
+            Config::GitDepth => "GIT_DEPTH",
+            Config::GitUsername => "GIT_USERNAME",
+            Config::GitToken => "GIT_TOKEN",
+            Config::GitSshKey => "GIT_SSH_KEY",
+            Config::GitSshKeyPassphrase => "GIT_SSH_KEY_PASSPHRASE",
+            Config::TrustPolicy => "TRUST_POLICY",
+            Config::TrustedSshKeys => "TRUSTED_SSH_KEYS",
+            Config::TrustedGpgKeyring => "TRUSTED_GPG_KEYRING",
+            Config::DataDir => "DATA_DIR",
+            Config::Datasets => "DATASETS",
+        }
+    }
+
+    fn env_name(&self) -> String {
+        format!("SCSRV_{}", self.key())
+    }
+
+    fn file_key(&self) -> String {
+        self.key().to_ascii_lowercase()
+    }
+
+    /// Whether the setting applies to the whole server. All other settings can be set per
+    /// dataset.
+    fn is_global(&self) -> bool {
+        matches!(
+            self,
+            Config::Address
+                | Config::Bind
+                | Config::Port
+                | Config::CorsOrigin
+                | Config::MaxUploadSize
+                | Config::TlsCert
+                | Config::TlsKey
+                | Config::UnixSocket
+                | Config::ShutdownDelay
+                | Config::DrainTimeout
+                | Config::Workdir
+                | Config::CacheBackend
+                | Config::CacheLock
+                | Config::CacheCompression
+                | Config::CacheTtl
+                | Config::CacheMaxSize
+                | Config::RedisHost
+                | Config::RedisPort
+                | Config::RedisNamespace
+                | Config::AuthFile
+                | Config::Datasets
+        )
+    }
+
+    /// Whether the setting can be set for a single dataset. The workdir is global, but datasets
+    /// can have their own.
+    fn is_per_dataset(&self) -> bool {
+        !self.is_global() || *self == Config::Workdir
+    }
+
+    /// Secrets are not printed by `config check`.
+    fn is_secret(&self) -> bool {
+        matches!(
+            self,
+            Config::GitToken
+                | Config::GitSshKeyPassphrase
+                | Config::WebhookSecret
+                | Config::GitHookSecret
+        )
+    }
+
+    fn default(&self) -> Option<String> {
+        Some(
+            match self {
+                Config::Bind => "0.0.0.0",
+                Config::Port => "3000",
+                Config::CorsOrigin => "*",
+                Config::MaxUploadSize => "20971520",
+                Config::ShutdownDelay => "0",
+                Config::DrainTimeout => "30",
+                Config::CacheBackend if Self::RedisHost.get_or_none().is_some() => "redis",
+                Config::CacheBackend => "memory",
+                Config::CacheLock => "false",
+                Config::CacheCompression => "0",
+                Config::RedisPort => "6379",
+                Config::RedisNamespace => "spritecollab-srv",
+                Config::GitAuthorName => "spritecollab-srv",
+                Config::GitAuthorEmail => "spritecollab-srv@localhost",
+                Config::SubmissionsPush => "false",
+                Config::RefreshInterval => "900",
+                Config::StatsInterval => "3600",
+                Config::WarmupWorkers => "2",
+                Config::WarmupPriority => "canon,base",
+                Config::DiskCacheSize => "1073741824",
+                Config::WarmupAssets => {
+                    "portrait_sheet,portrait_recolor_sheet,sprite_recolor_sheet,sprite_zip"
+                }
+                Config::MaxRollback => "50",
+                Config::GitBranch => "master",
+                Config::GitUsername => "x-access-token",
+                Config::TrustPolicy => "off",
+                _ => return None,
+            }
+            .to_string(),
+        )
+    }
+
+    /// Converts a value to the TOML type of the setting.
+    fn typed(&self, value: String) -> Value {
+        let typed = match self {
+            Config::Port
+            | Config::RedisPort
+            | Config::MaxUploadSize
+            | Config::MaxRollback
+            | Config::RefreshInterval
+            | Config::StatsInterval
+            | Config::WarmupWorkers
+            | Config::DiskCacheSize
+            | Config::CacheCompression
+            | Config::ShutdownDelay
+            | Config::DrainTimeout
+            | Config::GitDepth => value.parse().ok().map(Value::Integer),
+            Config::SubmissionsPush | Config::CacheLock => parse_bool(&value).map(Value::Boolean),
+            Config::WebhookUrls
+            | Config::Datasets
+            | Config::WarmupPriority
+            | Config::WarmupAssets
+            | Config::CacheTtl
+            | Config::CacheMaxSize => Some(Value::Array(
+                value
+                    .split(',')
+                    .map(|v| Value::String(v.to_string()))
+                    .collect(),
+            )),
+            _ => None,
+        };
+        typed.unwrap_or(Value::String(value))
+    }
+
+    /// Checks that a value has the type the setting expects.
+    fn validate_value(&self, value: Option<(String, Source)>, errors: &mut Vec<String>) {
+        let Some((value, source)) = value else {
+            return;
+        };
+        let valid = match self {
+            Config::Port | Config::RedisPort => value.parse::<u16>().is_ok(),
+            Config::MaxUploadSize | Config::MaxRollback => value.parse::<u32>().is_ok(),
+            Config::WarmupWorkers => value.parse::<usize>().is_ok(),
+            Config::RedisNamespace => !value.is_empty(),
+            Config::DiskCacheSize => value.parse::<u64>().is_ok(),
+            Config::CacheCompression => value.parse::<i32>().is_ok_and(|v| (0..=22).contains(&v)),
+            Config::CacheTtl => parse_category_list::<u64>(&value).is_some(),
+            Config::CacheMaxSize => parse_category_list::<usize>(&value).is_some(),
+            Config::WarmupPriority => value
+                .split(',')
+                .all(|v| WarmupPriority::from_name(v.trim()).is_some()),
+            Config::WarmupAssets => value
+                .split(',')
+                .all(|v| RenderedAsset::from_name(v.trim()).is_some()),
+            Config::RefreshInterval | Config::StatsInterval => {
+                value.parse::<u64>().is_ok_and(|v| v > 0)
+            }
+            Config::ShutdownDelay | Config::DrainTimeout => value.parse::<u64>().is_ok(),
+            Config::GitDepth => value.parse::<i32>().is_ok(),
+            Config::Bind => value.parse::<IpAddr>().is_ok(),
+            Config::CorsOrigin => HeaderValue::from_str(&value).is_ok(),
+            Config::SubmissionsPush | Config::CacheLock => parse_bool(&value).is_some(),
+            Config::CacheBackend => matches!(value.as_str(), "redis" | "memory"),
+            Config::TrustPolicy => matches!(value.as_str(), "off" | "tip" | "all"),
+            _ => true,
+        };
+        if !valid {
+            errors.push(format!(
+                "Invalid value for {} ({}): {}",
+                self.key(),
+                source,
+                value
+            ));
         }
     }
 
-    pub fn redis_config() -> (String, u16) {
+    /// Looks up the value in the environment, the config file and the defaults.
+    fn lookup(&self) -> Option<(String, Source)> {
+        if let Ok(value) = var(self.env_name()) {
+            return Some((value, Source::Env(self.env_name())));
+        }
+        if let Some(file) = ConfigFile::get() {
+            let value = match self {
+                // The datasets are the tables in `[datasets]`.
+                Config::Datasets => file.values.get("datasets").and_then(|v| {
+                    v.as_table()
+                        .map(|table| table.keys().cloned().collect::<Vec<_>>().join(","))
+                }),
+                _ => file.values.get(&self.file_key()).and_then(value_to_string),
+            };
+            if let Some(value) = value {
+                return Some((value, Source::File(self.file_key())));
+            }
+        }
+        self.default().map(|v| (v, Source::Default))
+    }
+
+    /// The value of the setting. Settings without a default are empty if they are not set,
+    /// [`Config::check`] makes sure the required ones are set before the server starts.
+    pub fn get(&self) -> String {
+        self.get_or_none().unwrap_or_default()
+    }
+
+    pub fn get_or_none(&self) -> Option<String> {
+        self.lookup().map(|(value, _)| value)
+    }
+
+    /// Address and port to listen on.
+    pub fn bind_address() -> (IpAddr, u16) {
         (
+            Self::Bind.get().parse().expect("Invalid SCSRV_BIND"),
+            Self::Port.get().parse().expect("Invalid SCSRV_PORT"),
+        )
+    }
+
+    /// Value of the `Access-Control-Allow-Origin` header.
+    pub fn cors_origin() -> HeaderValue {
+        HeaderValue::from_str(&Self::CorsOrigin.get()).expect("Invalid SCSRV_CORS_ORIGIN")
+    }
+
+    /// Maximum size of uploaded files in bytes.
+    pub fn max_upload_size() -> usize {
+        Self::MaxUploadSize
+            .get()
+            .parse()
+            .expect("Invalid SCSRV_MAX_UPLOAD_SIZE")
+    }
+
+    /// How long the server keeps accepting connections after it stopped being ready, so load
+    /// balancers can notice before it goes away.
+    pub fn shutdown_delay() -> Duration {
+        Duration::from_secs(
+            Self::ShutdownDelay
+                .get()
+                .parse()
+                .expect("Invalid SCSRV_SHUTDOWN_DELAY"),
+        )
+    }
+
+    /// How long open connections are waited for on shutdown.
+    pub fn drain_timeout() -> Duration {
+        Duration::from_secs(
+            Self::DrainTimeout
+                .get()
+                .parse()
+                .expect("Invalid SCSRV_DRAIN_TIMEOUT"),
+        )
+    }
+
+    /// Host and port of the Redis server. `None` if values are cached in memory instead.
+    pub fn redis_config() -> Option<(String, u16)> {
+        if Self::CacheBackend.get() == "memory" {
+            return None;
+        }
+        Some((
             Self::RedisHost.get(),
             Self::RedisPort
                 .get()
                 .parse::<u16>()
                 .expect("Invalid Redis port"),
+        ))
+    }
+
+    /// Whether computing a cached value is coordinated through a lock in Redis, so servers
+    /// sharing the Redis server don't compute the same value at the same time.
+    pub fn cache_lock() -> bool {
+        parse_bool(&Self::CacheLock.get()).unwrap_or_default()
+    }
+
+    /// zstd level cached JSON values are compressed with. 0 disables compression.
+    pub fn cache_compression() -> i32 {
+        Self::CacheCompression
+            .get()
+            .parse()
+            .expect("Invalid SCSRV_CACHE_COMPRESSION")
+    }
+
+    /// How long cached values are kept, by key category. Values of categories without a TTL are
+    /// kept until the cache is cleared.
+    pub fn cache_ttls() -> HashMap<String, Duration> {
+        Self::CacheTtl
+            .get_or_none()
+            .map(|v| parse_category_list(&v).expect("Invalid SCSRV_CACHE_TTL"))
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(category, secs)| (category, Duration::from_secs(secs)))
+            .collect()
+    }
+
+    /// Maximum size of cached values in bytes, by key category.
+    pub fn cache_max_sizes() -> HashMap<String, usize> {
+        Self::CacheMaxSize
+            .get_or_none()
+            .map(|v| parse_category_list(&v).expect("Invalid SCSRV_CACHE_MAX_SIZE"))
+            .unwrap_or_default()
+    }
+
+    /// Implements `config check`: Prints the effective configuration and all validation errors.
+    /// Returns whether the configuration is valid.
+    pub fn print_check() -> bool {
+        match FILE.get() {
+            Some(Ok(Some(file))) => println!("# Config file: {}", file.path.display()),
+            _ => println!("# No config file"),
+        }
+        for config in Self::iter().filter(Config::is_global) {
+            print_value(config, config.lookup());
+        }
+        for dataset in Dataset::try_all().unwrap_or_default() {
+            println!();
+            if let Some(name) = dataset.name() {
+                println!("[datasets.{}]", name);
+            }
+            println!(
+                "# workdir = {}",
+                toml::Value::String(dataset.workdir().display().to_string())
+            );
+            for config in Self::iter().filter(|c| !c.is_global()) {
+                print_value(config, dataset.lookup(config));
+            }
+        }
+        let errors = Self::validate();
+        println!();
+        if errors.is_empty() {
+            println!("# The configuration is valid.");
+        } else {
+            println!("# {} errors:", errors.len());
+            for error in &errors {
+                println!("# - {}", error);
+            }
+        }
+        errors.is_empty()
+    }
+}
+
+/// A SpriteCollab repository (or data directory) served by this server, with its own data,
+/// refresh schedule and cache namespace.
+///
+/// Datasets are listed in `SCSRV_DATASETS` (comma separated) or are the tables in `[datasets]`
+/// of the config file. Settings can be overridden for a dataset with `SCSRV_<NAME>_<SETTING>`,
+/// eg. `SCSRV_MYCOLLAB_GIT_REPO` for the dataset `mycollab`, or in its table. Otherwise the global
+/// value is used. If no datasets are configured, the server serves a single unnamed dataset.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Dataset {
+    name: Option<String>,
+}
+
+impl Dataset {
+    /// All configured datasets. The first one is the default dataset, that is also served
+    /// without a prefix.
+    pub fn all() -> Result<Vec<Self>, anyhow::Error> {
+        Self::try_all().map_err(anyhow::Error::msg)
+    }
+
+    fn try_all() -> Result<Vec<Self>, String> {
+        let Some(names) = Config::Datasets.get_or_none() else {
+            return Ok(vec![Self { name: None }]);
+        };
+        let datasets = names
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(|name| {
+                if !name
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
+                    || RESERVED_DATASET_NAMES.contains(&name)
+                {
+                    return Err(format!("Invalid dataset name: {}", name));
+                }
+                if let Some(collision) = env_collision(name) {
+                    return Err(format!(
+                        "Invalid dataset name: {}, its settings could be confused with {}",
+                        name, collision
+                    ));
+                }
+                Ok(Self {
+                    name: Some(name.to_string()),
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if datasets.is_empty() {
+            return Err("SCSRV_DATASETS is empty".to_string());
+        }
+        Ok(datasets)
+    }
+
+    fn validate(&self, errors: &mut Vec<String>) {
+        let prefix = self
+            .name
+            .as_ref()
+            .map(|name| format!("Dataset {}: ", name))
+            .unwrap_or_default();
+        let mut dataset_errors = Vec::new();
+        for config in Config::iter().filter(|c| !c.is_global()) {
+            config.validate_value(self.lookup(config), &mut dataset_errors);
+        }
+        if self.data_dir().is_none() && self.lookup(Config::GitRepo).is_none() {
+            dataset_errors.push(format!("{} is not set", Config::GitRepo.env_name()));
+        }
+        if self.lookup(Config::GitAssetsUrl).is_none() {
+            dataset_errors.push(format!("{} is not set", Config::GitAssetsUrl.env_name()));
+        }
+        errors.extend(
+            dataset_errors
+                .into_iter()
+                .map(|e| format!("{}{}", prefix, e)),
+        );
+    }
+
+    /// `None` for the single dataset if no datasets are configured.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Prefix of all routes of this dataset, eg. `/mycollab`. Empty for the unnamed dataset.
+    pub fn route_prefix(&self) -> String {
+        self.name
+            .as_ref()
+            .map(|name| format!("/{}", name))
+            .unwrap_or_default()
+    }
+
+    /// Path of the GraphQL endpoint of this dataset.
+    pub fn graphql_path(&self) -> String {
+        match &self.name {
+            Some(name) => format!("/graphql/{}", name),
+            None => "/graphql".to_string(),
+        }
+    }
+
+    /// Prefix of the cache keys of this dataset. The default dataset has an empty name, so its
+    /// keys can be told apart from those of named datasets.
+    pub fn cache_namespace(&self) -> String {
+        format!("{}|", self.name.as_deref().unwrap_or_default())
+    }
+
+    /// The value of the setting for this dataset. Like [`Config::get`], settings without a default
+    /// are empty if they are not set.
+    pub fn get(&self, config: Config) -> String {
+        self.get_or_none(config).unwrap_or_default()
+    }
+
+    pub fn get_or_none(&self, config: Config) -> Option<String> {
+        self.lookup(config).map(|(value, _)| value)
+    }
+
+    /// Looks up the value set for this dataset and falls back to the global value.
+    fn lookup(&self, config: Config) -> Option<(String, Source)> {
+        self.lookup_own(config).or_else(|| config.lookup())
+    }
+
+    /// Looks up the value set for this dataset, without falling back to the global value.
+    fn lookup_own(&self, config: Config) -> Option<(String, Source)> {
+        let name = self.name.as_ref()?;
+        let env_name = self.env_name(name, config);
+        if let Ok(value) = var(&env_name) {
+            return Some((value, Source::Env(env_name)));
+        }
+        let file = ConfigFile::get()?;
+        let value = file
+            .values
+            .get("datasets")?
+            .get(name)?
+            .get(config.file_key())
+            .and_then(value_to_string)?;
+        Some((
+            value,
+            Source::File(format!("datasets.{}.{}", name, config.file_key())),
+        ))
+    }
+
+    fn env_name(&self, name: &str, config: Config) -> String {
+        dataset_env_name(name, config)
+    }
+
+    /// Directory for the files of this dataset. Named datasets use `$SCSRV_WORKDIR/datasets/<name>`
+    /// unless their workdir is set explicitly.
+    pub fn workdir(&self) -> PathBuf {
+        if let Some((workdir, _)) = self.lookup_own(Config::Workdir) {
+            return PathBuf::from(workdir);
+        }
+        let workdir = PathBuf::from(Config::Workdir.get());
+        match &self.name {
+            Some(name) => workdir.join(DATASETS_DIR).join(name),
+            None => workdir,
+        }
+    }
+
+    /// Whether approved submissions are pushed to the `origin` remote.
+    pub fn push_submissions(&self) -> bool {
+        parse_bool(&self.get(Config::SubmissionsPush)).unwrap_or_default()
+    }
+
+    /// How often the data is refreshed.
+    pub fn refresh_interval(&self) -> Duration {
+        Duration::from_secs(
+            self.get(Config::RefreshInterval)
+                .parse()
+                .expect("Invalid SCSRV_REFRESH_INTERVAL"),
         )
     }
+
+    /// How often statistics snapshots are recorded.
+    pub fn stats_interval(&self) -> Duration {
+        Duration::from_secs(
+            self.get(Config::StatsInterval)
+                .parse()
+                .expect("Invalid SCSRV_STATS_INTERVAL"),
+        )
+    }
+
+    /// Number of assets rendered at the same time by the cache warmup. 0 disables it.
+    pub fn warmup_workers(&self) -> usize {
+        self.get(Config::WarmupWorkers)
+            .parse()
+            .expect("Invalid SCSRV_WARMUP_WORKERS")
+    }
+
+    /// The criteria forms are warmed up by, most important first.
+    pub fn warmup_priority(&self) -> Vec<WarmupPriority> {
+        self.get(Config::WarmupPriority)
+            .split(',')
+            .filter_map(|v| WarmupPriority::from_name(v.trim()))
+            .collect()
+    }
+
+    /// The assets rendered by the cache warmup.
+    pub fn warmup_assets(&self) -> Vec<RenderedAsset> {
+        self.get(Config::WarmupAssets)
+            .split(',')
+            .filter_map(|v| RenderedAsset::from_name(v.trim()))
+            .collect()
+    }
+
+    /// Maximum size of the disk cache of rendered assets in bytes. 0 disables it.
+    pub fn disk_cache_size(&self) -> u64 {
+        self.get(Config::DiskCacheSize)
+            .parse()
+            .expect("Invalid SCSRV_DISK_CACHE_SIZE")
+    }
+
+    /// How many commits the server may go back on startup to find data that can be read.
+    pub fn max_rollback(&self) -> u32 {
+        self.get(Config::MaxRollback)
+            .parse::<u32>()
+            .expect("Invalid SCSRV_MAX_ROLLBACK")
+    }
+
+    /// Number of commits to clone and fetch. `None` for the full history.
+    pub fn git_depth(&self) -> Option<i32> {
+        self.get_or_none(Config::GitDepth)
+            .map(|v| v.parse::<i32>().expect("Invalid SCSRV_GIT_DEPTH"))
+            .filter(|depth| *depth > 0)
+    }
+
+    /// Directory the data is served from instead of a clone of the repository. `None` if the
+    /// repository is used.
+    pub fn data_dir(&self) -> Option<PathBuf> {
+        self.get_or_none(Config::DataDir).map(PathBuf::from)
+    }
+}
+
+struct ConfigFile {
+    path: PathBuf,
+    values: Table,
+}
+
+impl ConfigFile {
+    fn load() -> Result<Option<Self>, String> {
+        let path = match var("SCSRV_CONFIG_FILE") {
+            Ok(path) => PathBuf::from(path),
+            Err(_) if Path::new(DEFAULT_CONFIG_FILE).exists() => PathBuf::from(DEFAULT_CONFIG_FILE),
+            Err(_) => return Ok(None),
+        };
+        let values = read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|content| content.parse::<Table>().map_err(|e| e.to_string()))
+            .map_err(|e| format!("Failed reading {}: {}", path.display(), e))?;
+        Ok(Some(Self { path, values }))
+    }
+
+    fn get() -> Option<&'static Self> {
+        FILE.get()?.as_ref().ok()?.as_ref()
+    }
+
+    /// Errors for keys that are no setting, eg. because of a typo.
+    fn unknown_keys(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+        for key in self.values.keys() {
+            if !Config::iter().any(|c| &c.file_key() == key) {
+                errors.push(format!("Unknown setting {} in the config file", key));
+            }
+        }
+        let datasets = self.values.get("datasets").and_then(Value::as_table);
+        for (name, table) in datasets.into_iter().flatten() {
+            let Some(table) = table.as_table() else {
+                errors.push(format!(
+                    "datasets.{} in the config file is not a table",
+                    name
+                ));
+                continue;
+            };
+            for key in table.keys() {
+                if !Config::iter().any(|c| c.is_per_dataset() && &c.file_key() == key) {
+                    errors.push(format!(
+                        "Unknown setting datasets.{}.{} in the config file",
+                        name, key
+                    ));
+                }
+            }
+        }
+        errors
+    }
+}
+
+/// The prefix of the environment variables that override settings of the dataset `name`.
+fn dataset_env_prefix(name: &str) -> String {
+    format!("SCSRV_{}_", name.to_ascii_uppercase().replace('-', "_"))
+}
+
+/// The environment variable that overrides `config` for the dataset `name`.
+fn dataset_env_name(name: &str, config: Config) -> String {
+    format!("{}{}", dataset_env_prefix(name), config.key())
+}
+
+/// Returns a setting whose environment variable starts like the overrides of the dataset `name`,
+/// eg. `SCSRV_GIT_BRANCH` for the dataset `git`. It would be unclear which setting it sets.
+fn env_collision(name: &str) -> Option<String> {
+    let prefix = dataset_env_prefix(name);
+    Config::iter()
+        .map(|config| config.env_name())
+        .chain(["SCSRV_CONFIG_FILE".to_string()])
+        .find(|env_name| env_name.starts_with(&prefix))
+}
+
+/// Parses comma-separated `<category>=<value>` pairs.
+fn parse_category_list<T: FromStr>(value: &str) -> Option<HashMap<String, T>> {
+    value
+        .split(',')
+        .filter(|v| !v.trim().is_empty())
+        .map(|v| {
+            let (category, value) = v.split_once('=')?;
+            Some((category.trim().to_string(), value.trim().parse().ok()?))
+        })
+        .collect()
+}
+
+/// Converts a value of the config file to the format of the environment variables. Lists are
+/// comma separated.
+fn value_to_string(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Integer(i) => Some(i.to_string()),
+        Value::Float(f) => Some(f.to_string()),
+        Value::Boolean(b) => Some(b.to_string()),
+        Value::Array(values) => Some(
+            values
+                .iter()
+                .filter_map(value_to_string)
+                .collect::<Vec<_>>()
+                .join(","),
+        ),
+        Value::Datetime(_) | Value::Table(_) => None,
+    }
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value {
+        "1" | "true" | "yes" => Some(true),
+        "0" | "false" | "no" => Some(false),
+        _ => None,
+    }
+}
+
+fn print_value(config: Config, value: Option<(String, Source)>) {
+    let key = config.file_key();
+    match value {
+        Some(_) if config.is_secret() => println!("{} = \"********\"", key),
+        Some((value, source)) => println!("{} = {}  # {}", key, config.typed(value), source),
+        None => println!("# {} is not set", key),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILE_CONTENT: &str = r#"
+max_rollback = 7
+refresh_interval = 100
+stats_interval = 10
+redis_hots = "typo"
+
+[datasets.alpha]
+refresh_interval = 300
+stats_interval = 20
+git_depht = 1
+port = 3000
+"#;
+
+    /// Every test uses the same config file, it can only be loaded once.
+    fn init_file() {
+        FILE.get_or_init(|| {
+            Ok(Some(ConfigFile {
+                path: PathBuf::from("test.toml"),
+                values: FILE_CONTENT.parse().unwrap(),
+            }))
+        });
+    }
+
+    /// Each variable must only be set by one test, the tests run in parallel.
+    fn set_env(name: &str, value: &str) {
+        // SAFETY: No test reads a variable another test sets.
+        unsafe { std::env::set_var(name, value) };
+    }
+
+    fn dataset(name: &str) -> Dataset {
+        Dataset {
+            name: Some(name.to_string()),
+        }
+    }
+
+    fn lookup(dataset: &Dataset, config: Config) -> (String, String) {
+        let (value, source) = dataset.lookup(config).unwrap();
+        (value, source.to_string())
+    }
+
+    fn pair(value: &str, source: &str) -> (String, String) {
+        (value.to_string(), source.to_string())
+    }
+
+    #[test]
+    fn default_applies_without_value() {
+        init_file();
+        assert_eq!(
+            lookup(&dataset("beta"), Config::GitUsername),
+            pair("x-access-token", "default")
+        );
+    }
+
+    #[test]
+    fn file_overrides_default() {
+        init_file();
+        assert_eq!(
+            lookup(&dataset("beta"), Config::MaxRollback),
+            pair("7", "file max_rollback")
+        );
+    }
+
+    #[test]
+    fn env_overrides_file() {
+        init_file();
+        set_env("SCSRV_REFRESH_INTERVAL", "200");
+        assert_eq!(
+            lookup(&dataset("beta"), Config::RefreshInterval),
+            pair("200", "env SCSRV_REFRESH_INTERVAL")
+        );
+        // The dataset table still overrides the global environment variable.
+        assert_eq!(
+            lookup(&dataset("alpha"), Config::RefreshInterval),
+            pair("300", "file datasets.alpha.refresh_interval")
+        );
+    }
+
+    #[test]
+    fn dataset_env_overrides_dataset_table() {
+        init_file();
+        set_env("SCSRV_ALPHA_STATS_INTERVAL", "30");
+        assert_eq!(
+            lookup(&dataset("alpha"), Config::StatsInterval),
+            pair("30", "env SCSRV_ALPHA_STATS_INTERVAL")
+        );
+        assert_eq!(
+            lookup(&dataset("beta"), Config::StatsInterval),
+            pair("10", "file stats_interval")
+        );
+    }
+
+    #[test]
+    fn dataset_env_names_use_underscores() {
+        assert_eq!(
+            dataset_env_name("my-collab", Config::GitRepo),
+            "SCSRV_MY_COLLAB_GIT_REPO"
+        );
+    }
+
+    #[test]
+    fn reports_unknown_keys() {
+        init_file();
+        assert_eq!(
+            ConfigFile::get().unwrap().unknown_keys(),
+            [
+                "Unknown setting redis_hots in the config file",
+                "Unknown setting datasets.alpha.git_depht in the config file",
+                "Unknown setting datasets.alpha.port in the config file",
+            ]
+        );
+    }
+
+    #[test]
+    fn settings_have_distinct_names() {
+        let mut keys: Vec<&str> = Config::iter().map(|c| c.key()).collect();
+        let count = keys.len();
+        keys.sort();
+        keys.dedup();
+        assert_eq!(keys.len(), count);
+    }
+
+    #[test]
+    fn detects_dataset_names_like_global_settings() {
+        assert_eq!(env_collision("git").as_deref(), Some("SCSRV_GIT_REPO"));
+        assert_eq!(
+            env_collision("config").as_deref(),
+            Some("SCSRV_CONFIG_FILE")
+        );
+        assert_eq!(env_collision("mycollab"), None);
+        assert_eq!(env_collision("gitcollab"), None);
+    }
 }
@@ -0,0 +1,87 @@
+//! Offline mode: Serving a plain directory with the SpriteCollab layout instead of a clone of the
+//! Git repository (`SCSRV_DATA_DIR`).
+//!
+//! There are no commits in this mode, the data is identified by a hash of the content of the
+//! directory instead. Changes to the directory are picked up by a file watcher.
+
+use std::fs::{File, read_dir};
+use std::io::copy;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use anyhow::Error;
+use chrono::{DateTime, Utc};
+use log::{debug, info, warn};
+use notify::event::{AccessKind, AccessMode, EventKind};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use sha2::{Digest, Sha256};
+
+use crate::jobs::REFRESH_JOB;
+use crate::sprite_collab::SpriteCollab;
+
+/// Hash of the content of the data directory, and the time it was last modified.
+pub struct ContentHash {
+    pub hash: String,
+    pub modified: DateTime<Utc>,
+}
+
+/// Hashes the paths and contents of all files in `dir`. Hidden files and directories are skipped.
+pub fn content_hash(dir: &Path) -> Result<ContentHash, Error> {
+    let mut files = Vec::new();
+    collect_files(dir, &mut files)?;
+    files.sort();
+
+    let mut hasher = Sha256::new();
+    let mut modified = SystemTime::UNIX_EPOCH;
+    for file in &files {
+        let relative = file.strip_prefix(dir)?;
+        hasher.update(relative.to_string_lossy().as_bytes());
+        hasher.update([0]);
+        let mut f = File::open(file)?;
+        modified = modified.max(f.metadata()?.modified()?);
+        copy(&mut f, &mut hasher)?;
+        hasher.update([0]);
+    }
+    Ok(ContentHash {
+        hash: hex::encode(hasher.finalize()),
+        modified: modified.into(),
+    })
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<(), Error> {
+    for entry in read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            collect_files(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Watches `dir` and requests a refresh whenever files in it change. The watcher stops when the
+/// returned value is dropped.
+pub fn watch(dir: &Path, sprite_collab: Arc<SpriteCollab>) -> Result<RecommendedWatcher, Error> {
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        match event {
+            // Files are only changed if they were opened for writing. This also ignores the reads
+            // of the refresh itself.
+            Ok(event) if matches!(event.kind, EventKind::Access(kind) if kind != AccessKind::Close(AccessMode::Write)) =>
+                {}
+            Ok(event) => {
+                debug!("Data directory changed: {:?}", event.paths);
+                sprite_collab.jobs().trigger(REFRESH_JOB, "file watcher");
+            }
+            Err(e) => warn!("Error watching the data directory: {}", e),
+        }
+    })?;
+    watcher.watch(dir, RecursiveMode::Recursive)?;
+    info!("Watching {} for changes.", dir.display());
+    Ok(watcher)
+}
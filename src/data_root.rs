@@ -0,0 +1,138 @@
+//! Snapshots of the SpriteCollab repository that the data and assets are served from.
+//!
+//! Each commit that is served gets its own checkout below `trees` in the workdir of the dataset. A refresh reads
+//! and validates the data from a fresh snapshot and only then swaps it in, together with the
+//! data read from it. Requests keep using the snapshot they started with, a replaced snapshot is
+//! deleted once the last request holding it finished.
+
+use std::fs::{create_dir_all, read_dir, remove_dir_all};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+
+use anyhow::Error;
+use git2::build::CheckoutBuilder;
+use git2::{Commit, Repository};
+use log::{debug, warn};
+
+use crate::assets::util::join_monster_and_form;
+use crate::config::Dataset;
+
+const TREES_DIR: &str = "trees";
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+pub struct DataRoot {
+    path: PathBuf,
+    commit: String,
+    /// Whether the directory is a snapshot that is removed when it is no longer used.
+    owned: bool,
+}
+
+impl DataRoot {
+    /// Checks out the tree of `commit` into a new snapshot directory.
+    pub fn create(dataset: &Dataset, repo: &Repository, commit: &Commit) -> Result<Self, Error> {
+        let path = trees_dir(dataset).join(format!(
+            "{}-{}",
+            commit.id(),
+            NEXT_ID.fetch_add(1, Ordering::Relaxed)
+        ));
+        create_dir_all(&path)?;
+        // Delete the directory again if the checkout fails.
+        let root = Self {
+            path,
+            commit: commit.id().to_string(),
+            owned: true,
+        };
+        debug!("Checking out {} into {}.", root.commit, root.path.display());
+        repo.checkout_tree(
+            commit.as_object(),
+            Some(
+                CheckoutBuilder::new()
+                    .target_dir(&root.path)
+                    .update_index(false)
+                    .force()
+                    .recreate_missing(true),
+            ),
+        )?;
+        Ok(root)
+    }
+
+    /// Uses a directory that is managed by someone else, like the data directory
+    /// (`SCSRV_DATA_DIR`). `commit` identifies its content.
+    pub fn external(path: PathBuf, commit: String) -> Self {
+        Self {
+            path,
+            commit,
+            owned: false,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn commit(&self) -> &str {
+        &self.commit
+    }
+
+    /// Path to the portrait directory of a form.
+    pub fn portrait_dir(&self, monster_idx: i32, form_path: &[i32]) -> PathBuf {
+        self.path.join(format!(
+            "portrait/{}",
+            join_monster_and_form(monster_idx, form_path, '/')
+        ))
+    }
+
+    /// Path to the sprite directory of a form.
+    pub fn sprite_dir(&self, monster_idx: i32, form_path: &[i32]) -> PathBuf {
+        self.path.join(format!(
+            "sprite/{}",
+            join_monster_and_form(monster_idx, form_path, '/')
+        ))
+    }
+}
+
+impl PartialEq for DataRoot {
+    fn eq(&self, other: &Self) -> bool {
+        self.commit == other.commit
+    }
+}
+
+impl Eq for DataRoot {}
+
+impl Drop for DataRoot {
+    fn drop(&mut self) {
+        if !self.owned {
+            return;
+        }
+        let path = self.path.clone();
+        // Deleting a whole checkout takes a while, don't block the runtime with it.
+        thread::spawn(move || {
+            debug!("Removing snapshot {}.", path.display());
+            if let Err(e) = remove_dir_all(&path) {
+                warn!("Failed removing snapshot {}: {}", path.display(), e);
+            }
+        });
+    }
+}
+
+/// Removes snapshots left over from previous runs.
+pub fn remove_stale_trees(dataset: &Dataset) {
+    let Ok(entries) = read_dir(trees_dir(dataset)) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        if let Err(e) = remove_dir_all(entry.path()) {
+            warn!(
+                "Failed removing stale snapshot {}: {}",
+                entry.path().display(),
+                e
+            );
+        }
+    }
+}
+
+fn trees_dir(dataset: &Dataset) -> PathBuf {
+    dataset.workdir().join(TREES_DIR)
+}
@@ -1,10 +1,9 @@
-use crate::Config;
 use crate::assets::util::join_monster_and_form;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::{BufReader, Read};
-use std::path::{Path, PathBuf};
+use std::path::Path;
 use thiserror::Error;
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -57,13 +56,14 @@ impl AnimDataXml {
         Ok(Self::from_reader(file_reader)?)
     }
 
+    /// Opens the AnimData.xml of a form in the checkout at `root`.
     pub fn open_for_form(
+        root: &Path,
         monster_idx: i32,
         path_to_form: &[i32],
     ) -> Result<Self, AnimDataXmlOpenError> {
         let joined_f = join_monster_and_form(monster_idx, path_to_form, '/');
-        let path = PathBuf::from(Config::Workdir.get())
-            .join(format!("spritecollab/sprite/{}/AnimData.xml", joined_f));
+        let path = root.join(format!("sprite/{}/AnimData.xml", joined_f));
         Self::open(path)
     }
 
@@ -73,6 +73,20 @@ pub struct LocalCreditRow {
     pub items: Vec<String>,
 }
 
+impl LocalCreditRow {
+    /// Formats the row as a line of a credits file, like SpriteBot writes it (without a line break).
+    pub fn to_line(&self) -> String {
+        format!(
+            "{}\t{}\t{}\t{}\t{}",
+            self.date.format("%Y-%m-%d %H:%M:%S%.6f"),
+            self.credit_id,
+            if self.obsolete { "OLD" } else { "CUR" },
+            self.license,
+            self.items.join(",")
+        )
+    }
+}
+
 // Old version of the credits rows, for backwards compat.
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
 struct LocalCreditRowV0 {
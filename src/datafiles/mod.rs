@@ -72,7 +72,10 @@ where
     out
 }
 
-pub async fn try_read_in_anim_data_xml(tracker: &Tracker) -> Result<(), DataReadError> {
+pub async fn try_read_in_anim_data_xml(
+    root: &Path,
+    tracker: &Tracker,
+) -> Result<(), DataReadError> {
     let errs = tracker
         .keys()
         .flat_map(|group_id| {
@@ -84,7 +87,7 @@ pub async fn try_read_in_anim_data_xml(tracker: &Tracker) -> Result<(), DataRead
                     if group.sprite_complete == 0 {
                         return None;
                     }
-                    if let Err(e) = AnimDataXml::open_for_form(group_id, &path) {
+                    if let Err(e) = AnimDataXml::open_for_form(root, group_id, &path) {
                         Some((group_id, path, Arc::new(e)))
                     } else {
                         None
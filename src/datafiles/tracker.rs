@@ -6,13 +6,13 @@ use std::path::Path;
 
 use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
 use indexmap::IndexMap;
-use serde::{Deserialize, Deserializer};
+use serde::{Deserialize, Deserializer, Serialize};
 use serde_json::Value;
 
 use crate::cache::CacheBehaviour;
 use crate::cache::ScCache;
-use crate::datafiles::DataReadResult;
 use crate::datafiles::group_id::GroupId;
+use crate::datafiles::{DataReadResult, parse_credit_id};
 use crate::search::fuzzy_find;
 
 pub async fn read_tracker<P: AsRef<Path>>(path: P) -> DataReadResult<Tracker> {
@@ -42,7 +42,8 @@ pub struct Group {
     pub portrait_link: String,
     #[serde(deserialize_with = "parse_datetime")]
     pub portrait_modified: Option<DateTime<Utc>>,
-    pub portrait_pending: Value,
+    #[serde(deserialize_with = "parse_pending")]
+    pub portrait_pending: Vec<PendingSubmission>,
     pub portrait_recolor_link: String,
     pub portrait_required: bool,
     pub sprite_bounty: MapImpl<i64, i64>,
@@ -52,12 +53,45 @@ pub struct Group {
     pub sprite_link: String,
     #[serde(deserialize_with = "parse_datetime")]
     pub sprite_modified: Option<DateTime<Utc>>,
-    pub sprite_pending: Value,
+    #[serde(deserialize_with = "parse_pending")]
+    pub sprite_pending: Vec<PendingSubmission>,
     pub sprite_recolor_link: String,
     pub sprite_required: bool,
     pub subgroups: MapImpl<GroupId, Group>,
 }
 
+/// A submission that is waiting to be approved, as recorded by SpriteBot.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct PendingSubmission {
+    /// ID of the submission. For SpriteBot this is the ID of the Discord message.
+    pub id: String,
+    /// The author of the submission.
+    pub credit_id: String,
+}
+
+/// SpriteBot stores pending submissions as an object, mapping the ID of the Discord message to
+/// the ID of the author. Entries in another format are skipped, anything but an object is treated
+/// as having nothing pending.
+fn parse_pending<'de, D>(deser: D) -> Result<Vec<PendingSubmission>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let Value::Object(map) = Value::deserialize(deser)? else {
+        return Ok(Vec::new());
+    };
+    Ok(map
+        .into_iter()
+        .filter_map(|(id, author)| {
+            let credit_id = match author {
+                Value::Number(n) => n.to_string(),
+                Value::String(s) if !s.is_empty() => parse_credit_id(s),
+                _ => return None,
+            };
+            Some(PendingSubmission { id, credit_id })
+        })
+        .collect())
+}
+
 fn parse_datetime<'de, D>(deser: D) -> Result<Option<DateTime<Utc>>, D::Error>
 where
     D: Deserializer<'de>,
@@ -72,8 +106,121 @@ where
     }
 }
 
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Modified,
+}
+
+/// A form whose tracker entry differs between two versions of the tracker.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct FormChange {
+    pub monster_id: i32,
+    /// Path to the form, without the monster ID.
+    pub form_path: Vec<i32>,
+    pub change: ChangeKind,
+    /// Whether any of the portrait fields changed.
+    pub portraits: bool,
+    /// Whether any of the sprite fields changed.
+    pub sprites: bool,
+}
+
+/// Lists all forms that were added, removed or modified between two versions of the tracker.
+pub fn diff_trackers(old: &Tracker, new: &Tracker) -> Vec<FormChange> {
+    let mut changes = Vec::new();
+    for key in old
+        .keys()
+        .chain(new.keys().filter(|k| !old.contains_key(*k)))
+    {
+        diff_groups(
+            **key as i32,
+            Vec::new(),
+            old.get(key),
+            new.get(key),
+            &mut changes,
+        );
+    }
+    changes
+}
+
+fn diff_groups(
+    monster_id: i32,
+    form_path: Vec<i32>,
+    old: Option<&Group>,
+    new: Option<&Group>,
+    changes: &mut Vec<FormChange>,
+) {
+    let change = match (old, new) {
+        (Some(old), Some(new)) => {
+            let portraits = !portraits_eq(old, new);
+            let sprites = !sprites_eq(old, new);
+            let other =
+                old.name != new.name || old.canon != new.canon || old.modreward != new.modreward;
+            (portraits || sprites || other).then_some((ChangeKind::Modified, portraits, sprites))
+        }
+        (None, Some(_)) => Some((ChangeKind::Added, true, true)),
+        (Some(_), None) => Some((ChangeKind::Removed, true, true)),
+        (None, None) => None,
+    };
+    if let Some((change, portraits, sprites)) = change {
+        changes.push(FormChange {
+            monster_id,
+            form_path: form_path.clone(),
+            change,
+            portraits,
+            sprites,
+        });
+    }
+
+    let empty = MapImpl::new();
+    let old_sub = old.map(|g| &g.subgroups).unwrap_or(&empty);
+    let new_sub = new.map(|g| &g.subgroups).unwrap_or(&empty);
+    for key in old_sub
+        .keys()
+        .chain(new_sub.keys().filter(|k| !old_sub.contains_key(*k)))
+    {
+        let mut sub_path = form_path.clone();
+        sub_path.push(**key as i32);
+        diff_groups(
+            monster_id,
+            sub_path,
+            old_sub.get(key),
+            new_sub.get(key),
+            changes,
+        );
+    }
+}
+
+fn portraits_eq(a: &Group, b: &Group) -> bool {
+    a.portrait_bounty == b.portrait_bounty
+        && a.portrait_complete == b.portrait_complete
+        && a.portrait_credit == b.portrait_credit
+        && a.portrait_files == b.portrait_files
+        && a.portrait_link == b.portrait_link
+        && a.portrait_modified == b.portrait_modified
+        && a.portrait_pending == b.portrait_pending
+        && a.portrait_recolor_link == b.portrait_recolor_link
+        && a.portrait_required == b.portrait_required
+}
+
+fn sprites_eq(a: &Group, b: &Group) -> bool {
+    a.sprite_bounty == b.sprite_bounty
+        && a.sprite_complete == b.sprite_complete
+        && a.sprite_credit == b.sprite_credit
+        && a.sprite_files == b.sprite_files
+        && a.sprite_link == b.sprite_link
+        && a.sprite_modified == b.sprite_modified
+        && a.sprite_pending == b.sprite_pending
+        && a.sprite_recolor_link == b.sprite_recolor_link
+        && a.sprite_required == b.sprite_required
+}
+
+/// `commit` is the commit the tracker was read from.
 pub async fn fuzzy_find_tracker<S, C, E, T, F>(
     tracker: &Tracker,
+    commit: &str,
     monster_name: S,
     cache: &C,
     consume: F,
@@ -84,7 +231,7 @@ where
     F: Fn(i64) -> T,
 {
     let index: MapImpl<String, Vec<i64>> = cache
-        .cached("fuzzy_find_tracker", || async {
+        .cached("fuzzy_find_tracker", commit, || async {
             let mut names: MapImpl<String, Vec<i64>> = MapImpl::with_capacity(tracker.len() * 10);
             for (monster_idx, monster) in tracker.iter() {
                 fft_insert(&mut names, **monster_idx, &monster.name);
@@ -357,3 +504,136 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn group(name: &str, subgroups: Value) -> Value {
+        json!({
+            "canon": true,
+            "modreward": false,
+            "name": name,
+            "portrait_bounty": {},
+            "portrait_complete": 0,
+            "portrait_credit": {"primary": "", "secondary": [], "total": 0},
+            "portrait_files": {"Normal": true},
+            "portrait_link": "",
+            "portrait_modified": "",
+            "portrait_pending": {},
+            "portrait_recolor_link": "",
+            "portrait_required": false,
+            "sprite_bounty": {},
+            "sprite_complete": 0,
+            "sprite_credit": {"primary": "", "secondary": [], "total": 0},
+            "sprite_files": {"Idle": false},
+            "sprite_link": "",
+            "sprite_modified": "",
+            "sprite_pending": {},
+            "sprite_recolor_link": "",
+            "sprite_required": false,
+            "subgroups": subgroups,
+        })
+    }
+
+    fn tracker(value: Value) -> Tracker {
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn change(monster_id: i32, form_path: &[i32], change: ChangeKind) -> FormChange {
+        FormChange {
+            monster_id,
+            form_path: form_path.to_vec(),
+            change,
+            portraits: true,
+            sprites: true,
+        }
+    }
+
+    #[test]
+    fn diff_of_equal_trackers_is_empty() {
+        let old = tracker(json!({"0001": group("A", json!({"0001": group("B", json!({}))}))}));
+        assert_eq!(diff_trackers(&old, &old.clone()), []);
+    }
+
+    #[test]
+    fn diff_lists_added_and_removed_forms() {
+        let old = tracker(json!({
+            "0001": group("A", json!({"0001": group("Mega", json!({}))})),
+            "0002": group("B", json!({})),
+        }));
+        let new = tracker(json!({
+            "0001": group("A", json!({"0002": group("Alola", json!({}))})),
+            "0003": group("C", json!({})),
+        }));
+        assert_eq!(
+            diff_trackers(&old, &new),
+            [
+                change(1, &[1], ChangeKind::Removed),
+                change(1, &[2], ChangeKind::Added),
+                change(2, &[], ChangeKind::Removed),
+                change(3, &[], ChangeKind::Added),
+            ]
+        );
+    }
+
+    #[test]
+    fn diff_tells_portrait_and_sprite_changes_apart() {
+        let old = tracker(json!({"0001": group("A", json!({"0001": group("B", json!({}))}))}));
+        let mut new = old.clone();
+        let monster = new.get_mut(&GroupId(1)).unwrap();
+        monster.portrait_files.insert("Happy".to_string(), false);
+        monster.subgroups.get_mut(&GroupId(1)).unwrap().sprite_link =
+            "https://example.org".to_string();
+        let mut name_changed = old.clone();
+        name_changed.get_mut(&GroupId(1)).unwrap().name = "Z".to_string();
+
+        assert_eq!(
+            diff_trackers(&old, &new),
+            [
+                FormChange {
+                    portraits: true,
+                    sprites: false,
+                    ..change(1, &[], ChangeKind::Modified)
+                },
+                FormChange {
+                    portraits: false,
+                    sprites: true,
+                    ..change(1, &[1], ChangeKind::Modified)
+                },
+            ]
+        );
+        assert_eq!(
+            diff_trackers(&old, &name_changed),
+            [FormChange {
+                portraits: false,
+                sprites: false,
+                ..change(1, &[], ChangeKind::Modified)
+            }]
+        );
+    }
+
+    #[test]
+    fn parses_pending_submissions() {
+        let mut value = group("A", json!({}));
+        value["portrait_pending"] = json!({"111": 123, "222": "<@!456>", "333": {}});
+        value["sprite_pending"] = json!([]);
+        let group: Group = serde_json::from_value(value).unwrap();
+        assert_eq!(
+            group.portrait_pending,
+            [
+                PendingSubmission {
+                    id: "111".to_string(),
+                    credit_id: "123".to_string(),
+                },
+                PendingSubmission {
+                    id: "222".to_string(),
+                    credit_id: "456".to_string(),
+                },
+            ]
+        );
+        assert_eq!(group.sprite_pending, []);
+    }
+}
@@ -0,0 +1,126 @@
+//! The datasets served by this server and routing requests to them.
+//!
+//! The first dataset is served at the usual routes (`/graphql`, `/assets/...`). Every named
+//! dataset is also served at `/graphql/<name>` and with its name as prefix for all other routes,
+//! eg. `/<name>/assets/...`.
+
+use std::sync::Arc;
+
+use anyhow::{Error, anyhow};
+use futures::future::join_all;
+use hyper::{Request, Uri};
+use log::{error, info, warn};
+use notify::RecommendedWatcher;
+
+use crate::cache::{CacheBackend, CacheOptions};
+use crate::config::{Config, Dataset};
+use crate::data_dir;
+use crate::jobs::RefreshJob;
+use crate::sprite_collab::SpriteCollab;
+use crate::stats::StatsJob;
+use crate::warmup::{WarmupJob, WarmupScope};
+use crate::webhooks::WebhookJob;
+
+/// A served dataset with its data.
+pub struct DatasetInstance {
+    pub sprite_collab: Arc<SpriteCollab>,
+    /// Watches the data directory, if one is configured. Stops when dropped.
+    _watcher: Option<RecommendedWatcher>,
+}
+
+pub struct Datasets(Vec<Arc<DatasetInstance>>);
+
+impl Datasets {
+    /// Loads the data of all configured datasets and starts their jobs.
+    pub async fn load() -> Result<Self, Error> {
+        let cache = Arc::new(
+            CacheBackend::connect(Config::redis_config(), CacheOptions::from_config()).await,
+        );
+        match cache.delete_old_versions().await {
+            Ok(0) => {}
+            Ok(count) => info!("Removed {} values of older cache versions.", count),
+            Err(e) => warn!("Failed removing values of older cache versions: {:?}", e),
+        }
+        let mut instances = Vec::new();
+        for dataset in Dataset::all()? {
+            let sprite_collab = SpriteCollab::new(dataset.clone(), cache.clone())
+                .await
+                .map_err(|e| with_dataset_name(&dataset, e))?;
+            let watcher = dataset
+                .data_dir()
+                .map(|dir| {
+                    data_dir::watch(&dir, sprite_collab.clone())
+                        .map_err(|e| anyhow!("Failed watching {}: {}", dir.display(), e))
+                })
+                .transpose()
+                .map_err(|e| with_dataset_name(&dataset, e))?;
+            let jobs = sprite_collab.jobs();
+            jobs.spawn(RefreshJob(sprite_collab.clone()));
+            jobs.spawn(StatsJob(sprite_collab.clone()));
+            let webhooks = sprite_collab.webhooks();
+            if webhooks.is_active() {
+                info!("Starting webhook delivery.");
+                jobs.spawn(WebhookJob(webhooks));
+            }
+            if dataset.warmup_workers() > 0 {
+                jobs.spawn(WarmupJob(sprite_collab.clone()));
+                sprite_collab.queue_warmup(WarmupScope::All, "startup");
+            }
+            instances.push(Arc::new(DatasetInstance {
+                sprite_collab,
+                _watcher: watcher,
+            }));
+        }
+        Ok(Self(instances))
+    }
+
+    /// Finds the dataset a request is for. The dataset prefix is removed from the path of the
+    /// returned request, so it can be handled like a request to the default dataset.
+    pub fn route<B>(&self, mut req: Request<B>) -> (Arc<DatasetInstance>, Request<B>) {
+        let path = req.uri().path();
+        let found = self.0.iter().find_map(|instance| {
+            let name = instance.sprite_collab.dataset().name()?;
+            if path == format!("/graphql/{}", name) {
+                return Some((instance, "/graphql".to_string()));
+            }
+            let rest = path.strip_prefix('/')?.strip_prefix(name)?;
+            if rest.is_empty() {
+                Some((instance, "/".to_string()))
+            } else if rest.starts_with('/') {
+                Some((instance, rest.to_string()))
+            } else {
+                None
+            }
+        });
+        let Some((instance, new_path)) = found else {
+            return (self.0[0].clone(), req);
+        };
+        let path_and_query = match req.uri().query() {
+            Some(query) => format!("{}?{}", new_path, query),
+            None => new_path,
+        };
+        match Uri::builder().path_and_query(path_and_query).build() {
+            Ok(uri) => *req.uri_mut() = uri,
+            Err(e) => error!("Failed rewriting request path: {}", e),
+        }
+        (instance.clone(), req)
+    }
+
+    /// Stops the jobs of all datasets.
+    pub async fn shutdown(&self) {
+        join_all(
+            self.0
+                .iter()
+                .map(|instance| instance.sprite_collab.jobs().shutdown()),
+        )
+        .await;
+    }
+}
+
+/// Prefixes errors of named datasets with the name of the dataset.
+fn with_dataset_name(dataset: &Dataset, e: Error) -> Error {
+    match dataset.name() {
+        Some(name) => anyhow!("Dataset {}: {}", name, e),
+        None => e,
+    }
+}
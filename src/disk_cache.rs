@@ -0,0 +1,152 @@
+//! Cache of rendered assets on local disk, below the memory or Redis cache.
+//!
+//! Rendered assets only depend on the data of the commit they were rendered from, so they are
+//! stored keyed by the commit and the cache key and stay valid across restarts and cache
+//! clears. The files are in `render-cache` in the workdir of the dataset. The least recently used
+//! files are removed once they take up more than `SCSRV_DISK_CACHE_SIZE` bytes.
+
+use std::collections::HashMap;
+use std::fs::{create_dir_all, read_dir, remove_file};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use anyhow::Error;
+use log::{debug, warn};
+use sha2::{Digest, Sha256};
+
+use crate::config::Dataset;
+
+const CACHE_DIR: &str = "render-cache";
+const TMP_EXTENSION: &str = "tmp";
+
+struct Entry {
+    size: u64,
+    last_used: SystemTime,
+}
+
+pub struct DiskCache {
+    dir: PathBuf,
+    /// 0 if the cache is disabled.
+    max_size: u64,
+    entries: Mutex<HashMap<String, Entry>>,
+}
+
+impl DiskCache {
+    /// Reads which files are cached. Leftovers of interrupted writes are removed.
+    pub fn load(dataset: &Dataset) -> Self {
+        let dir = dataset.workdir().join(CACHE_DIR);
+        let max_size = dataset.disk_cache_size();
+        let mut entries = HashMap::new();
+        if max_size > 0 {
+            if let Err(e) = create_dir_all(&dir) {
+                warn!("Failed creating {}: {}", dir.display(), e);
+            }
+            for file in read_dir(&dir).into_iter().flatten().flatten() {
+                let path = file.path();
+                if path.extension().is_some_and(|ext| ext == TMP_EXTENSION) {
+                    remove_file(&path).ok();
+                    continue;
+                }
+                if let Ok(metadata) = file.metadata() {
+                    entries.insert(
+                        file.file_name().to_string_lossy().to_string(),
+                        Entry {
+                            size: metadata.len(),
+                            last_used: metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+                        },
+                    );
+                }
+            }
+        }
+        let cache = Self {
+            dir,
+            max_size,
+            entries: Mutex::new(entries),
+        };
+        cache.evict();
+        cache
+    }
+
+    fn file_name(commit: &str, cache_key: &str) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(commit.as_bytes());
+        hasher.update([0]);
+        hasher.update(cache_key.as_bytes());
+        hex::encode(hasher.finalize())
+    }
+
+    pub async fn get(&self, commit: &str, cache_key: &str) -> Option<Vec<u8>> {
+        if self.max_size == 0 {
+            return None;
+        }
+        let name = Self::file_name(commit, cache_key);
+        let now = SystemTime::now();
+        self.entries.lock().unwrap().get_mut(&name)?.last_used = now;
+        let path = self.dir.join(&name);
+        match tokio::fs::read(&path).await {
+            Ok(value) => {
+                // So the order of use survives restarts.
+                if let Ok(file) = tokio::fs::File::options().write(true).open(&path).await {
+                    file.into_std().await.set_modified(now).ok();
+                }
+                Some(value)
+            }
+            Err(e) => {
+                // Evicted in the meantime.
+                debug!("Failed reading {} from the disk cache: {}", cache_key, e);
+                self.entries.lock().unwrap().remove(&name);
+                None
+            }
+        }
+    }
+
+    /// Stores the value. Values larger than the cache aren't stored. Failures are logged.
+    pub async fn put(&self, commit: &str, cache_key: &str, value: &[u8]) {
+        let size = value.len() as u64;
+        if self.max_size == 0 || size > self.max_size {
+            return;
+        }
+        let name = Self::file_name(commit, cache_key);
+        if let Err(e) = self.write(&name, value).await {
+            warn!("Failed writing {} to the disk cache: {}", cache_key, e);
+            return;
+        }
+        self.entries.lock().unwrap().insert(
+            name,
+            Entry {
+                size,
+                last_used: SystemTime::now(),
+            },
+        );
+        self.evict();
+    }
+
+    async fn write(&self, name: &str, value: &[u8]) -> Result<(), Error> {
+        let path = self.dir.join(name);
+        let tmp_path = path.with_extension(TMP_EXTENSION);
+        tokio::fs::write(&tmp_path, value).await?;
+        tokio::fs::rename(&tmp_path, &path).await?;
+        Ok(())
+    }
+
+    /// Removes the least recently used files until the cache fits its size.
+    fn evict(&self) {
+        let mut entries = self.entries.lock().unwrap();
+        let mut size: u64 = entries.values().map(|entry| entry.size).sum();
+        while size > self.max_size {
+            let Some(name) = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(name, _)| name.clone())
+            else {
+                break;
+            };
+            let entry = entries.remove(&name).unwrap();
+            size -= entry.size;
+            if let Err(e) = remove_file(self.dir.join(&name)) {
+                warn!("Failed removing {} from the disk cache: {}", name, e);
+            }
+        }
+    }
+}
@@ -0,0 +1,160 @@
+//! Endpoint for push webhooks of GitHub or Gitea (`POST /hooks/git`), to refresh the data right
+//! after changes were pushed to the SpriteCollab repository.
+//!
+//! The endpoint is only enabled if `SCSRV_GIT_HOOK_SECRET` is set. The same secret must be
+//! configured for the webhook on the Git host.
+
+use hmac::{Hmac, Mac};
+use http_body_util::Full;
+use hyper::body::{Bytes, Incoming};
+use hyper::{HeaderMap, Request, Response, StatusCode};
+use log::{info, warn};
+use serde::Deserialize;
+use sha2::Sha256;
+
+use crate::assets::{AssetBody, make_box_body, read_body};
+use crate::config::Config;
+use crate::git_remote::tracked_branch;
+use crate::jobs::REFRESH_JOB;
+use crate::sprite_collab::SpriteCollab;
+
+#[derive(Deserialize)]
+struct PushPayload {
+    #[serde(rename = "ref")]
+    git_ref: String,
+}
+
+/// Handles a push webhook. Returns `None` if the endpoint is disabled.
+pub async fn process_git_hook(
+    req: Request<Incoming>,
+    sprite_collab: &SpriteCollab,
+) -> Option<Response<AssetBody>> {
+    let dataset = sprite_collab.dataset();
+    let secret = dataset.get_or_none(Config::GitHookSecret)?;
+    let headers = req.headers().clone();
+    let body = match read_body(req.into_body()).await {
+        Ok(v) => v,
+        Err(response) => return Some(response),
+    };
+
+    if !verify_signature(&headers, secret.as_bytes(), &body) {
+        warn!("Git hook with invalid or missing signature received.");
+        return Some(make_response(
+            StatusCode::UNAUTHORIZED,
+            "Invalid signature.",
+        ));
+    }
+
+    let event = headers
+        .get("X-GitHub-Event")
+        .or_else(|| headers.get("X-Gitea-Event"))
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("push");
+    match event {
+        "ping" => return Some(make_response(StatusCode::OK, "pong")),
+        "push" => {}
+        _ => return Some(make_response(StatusCode::OK, "Ignored: Not a push event.")),
+    }
+
+    let payload: PushPayload = match serde_json::from_slice(&body) {
+        Ok(v) => v,
+        Err(e) => {
+            return Some(make_response(
+                StatusCode::BAD_REQUEST,
+                &format!("Invalid payload: {}", e),
+            ));
+        }
+    };
+    if payload.git_ref != format!("refs/heads/{}", tracked_branch(dataset)) {
+        return Some(make_response(
+            StatusCode::OK,
+            "Ignored: Not the tracked branch.",
+        ));
+    }
+
+    info!("Push to {} received, requesting refresh.", payload.git_ref);
+    sprite_collab.jobs().trigger(REFRESH_JOB, "git hook");
+    Some(make_response(StatusCode::ACCEPTED, "Refresh scheduled."))
+}
+
+/// Verifies the HMAC-SHA256 signature of the body. GitHub sends it as
+/// `X-Hub-Signature-256: sha256=<hex>`, Gitea as `X-Gitea-Signature: <hex>`.
+fn verify_signature(headers: &HeaderMap, secret: &[u8], body: &[u8]) -> bool {
+    let signature = headers
+        .get("X-Hub-Signature-256")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("sha256="))
+        .or_else(|| {
+            headers
+                .get("X-Gitea-Signature")
+                .and_then(|v| v.to_str().ok())
+        });
+    let Some(signature) = signature.and_then(|v| hex::decode(v.trim()).ok()) else {
+        return false;
+    };
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any size");
+    mac.update(body);
+    mac.verify_slice(&signature).is_ok()
+}
+
+fn make_response(status: StatusCode, message: &str) -> Response<AssetBody> {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "text/plain; charset=utf-8")
+        .body(make_box_body(Full::new(Bytes::from(message.to_string()))))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::header::HeaderValue;
+
+    use super::*;
+
+    // The example from GitHub's documentation on validating webhook deliveries.
+    const SECRET: &[u8] = b"It's a Secret to Everybody";
+    const BODY: &[u8] = b"Hello, World!";
+    const SIGNATURE: &str = "757107ea0eb2509fc211221cce984b8a37570b6d7586c22c46f4379c8b043e17";
+
+    fn headers(name: &'static str, value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(name, HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    #[test]
+    fn accepts_github_signature() {
+        let headers = headers("X-Hub-Signature-256", &format!("sha256={}", SIGNATURE));
+        assert!(verify_signature(&headers, SECRET, BODY));
+    }
+
+    #[test]
+    fn accepts_gitea_signature() {
+        let headers = headers("X-Gitea-Signature", SIGNATURE);
+        assert!(verify_signature(&headers, SECRET, BODY));
+    }
+
+    #[test]
+    fn rejects_wrong_secret_or_body() {
+        let headers = headers("X-Gitea-Signature", SIGNATURE);
+        assert!(!verify_signature(&headers, b"another secret", BODY));
+        assert!(!verify_signature(&headers, SECRET, b"Hello, World?"));
+    }
+
+    #[test]
+    fn rejects_malformed_signatures() {
+        for (name, value) in [
+            ("X-Hub-Signature-256", SIGNATURE.to_string()),
+            ("X-Hub-Signature-256", format!("sha1={}", SIGNATURE)),
+            ("X-Gitea-Signature", format!("{}00", SIGNATURE)),
+            ("X-Gitea-Signature", "not hex".to_string()),
+        ] {
+            assert!(!verify_signature(&headers(name, &value), SECRET, BODY));
+        }
+    }
+
+    #[test]
+    fn rejects_missing_signature() {
+        assert!(!verify_signature(&HeaderMap::new(), SECRET, BODY));
+    }
+}
@@ -0,0 +1,104 @@
+//! Network access to the SpriteCollab repository: Cloning, fetching and pushing the tracked
+//! branch, with the configured credentials and clone depth.
+//!
+//! - `SCSRV_GIT_BRANCH`: Branch to serve (default: `master`).
+//! - `SCSRV_GIT_TOKEN`: Token for HTTPS remotes. Sent with `SCSRV_GIT_USERNAME` (default:
+//!   `x-access-token`) as the user name.
+//! - `SCSRV_GIT_SSH_KEY`: Path to a private key for SSH remotes, `SCSRV_GIT_SSH_KEY_PASSPHRASE`
+//!   its passphrase.
+//! - `SCSRV_GIT_DEPTH`: If set, clones and fetches are shallow and only include this many commits.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Error;
+use git2::build::RepoBuilder;
+use git2::{Cred, CredentialType, FetchOptions, RemoteCallbacks, Repository};
+use log::{info, warn};
+
+use crate::config::{Config, Dataset};
+
+const REMOTE: &str = "origin";
+/// Credentials are only tried this often, libgit2 keeps asking if they are rejected.
+const MAX_AUTH_ATTEMPTS: u32 = 3;
+
+/// The branch of the SpriteCollab repository that is served.
+pub fn tracked_branch(dataset: &Dataset) -> String {
+    dataset.get(Config::GitBranch)
+}
+
+/// Callbacks that authenticate with the configured credentials.
+pub fn remote_callbacks<'a>(dataset: &Dataset) -> RemoteCallbacks<'a> {
+    let token = dataset.get_or_none(Config::GitToken);
+    let username = dataset.get(Config::GitUsername);
+    let ssh_key = dataset.get_or_none(Config::GitSshKey).map(PathBuf::from);
+    let ssh_passphrase = dataset.get_or_none(Config::GitSshKeyPassphrase);
+    let mut attempts = 0;
+
+    let mut callbacks = RemoteCallbacks::new();
+    callbacks.credentials(move |url, username_from_url, allowed| {
+        attempts += 1;
+        if attempts > MAX_AUTH_ATTEMPTS {
+            return Err(git2::Error::from_str(&format!(
+                "Authentication for {} failed.",
+                url
+            )));
+        }
+        let ssh_user = username_from_url.unwrap_or("git");
+        if allowed.contains(CredentialType::USERNAME) {
+            Cred::username(ssh_user)
+        } else if allowed.contains(CredentialType::SSH_KEY)
+            && let Some(ssh_key) = &ssh_key
+        {
+            Cred::ssh_key(ssh_user, None, ssh_key, ssh_passphrase.as_deref())
+        } else if allowed.contains(CredentialType::USER_PASS_PLAINTEXT)
+            && let Some(token) = &token
+        {
+            Cred::userpass_plaintext(&username, token)
+        } else if allowed.contains(CredentialType::DEFAULT) {
+            Cred::default()
+        } else {
+            Err(git2::Error::from_str(&format!(
+                "No credentials configured for {}.",
+                url
+            )))
+        }
+    });
+    callbacks
+}
+
+/// Options for fetching with the configured credentials and depth.
+pub fn fetch_options<'a>(dataset: &Dataset) -> FetchOptions<'a> {
+    let mut options = FetchOptions::new();
+    options.remote_callbacks(remote_callbacks(dataset));
+    if let Some(depth) = dataset.git_depth() {
+        options.depth(depth);
+    }
+    options
+}
+
+/// Clones the tracked branch of the repository.
+pub fn clone(dataset: &Dataset, path: &Path) -> Result<Repository, Error> {
+    info!("Cloning SpriteCollab repo...");
+    let repo = RepoBuilder::new()
+        .branch(&tracked_branch(dataset))
+        .fetch_options(fetch_options(dataset))
+        .clone(&dataset.get(Config::GitRepo), path)?;
+    info!("Cloning SpriteCollab repo. Done!");
+    Ok(repo)
+}
+
+/// Fetches the tracked branch into `FETCH_HEAD`. If the configured repository URL changed, the
+/// remote is updated first.
+pub fn fetch_tracked_branch(dataset: &Dataset, repo: &Repository) -> Result<(), Error> {
+    let url = dataset.get(Config::GitRepo);
+    if repo.find_remote(REMOTE)?.url() != Some(url.as_str()) {
+        warn!("Repository URL changed, updating the remote to {}.", url);
+        repo.remote_set_url(REMOTE, &url)?;
+    }
+    repo.find_remote(REMOTE)?.fetch(
+        &[tracked_branch(dataset)],
+        Some(&mut fetch_options(dataset)),
+        None,
+    )?;
+    Ok(())
+}
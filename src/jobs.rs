@@ -0,0 +1,264 @@
+//! Background jobs of a dataset, run periodically on the main runtime.
+//!
+//! Every job runs in its own task. The time between runs is randomized by up to [`JITTER`], so
+//! jobs of several datasets or servers don't all run at once, and grows exponentially after
+//! consecutive failures. Jobs can also be triggered to run right away.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::Error;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures::future::join_all;
+use log::{debug, info, warn};
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
+
+use crate::sprite_collab::SpriteCollab;
+
+pub const REFRESH_JOB: &str = "refresh";
+pub const WEBHOOKS_JOB: &str = "webhooks";
+pub const STATS_JOB: &str = "stats";
+pub const WARMUP_JOB: &str = "warmup";
+
+/// Maximum share of the delay between runs that is added or subtracted at random.
+const JITTER: f64 = 0.1;
+/// Upper limit of the delay after failures, unless the interval of the job is longer.
+const MAX_BACKOFF: Duration = Duration::from_secs(60 * 60);
+/// After a debounced job was triggered, wait until it wasn't triggered for this long.
+const DEBOUNCE: Duration = Duration::from_secs(5);
+/// Maximum time a triggered run is delayed by debouncing.
+const MAX_DEBOUNCE: Duration = Duration::from_secs(60);
+
+#[async_trait]
+pub trait Job: Send + Sync + 'static {
+    fn name(&self) -> &'static str;
+
+    /// Time between runs. Jobs without an interval only run when triggered.
+    fn interval(&self) -> Option<Duration>;
+
+    /// Whether bursts of triggers result in a single run.
+    fn debounce(&self) -> bool {
+        false
+    }
+
+    /// Runs the job once. `triggered_by` is `scheduler` for periodic runs.
+    async fn run(&self, triggered_by: &str) -> Result<(), Error>;
+}
+
+#[derive(Clone, Debug)]
+pub struct JobStatus {
+    pub name: &'static str,
+    pub interval: Option<Duration>,
+    pub running: bool,
+    pub last_run: Option<DateTime<Utc>>,
+    pub last_duration: Option<Duration>,
+    pub last_triggered_by: Option<String>,
+    /// The error of the last run, if it failed.
+    pub last_error: Option<String>,
+    /// Number of consecutive failed runs.
+    pub failures: u32,
+    /// When the job runs next, unless it is triggered earlier. Not set while it is running.
+    pub next_run: Option<DateTime<Utc>>,
+}
+
+struct RegisteredJob {
+    status: Arc<Mutex<JobStatus>>,
+    trigger: mpsc::UnboundedSender<String>,
+}
+
+pub struct Jobs {
+    jobs: Mutex<Vec<RegisteredJob>>,
+    tasks: Mutex<Vec<JoinHandle<()>>>,
+    shutdown: watch::Sender<bool>,
+}
+
+impl Jobs {
+    pub fn new() -> Self {
+        Self {
+            jobs: Mutex::new(Vec::new()),
+            tasks: Mutex::new(Vec::new()),
+            shutdown: watch::Sender::new(false),
+        }
+    }
+
+    /// Starts running the job periodically, if it has an interval. The first run is after one
+    /// interval.
+    pub fn spawn<J: Job>(&self, job: J) {
+        let status = Arc::new(Mutex::new(JobStatus {
+            name: job.name(),
+            interval: job.interval(),
+            running: false,
+            last_run: None,
+            last_duration: None,
+            last_triggered_by: None,
+            last_error: None,
+            failures: 0,
+            next_run: None,
+        }));
+        let (trigger, receiver) = mpsc::unbounded_channel();
+        let task = tokio::spawn(run_job(
+            job,
+            status.clone(),
+            receiver,
+            self.shutdown.subscribe(),
+        ));
+        self.jobs
+            .lock()
+            .unwrap()
+            .push(RegisteredJob { status, trigger });
+        self.tasks.lock().unwrap().push(task);
+    }
+
+    /// Runs the job right away, or as soon as its current run finished.
+    pub fn trigger(&self, name: &str, triggered_by: &str) {
+        let jobs = self.jobs.lock().unwrap();
+        match jobs
+            .iter()
+            .find(|job| job.status.lock().unwrap().name == name)
+        {
+            Some(job) => {
+                job.trigger.send(triggered_by.to_string()).ok();
+            }
+            None => debug!("Job {} is not registered, not triggering it.", name),
+        }
+    }
+
+    pub fn status(&self) -> Vec<JobStatus> {
+        self.jobs
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|job| job.status.lock().unwrap().clone())
+            .collect()
+    }
+
+    /// Whether the jobs are being stopped. Long running jobs should finish early.
+    pub fn is_stopping(&self) -> bool {
+        *self.shutdown.borrow()
+    }
+
+    /// Stops all jobs. Waits for running jobs to finish.
+    pub async fn shutdown(&self) {
+        self.shutdown.send_replace(true);
+        let tasks: Vec<_> = self.tasks.lock().unwrap().drain(..).collect();
+        join_all(tasks).await;
+    }
+}
+
+async fn run_job<J: Job>(
+    job: J,
+    status: Arc<Mutex<JobStatus>>,
+    mut trigger: mpsc::UnboundedReceiver<String>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    info!("Starting job {}.", job.name());
+    let mut failures = 0;
+    loop {
+        let delay = job
+            .interval()
+            .map(|interval| jitter(backoff(interval, failures)));
+        status.lock().unwrap().next_run = delay
+            .and_then(|delay| chrono::Duration::from_std(delay).ok())
+            .map(|delay| Utc::now() + delay);
+        let scheduled = async {
+            match delay {
+                Some(delay) => tokio::time::sleep(delay).await,
+                None => std::future::pending().await,
+            }
+        };
+        let triggered_by = tokio::select! {
+            Some(triggered_by) = trigger.recv() => {
+                debug!("Job {} triggered by {}.", job.name(), triggered_by);
+                if job.debounce() && !debounce(&mut trigger, &mut shutdown).await {
+                    break;
+                }
+                triggered_by
+            }
+            _ = scheduled => "scheduler".to_string(),
+            _ = shutdown.changed() => break,
+        };
+
+        {
+            let mut status = status.lock().unwrap();
+            status.running = true;
+            status.next_run = None;
+            status.last_run = Some(Utc::now());
+            status.last_triggered_by = Some(triggered_by.clone());
+        }
+        let start = Instant::now();
+        let result = job.run(&triggered_by).await;
+        match &result {
+            Ok(()) => failures = 0,
+            Err(e) => {
+                failures += 1;
+                warn!(
+                    "Job {} failed ({} time(s) in a row): {}",
+                    job.name(),
+                    failures,
+                    e
+                );
+            }
+        }
+        let mut status = status.lock().unwrap();
+        status.running = false;
+        status.last_duration = Some(start.elapsed());
+        status.last_error = result.err().map(|e| e.to_string());
+        status.failures = failures;
+    }
+    info!("Stopped job {}.", job.name());
+}
+
+/// Waits until the job wasn't triggered for [`DEBOUNCE`]. Returns false if the job should stop
+/// instead.
+async fn debounce(
+    trigger: &mut mpsc::UnboundedReceiver<String>,
+    shutdown: &mut watch::Receiver<bool>,
+) -> bool {
+    let deadline = Instant::now() + MAX_DEBOUNCE;
+    while Instant::now() < deadline {
+        tokio::select! {
+            Some(_) = trigger.recv() => {}
+            _ = tokio::time::sleep(DEBOUNCE) => break,
+            _ = shutdown.changed() => return false,
+        }
+    }
+    true
+}
+
+/// The delay before the next run after `failures` consecutive failed runs.
+fn backoff(interval: Duration, failures: u32) -> Duration {
+    if failures == 0 {
+        return interval;
+    }
+    interval
+        .saturating_mul(1 << failures.min(16))
+        .min(MAX_BACKOFF.max(interval))
+}
+
+fn jitter(delay: Duration) -> Duration {
+    delay.mul_f64(1.0 + JITTER * (fastrand::f64() * 2.0 - 1.0))
+}
+
+/// Refreshes the data of the dataset.
+pub struct RefreshJob(pub Arc<SpriteCollab>);
+
+#[async_trait]
+impl Job for RefreshJob {
+    fn name(&self) -> &'static str {
+        REFRESH_JOB
+    }
+
+    fn interval(&self) -> Option<Duration> {
+        Some(self.0.dataset().refresh_interval())
+    }
+
+    fn debounce(&self) -> bool {
+        true
+    }
+
+    async fn run(&self, triggered_by: &str) -> Result<(), Error> {
+        self.0.refresh_now(triggered_by).await.map(|_| ())
+    }
+}
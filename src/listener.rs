@@ -0,0 +1,263 @@
+//! The sockets the server listens on: TCP, optionally with TLS, and optionally a Unix domain
+//! socket.
+//!
+//! - `SCSRV_TLS_CERT` and `SCSRV_TLS_KEY`: PEM files with the certificate chain and private key.
+//!   If set, the TCP listener serves HTTPS. The files are reloaded on `SIGHUP`.
+//! - `SCSRV_UNIX_SOCKET`: Path of a Unix domain socket to listen on in addition to TCP. The socket
+//!   is removed again on shutdown.
+
+use std::fs::{remove_file, symlink_metadata};
+use std::io;
+use std::net::SocketAddr;
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, RwLock};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use anyhow::{Error, anyhow};
+use log::{error, info, warn};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream, UnixListener, UnixStream};
+use tokio::signal::unix::{SignalKind, signal};
+use tokio_rustls::TlsAcceptor;
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::server::TlsStream;
+
+use crate::config::Config;
+
+/// Clients that don't finish the TLS handshake in this time are disconnected.
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct Listeners {
+    tcp: TcpListener,
+    tls: Option<Arc<Tls>>,
+    unix: Option<(UnixListener, PathBuf)>,
+}
+
+impl Listeners {
+    /// Binds all configured listeners.
+    pub async fn bind() -> Result<Self, Error> {
+        let addr: SocketAddr = Config::bind_address().into();
+        let tcp = TcpListener::bind(addr).await?;
+        let tls = match (Config::TlsCert.get_or_none(), Config::TlsKey.get_or_none()) {
+            (Some(cert), Some(key)) => {
+                let tls = Arc::new(Tls::load(PathBuf::from(cert), PathBuf::from(key))?);
+                tokio::spawn(tls.clone().reload_on_sighup());
+                Some(tls)
+            }
+            _ => None,
+        };
+        info!(
+            "Listening on {}://{}.",
+            if tls.is_some() { "https" } else { "http" },
+            addr
+        );
+        let unix = match Config::UnixSocket.get_or_none() {
+            Some(path) => {
+                let path = PathBuf::from(path);
+                remove_stale_socket(&path)?;
+                let listener = UnixListener::bind(&path)?;
+                info!("Listening on {}.", path.display());
+                Some((listener, path))
+            }
+            None => None,
+        };
+        Ok(Self { tcp, tls, unix })
+    }
+
+    /// Waits for the next connection on any of the listeners. The connection still needs to be
+    /// established with [`Accepted::establish`].
+    pub async fn accept(&self) -> io::Result<Accepted> {
+        let unix = async {
+            match &self.unix {
+                Some((unix, _)) => unix.accept().await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            conn = self.tcp.accept() => {
+                let (stream, _) = conn?;
+                Ok(match &self.tls {
+                    Some(tls) => Accepted::Tls(stream, tls.acceptor()),
+                    None => Accepted::Tcp(stream),
+                })
+            }
+            conn = unix => {
+                let (stream, _) = conn?;
+                Ok(Accepted::Unix(stream))
+            }
+        }
+    }
+}
+
+impl Drop for Listeners {
+    fn drop(&mut self) {
+        if let Some((_, path)) = &self.unix
+            && let Err(e) = remove_file(path)
+        {
+            warn!("Failed removing {}: {}", path.display(), e);
+        }
+    }
+}
+
+/// Removes a socket left over from a previous run. Fails if something else is at the path or
+/// another process still listens on the socket.
+fn remove_stale_socket(path: &Path) -> Result<(), Error> {
+    match symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => {
+            if std::os::unix::net::UnixStream::connect(path).is_ok() {
+                return Err(anyhow!(
+                    "{} is used by another running process",
+                    path.display()
+                ));
+            }
+            remove_file(path)?;
+            Ok(())
+        }
+        Ok(_) => Err(anyhow!("{} exists and is not a socket", path.display())),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub enum Accepted {
+    Tcp(TcpStream),
+    Tls(TcpStream, TlsAcceptor),
+    Unix(UnixStream),
+}
+
+impl Accepted {
+    /// Does the TLS handshake, if needed.
+    pub async fn establish(self) -> io::Result<Connection> {
+        Ok(match self {
+            Accepted::Tcp(stream) => Connection::Tcp(stream),
+            Accepted::Tls(stream, acceptor) => {
+                let stream = tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(stream))
+                    .await
+                    .map_err(|_| {
+                        io::Error::new(io::ErrorKind::TimedOut, "TLS handshake timed out")
+                    })??;
+                Connection::Tls(Box::new(stream))
+            }
+            Accepted::Unix(stream) => Connection::Unix(stream),
+        })
+    }
+}
+
+/// A connection of any of the listeners.
+pub enum Connection {
+    Tcp(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>),
+    Unix(UnixStream),
+}
+
+impl AsyncRead for Connection {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Connection::Tcp(s) => Pin::new(s).poll_read(cx, buf),
+            Connection::Tls(s) => Pin::new(s).poll_read(cx, buf),
+            Connection::Unix(s) => Pin::new(s).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Connection {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Connection::Tcp(s) => Pin::new(s).poll_write(cx, buf),
+            Connection::Tls(s) => Pin::new(s).poll_write(cx, buf),
+            Connection::Unix(s) => Pin::new(s).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Connection::Tcp(s) => Pin::new(s).poll_flush(cx),
+            Connection::Tls(s) => Pin::new(s).poll_flush(cx),
+            Connection::Unix(s) => Pin::new(s).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Connection::Tcp(s) => Pin::new(s).poll_shutdown(cx),
+            Connection::Tls(s) => Pin::new(s).poll_shutdown(cx),
+            Connection::Unix(s) => Pin::new(s).poll_shutdown(cx),
+        }
+    }
+}
+
+/// The TLS certificate and key, which can be reloaded while the server is running.
+struct Tls {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    acceptor: RwLock<TlsAcceptor>,
+}
+
+impl Tls {
+    fn load(cert_path: PathBuf, key_path: PathBuf) -> Result<Self, Error> {
+        let acceptor = RwLock::new(make_acceptor(&cert_path, &key_path)?);
+        Ok(Self {
+            cert_path,
+            key_path,
+            acceptor,
+        })
+    }
+
+    fn acceptor(&self) -> TlsAcceptor {
+        self.acceptor.read().unwrap().clone()
+    }
+
+    /// Reloads the certificate and key whenever the process receives `SIGHUP`. If they can't be
+    /// read, the previous ones are kept.
+    async fn reload_on_sighup(self: Arc<Self>) {
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(v) => v,
+            Err(e) => {
+                error!(
+                    "Failed listening for SIGHUP, TLS certificates can't be reloaded: {}",
+                    e
+                );
+                return;
+            }
+        };
+        while hangup.recv().await.is_some() {
+            match make_acceptor(&self.cert_path, &self.key_path) {
+                Ok(acceptor) => {
+                    *self.acceptor.write().unwrap() = acceptor;
+                    info!("Reloaded the TLS certificate.");
+                }
+                Err(e) => error!(
+                    "Failed reloading the TLS certificate, keeping the old one: {}",
+                    e
+                ),
+            }
+        }
+    }
+}
+
+fn make_acceptor(cert_path: &Path, key_path: &Path) -> Result<TlsAcceptor, Error> {
+    let certs = CertificateDer::pem_file_iter(cert_path)
+        .map_err(|e| anyhow!("Failed reading {}: {}", cert_path.display(), e))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| anyhow!("Failed reading {}: {}", cert_path.display(), e))?;
+    let key = PrivateKeyDer::from_pem_file(key_path)
+        .map_err(|e| anyhow!("Failed reading {}: {}", key_path.display(), e))?;
+    let mut config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?;
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
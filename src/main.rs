@@ -3,77 +3,128 @@
 //! Access `/ for GraphiQL.
 #![forbid(unused_must_use)]
 
-use std::net::SocketAddr;
 use std::pin::pin;
-use std::sync::Mutex;
+use std::process::exit;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::Duration;
 use std::{convert::Infallible, sync::Arc};
 
 use http_body_util::Empty;
-use hyper::body::Bytes;
+use hyper::body::{Bytes, Incoming};
+use hyper::header::AUTHORIZATION;
 use hyper::http::HeaderValue;
-use hyper::{Method, Response, StatusCode, service::service_fn};
+use hyper::{Method, Request, Response, StatusCode, service::service_fn};
 use hyper_util::rt::{TokioExecutor, TokioIo};
 use hyper_util::server::conn::auto;
-use hyper_util::server::graceful::GracefulShutdown;
-use juniper::{EmptyMutation, EmptySubscription, RootNode};
-use log::{info, warn};
-use tokio::net::TcpListener;
+use hyper_util::server::graceful::{GracefulShutdown, Watcher};
+use juniper::{EmptySubscription, RootNode};
+use log::{debug, error, info, warn};
+use tokio::signal::unix::{SignalKind, signal};
 
-use crate::assets::{make_box_body, match_and_process_assets_path};
+use crate::assets::pinned::{PINNED_PREFIX, match_and_process_pinned_path};
+use crate::assets::{AssetBody, make_box_body, match_and_process_assets_path};
+use crate::auth::Users;
 use crate::config::Config;
-use crate::scheduler::DataRefreshScheduler;
-use crate::schema::{Context, Query};
+use crate::datasets::Datasets;
+use crate::git_hook::process_git_hook;
+use crate::listener::{Accepted, Listeners};
+use crate::schema::{Context, Mutation, Query};
 use crate::sprite_collab::SpriteCollab;
+use crate::submissions::match_and_process_validation_path;
 
 mod assets;
+mod auth;
 mod cache;
 mod config;
+mod data_dir;
+mod data_root;
 mod datafiles;
-mod scheduler;
+mod datasets;
+mod disk_cache;
+mod git_hook;
+mod git_remote;
+mod jobs;
+mod listener;
+mod refresh_history;
 mod schema;
 mod search;
 mod sprite_collab;
+mod stats;
+mod submissions;
+mod trust;
+mod warmup;
+mod webhooks;
 
-const PORT: u16 = 3000;
+/// State shared by all connections.
+struct App {
+    datasets: Datasets,
+    users: Users,
+    /// Whether the server is ready to serve requests. Reported at `/ready`.
+    ready: AtomicBool,
+    root_node: Arc<RootNode<Query, Mutation, EmptySubscription<Context>>>,
+}
 
 #[tokio::main]
 async fn main() {
     Config::init();
-    Config::check();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args == ["config", "check"] {
+        exit(if Config::print_check() { 0 } else { 1 });
+    }
     pretty_env_logger::init_timed();
+    if !Config::check() {
+        error!("Invalid configuration, not starting.");
+        exit(1);
+    }
 
-    let sprite_collab = SpriteCollab::new(Config::redis_config()).await;
-
-    let scheduler = Arc::new(Mutex::new(DataRefreshScheduler::new(sprite_collab.clone())));
-
-    let addr: SocketAddr = ([0, 0, 0, 0], PORT).into();
-
-    let ctx = Arc::new(Context::new(sprite_collab.clone()));
-    let root_node = Arc::new(RootNode::new(
-        Query,
-        EmptyMutation::<Context>::new(),
-        EmptySubscription::<Context>::new(),
-    ));
+    let datasets = match Datasets::load().await {
+        Ok(v) => v,
+        Err(e) => {
+            error!("Failed loading the data, not starting: {}", e);
+            exit(1);
+        }
+    };
+    let app = Arc::new(App {
+        datasets,
+        users: Users::load(),
+        ready: AtomicBool::new(true),
+        root_node: Arc::new(RootNode::new(
+            Query,
+            Mutation,
+            EmptySubscription::<Context>::new(),
+        )),
+    });
 
-    let listener = TcpListener::bind(addr)
-        .await
-        .expect("expected to listen on address");
+    let listeners = match Listeners::bind().await {
+        Ok(v) => v,
+        Err(e) => {
+            error!("Failed listening, not starting: {}", e);
+            exit(1);
+        }
+    };
     let graceful = GracefulShutdown::new();
     let server = Arc::new(auto::Builder::new(TokioExecutor::new()));
 
-    let mut ctrl_c = pin!(tokio::signal::ctrl_c());
+    // Once a shutdown signal is received, the server reports that it is not ready anymore, but
+    // still accepts connections for the shutdown delay.
+    let mut stop = pin!(async {
+        shutdown_signal().await;
+        app.ready.store(false, Ordering::SeqCst);
+        let delay = Config::shutdown_delay();
+        if !delay.is_zero() {
+            info!(
+                "Not ready, accepting connections for {} more seconds",
+                delay.as_secs()
+            );
+            tokio::time::sleep(delay).await;
+        }
+    });
 
     info!("GraphQL server started.");
     loop {
-        let root_node = root_node.clone();
-        let ctx = ctx.clone();
-        let sprite_collab = sprite_collab.clone();
-        let server = server.clone();
-
         tokio::select! {
-            conn = listener.accept() => {
-                let (stream, _) = match conn {
+            conn = listeners.accept() => {
+                let accepted = match conn {
                     Ok(v) => v,
                     Err(e) => {
                         warn!("failed to accept connection: {}", e);
@@ -81,83 +132,157 @@ async fn main() {
                         continue;
                     }
                 };
-                let io = TokioIo::new(stream);
-
-                tokio::spawn(async move {
-                    if let Err(e) = server
-                        .serve_connection(
-                            io,
-                            service_fn(move |req| {
-                                let root_node = root_node.clone();
-                                let ctx = ctx.clone();
-                                let sprite_collab = sprite_collab.clone();
-                                async move {
-                                    Ok::<_, Infallible>(match (req.method(), req.uri().path()) {
-                                        (&Method::OPTIONS, _) => make_http_options_response().map(make_box_body),
-                                        (&Method::GET, "/") => juniper_hyper::graphiql("/graphql", None).await.map(make_box_body),
-                                        (&Method::GET, "/graphql") | (&Method::POST, "/graphql") => {
-                                            let mut response = juniper_hyper::graphql(root_node, ctx, req).await;
-                                            response.headers_mut().insert(
-                                                "Access-Control-Allow-Origin",
-                                                HeaderValue::try_from("*").unwrap(),
-                                            );
-                                            if response.status() != StatusCode::OK {
-                                                let body = response.body();
-                                                warn!(
-                                                    "GraphQL request failed: {}",
-                                                    body
-                                                );
-                                            }
-                                            response.map(make_box_body)
-                                        }
-                                        (method, path) =>
-                                            match_and_process_assets_path(
-                                                method,
-                                                path,
-                                                sprite_collab.clone(),
-                                            )
-                                                .await
-                                                .unwrap_or_else(|| {
-                                                    let mut response = Response::new(String::from(
-                                                        "<html><body><img src=\"https://http.cat/404\"></body></html>"
-                                                    ));
-                                                    *response.status_mut() = StatusCode::NOT_FOUND;
-                                                    response.headers_mut().insert(
-                                                        "content-type",
-                                                        HeaderValue::from_str("text/html; charset=UTF-8").unwrap(),
-                                                    );
-                                                    response.map(make_box_body)
-                                            })
-                                    })
-                                }
-                            }),
-                        )
-                        .await
-                    {
-                        warn!("Error serving connection: {e}");
-                    }
-                });
+                tokio::spawn(serve(accepted, server.clone(), graceful.watcher(), app.clone()));
             }
 
-            _ = ctrl_c.as_mut() => {
-                drop(listener);
-                info!("Ctrl-C received, starting shutdown");
+            _ = stop.as_mut() => {
+                drop(listeners);
                 break;
             }
         }
     }
-    scheduler.lock().unwrap().shutdown();
-
+    let drain_timeout = Config::drain_timeout();
+    info!(
+        "Waiting for {} open connections and the running jobs to finish",
+        graceful.count()
+    );
     tokio::select! {
-        _ = graceful.shutdown() => {
+        _ = async { tokio::join!(app.datasets.shutdown(), graceful.shutdown()) } => {
             info!("Gracefully shutdown!");
         },
-        _ = tokio::time::sleep(Duration::from_secs(10)) => {
-            warn!("Waited 10 seconds for graceful shutdown, aborting...");
+        _ = tokio::time::sleep(drain_timeout) => {
+            warn!(
+                "Waited {} seconds for graceful shutdown, aborting...",
+                drain_timeout.as_secs()
+            );
+            // Dropping the runtime would wait for blocking work like a running clone.
+            exit(1);
         }
     }
 }
 
+/// Waits for Ctrl-C or SIGTERM.
+async fn shutdown_signal() {
+    let mut terminate = signal(SignalKind::terminate()).expect("expected to listen for SIGTERM");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => info!("Ctrl-C received, starting shutdown"),
+        _ = terminate.recv() => info!("SIGTERM received, starting shutdown"),
+    }
+}
+
+/// Serves a connection accepted by any of the listeners.
+async fn serve(
+    accepted: Accepted,
+    server: Arc<auto::Builder<TokioExecutor>>,
+    watcher: Watcher,
+    app: Arc<App>,
+) {
+    let conn = match accepted.establish().await {
+        Ok(v) => v,
+        Err(e) => {
+            debug!("Failed establishing connection: {e}");
+            return;
+        }
+    };
+    let service = service_fn(move |req| {
+        let app = app.clone();
+        async move { Ok::<_, Infallible>(handle_request(req, &app).await) }
+    });
+    let conn = server.serve_connection(TokioIo::new(conn), service);
+    if let Err(e) = watcher.watch(conn).await {
+        warn!("Error serving connection: {e}");
+    }
+}
+
+async fn handle_request(req: Request<Incoming>, app: &App) -> Response<AssetBody> {
+    if req.uri().path() == "/ready" {
+        return make_ready_response(app.ready.load(Ordering::SeqCst)).map(make_box_body);
+    }
+    let (dataset, req) = app.datasets.route(req);
+    let sprite_collab = &dataset.sprite_collab;
+    match (req.method(), req.uri().path()) {
+        (&Method::OPTIONS, _) => make_http_options_response().map(make_box_body),
+        (&Method::GET, "/") => {
+            juniper_hyper::graphiql(&sprite_collab.dataset().graphql_path(), None)
+                .await
+                .map(make_box_body)
+        }
+        (&Method::GET, "/graphql") | (&Method::POST, "/graphql") => {
+            let ctx = Arc::new(Context::new(
+                sprite_collab.clone(),
+                app.users.authenticate(req.headers().get(AUTHORIZATION)),
+                wants_pinned_urls(&req),
+            ));
+            let mut response = juniper_hyper::graphql(app.root_node.clone(), ctx, req).await;
+            response
+                .headers_mut()
+                .insert("Access-Control-Allow-Origin", Config::cors_origin());
+            if response.status() != StatusCode::OK {
+                let body = response.body();
+                warn!("GraphQL request failed: {}", body);
+            }
+            response.map(make_box_body)
+        }
+        (&Method::POST, "/hooks/git") => process_git_hook(req, sprite_collab)
+            .await
+            .unwrap_or_else(make_not_found_response),
+        (&Method::GET, path) if path.starts_with(PINNED_PREFIX) => {
+            match_and_process_pinned_path(path, sprite_collab.clone())
+                .await
+                .unwrap_or_else(make_not_found_response)
+        }
+        (&Method::POST, path) if path.starts_with("/validate/") => {
+            match_and_process_validation_path(req, sprite_collab.clone())
+                .await
+                .unwrap_or_else(make_not_found_response)
+        }
+        _ => match_and_process_assets_path(req, sprite_collab.clone())
+            .await
+            .unwrap_or_else(make_not_found_response),
+    }
+}
+
+/// Whether the GraphQL client asked for commit-pinned asset URLs, via the
+/// `X-SpriteCollab-Pinned-Urls` header or the `pinned_urls` query parameter.
+fn wants_pinned_urls<B>(req: &Request<B>) -> bool {
+    let is_true = |v: &str| matches!(v, "1" | "true" | "yes");
+    req.headers()
+        .get("X-SpriteCollab-Pinned-Urls")
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(is_true)
+        || req.uri().query().is_some_and(|query| {
+            query
+                .split('&')
+                .filter_map(|pair| pair.split_once('='))
+                .any(|(k, v)| k == "pinned_urls" && is_true(v))
+        })
+}
+
+/// Make a HTTP 404 response.
+fn make_not_found_response() -> Response<AssetBody> {
+    let mut response = Response::new(String::from(
+        "<html><body><img src=\"https://http.cat/404\"></body></html>",
+    ));
+    *response.status_mut() = StatusCode::NOT_FOUND;
+    response.headers_mut().insert(
+        "content-type",
+        HeaderValue::from_str("text/html; charset=UTF-8").unwrap(),
+    );
+    response.map(make_box_body)
+}
+
+/// Make the response of the readiness check, HTTP 503 while shutting down.
+fn make_ready_response(ready: bool) -> Response<String> {
+    let (status, body) = if ready {
+        (StatusCode::OK, "ready")
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, "shutting down")
+    };
+    let mut response = Response::new(String::from(body));
+    *response.status_mut() = status;
+    response
+}
+
 /// Make a HTTP OPTIONS response.
 fn make_http_options_response() -> Response<Empty<Bytes>> {
     Response::builder()
@@ -167,7 +292,7 @@ fn make_http_options_response() -> Response<Empty<Bytes>> {
             "Access-Control-Allow-Headers",
             "Content-Type, Authorization, Accept",
         )
-        .header("Access-Control-Allow-Origin", "*")
+        .header("Access-Control-Allow-Origin", Config::cors_origin())
         .header("Access-Control-Max-Age", "86400")
         .body(Empty::new())
         .unwrap()
@@ -0,0 +1,214 @@
+//! Bookkeeping for data refreshes: The history of recent refreshes, the commit pin set by
+//! admins and the quarantine of commits whose data could not be read. All of them are persisted
+//! in the workdir.
+
+use std::collections::VecDeque;
+use std::fs::{read, remove_file, write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::config::Dataset;
+
+const PIN_FILE: &str = "pin.json";
+const HISTORY_FILE: &str = "refresh_history.json";
+const QUARANTINE_FILE: &str = "quarantine.json";
+/// Number of refreshes kept in the history.
+const HISTORY_SIZE: usize = 100;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RefreshRecord {
+    pub date: DateTime<Utc>,
+    /// What was done, eg. `refresh`, `pin` or `rollback`.
+    pub action: String,
+    /// Who triggered the refresh: A user name, `scheduler` or `git hook`.
+    pub triggered_by: String,
+    /// The commit that is served after the refresh, if it succeeded.
+    pub commit: Option<String>,
+    pub duration: Duration,
+    /// The error, if the refresh failed.
+    pub error: Option<String>,
+}
+
+/// The most recent refreshes, so admins can see what was pinned or rolled back and why.
+pub struct RefreshHistory {
+    path: PathBuf,
+    records: Mutex<VecDeque<RefreshRecord>>,
+}
+
+impl RefreshHistory {
+    pub fn load(dataset: &Dataset) -> Self {
+        let path = dataset.workdir().join(HISTORY_FILE);
+        let records = if path.exists() {
+            read(&path)
+                .map_err(anyhow::Error::from)
+                .and_then(|content| Ok(serde_json::from_slice(&content)?))
+                .unwrap_or_else(|e| {
+                    error!("Failed reading the refresh history, discarding it: {}", e);
+                    VecDeque::new()
+                })
+        } else {
+            VecDeque::new()
+        };
+        Self {
+            path,
+            records: Mutex::new(records),
+        }
+    }
+
+    pub fn record(&self, record: RefreshRecord) {
+        let mut records = self.records.lock().unwrap();
+        while records.len() >= HISTORY_SIZE {
+            records.pop_front();
+        }
+        records.push_back(record);
+        self.persist(&records);
+    }
+
+    /// Returns the recorded refreshes, newest first.
+    pub fn list(&self) -> Vec<RefreshRecord> {
+        self.records.lock().unwrap().iter().rev().cloned().collect()
+    }
+
+    fn persist(&self, records: &VecDeque<RefreshRecord>) {
+        let result = serde_json::to_vec_pretty(records)
+            .map_err(anyhow::Error::from)
+            .and_then(|content| Ok(write(&self.path, content)?));
+        if let Err(e) = result {
+            error!("Failed writing the refresh history: {}", e);
+        }
+    }
+}
+
+/// A commit the served data is pinned to. Persisted in the workdir.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Pin {
+    pub commit: String,
+    pub pinned_by: String,
+    pub date: DateTime<Utc>,
+}
+
+impl Pin {
+    pub fn load(dataset: &Dataset) -> Option<Self> {
+        let path = Self::path(dataset);
+        if !path.exists() {
+            return None;
+        }
+        match read(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|content| Ok(serde_json::from_slice(&content)?))
+        {
+            Ok(pin) => Some(pin),
+            Err(e) => {
+                error!("Failed reading the pinned commit, ignoring it: {}", e);
+                None
+            }
+        }
+    }
+
+    pub fn save(&self, dataset: &Dataset) -> Result<(), anyhow::Error> {
+        write(Self::path(dataset), serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn remove(dataset: &Dataset) -> Result<(), anyhow::Error> {
+        let path = Self::path(dataset);
+        if path.exists() {
+            remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    fn path(dataset: &Dataset) -> PathBuf {
+        dataset.workdir().join(PIN_FILE)
+    }
+}
+
+/// A commit whose data failed to load.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct QuarantinedCommit {
+    pub commit: String,
+    pub date: DateTime<Utc>,
+    pub error: String,
+}
+
+/// Commits whose data failed to load. They are not checked out again by refreshes, so the last
+/// good commit keeps being served until a newer commit arrives. Persisted in the workdir.
+pub struct Quarantine {
+    path: PathBuf,
+    commits: Mutex<Vec<QuarantinedCommit>>,
+}
+
+impl Quarantine {
+    pub fn load(dataset: &Dataset) -> Self {
+        let path = dataset.workdir().join(QUARANTINE_FILE);
+        let commits = if path.exists() {
+            read(&path)
+                .map_err(anyhow::Error::from)
+                .and_then(|content| Ok(serde_json::from_slice(&content)?))
+                .unwrap_or_else(|e| {
+                    error!(
+                        "Failed reading the quarantined commits, discarding them: {}",
+                        e
+                    );
+                    Vec::new()
+                })
+        } else {
+            Vec::new()
+        };
+        Self {
+            path,
+            commits: Mutex::new(commits),
+        }
+    }
+
+    pub fn contains(&self, commit: &str) -> bool {
+        self.commits
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|c| c.commit == commit)
+    }
+
+    pub fn add(&self, commit: &str, error: &str) {
+        let mut commits = self.commits.lock().unwrap();
+        if commits.iter().any(|c| c.commit == commit) {
+            return;
+        }
+        warn!("Quarantining commit {}: {}", commit, error);
+        commits.push(QuarantinedCommit {
+            commit: commit.to_string(),
+            date: Utc::now(),
+            error: error.to_string(),
+        });
+        self.persist(&commits);
+    }
+
+    /// Removes a commit from the quarantine, eg. because its data could be loaded after all.
+    pub fn remove(&self, commit: &str) {
+        let mut commits = self.commits.lock().unwrap();
+        let len = commits.len();
+        commits.retain(|c| c.commit != commit);
+        if commits.len() != len {
+            info!("Commit {} is no longer quarantined.", commit);
+            self.persist(&commits);
+        }
+    }
+
+    pub fn list(&self) -> Vec<QuarantinedCommit> {
+        self.commits.lock().unwrap().clone()
+    }
+
+    fn persist(&self, commits: &[QuarantinedCommit]) {
+        let result = serde_json::to_vec_pretty(commits)
+            .map_err(anyhow::Error::from)
+            .and_then(|content| Ok(write(&self.path, content)?));
+        if let Err(e) = result {
+            error!("Failed writing the quarantined commits: {}", e);
+        }
+    }
+}
@@ -3,11 +3,15 @@ use std::env;
 use std::fmt::Debug;
 use std::future::Future;
 use std::iter::once;
+use std::path::Path;
 use std::sync::Arc;
 
 use async_trait::async_trait;
+use base64::Engine;
+use base64::prelude::BASE64_STANDARD;
 use chrono::{DateTime, Utc};
 use fred::types::Key;
+use hyper::body::Bytes;
 use itertools::Itertools;
 use juniper::{
     FieldError, FieldResult, GraphQLEnum, GraphQLObject, GraphQLUnion, graphql_object,
@@ -22,23 +26,36 @@ use crate::assets::fs_check::{
     AssetCategory, get_existing_portrait_file, get_existing_sprite_file, get_local_credits_file,
     iter_existing_portrait_files, iter_existing_sprite_files,
 };
-use crate::assets::url::{AssetType, get_url};
+use crate::assets::palette;
+use crate::assets::url::{AssetType, UrlBase, get_url};
+use crate::assets::util::{force_non_shiny_group, force_shiny_group, join_monster_and_form};
+use crate::auth::{Role, User};
 use crate::cache::{CacheBehaviour, ScCache};
 use crate::config::Config as SystemConfig;
+use crate::data_root::DataRoot;
 use crate::datafiles::anim_data_xml::AnimDataXml;
 use crate::datafiles::credit_names::CreditNamesRow;
 use crate::datafiles::group_id::GroupId;
 use crate::datafiles::local_credits_file::LocalCreditRow;
 use crate::datafiles::parse_credit_id;
 use crate::datafiles::sprite_config::SpriteConfig;
+use crate::datafiles::tracker;
 use crate::datafiles::tracker::{
     FormMatch, Group, MapImpl, MonsterFormCollector, fuzzy_find_tracker,
 };
+use crate::jobs;
+use crate::refresh_history;
 use crate::sprite_collab::SpriteCollab;
+use crate::stats;
+use crate::submissions::apply::apply_submission;
+use crate::submissions::store;
+use crate::submissions::validate_submission;
 
 /// Maximum length for search query strings
 const MAX_QUERY_LEN: usize = 75;
-const API_VERSION: &str = "1.6";
+/// License of submissions that don't specify one.
+const DEFAULT_SUBMISSION_LICENSE: &str = "CC_BY-NC_4";
+const API_VERSION: &str = "1.14";
 
 #[derive(GraphQLEnum)]
 #[graphql(description = "A known license from a common list of options.")]
@@ -352,19 +369,14 @@ impl MonsterFormPortraits {
 
     #[graphql(description = "URL to a SpriteBot format sheet of all portraits.")]
     fn sheet_url(&self, context: &Context) -> String {
-        get_url(
-            AssetType::PortraitSheet,
-            &context.this_server_url,
-            self.1,
-            &self.2,
-        )
+        get_url(AssetType::PortraitSheet, &context.url_base, self.1, &self.2)
     }
 
     #[graphql(description = "URL to a SpriteBot format recolor sheet.")]
     fn recolor_sheet_url(&self, context: &Context) -> String {
         get_url(
             AssetType::PortraitRecolorSheet,
-            &context.this_server_url,
+            &context.url_base,
             self.1,
             &self.2,
         )
@@ -372,28 +384,34 @@ impl MonsterFormPortraits {
 
     #[graphql(description = "A list of all existing portraits for the emotions.")]
     async fn emotions(&self, context: &Context) -> FieldResult<Vec<Portrait>> {
-        Ok(
-            iter_existing_portrait_files(&context, &self.0.portrait_files, false, self.1, &self.2)
-                .await?
-                .into_iter()
-                .map(|(emotion, locked)| Portrait {
-                    emotion: emotion.clone(),
-                    locked,
-                    url: get_url(
-                        AssetType::Portrait(&emotion),
-                        &context.this_server_url,
-                        self.1,
-                        &self.2,
-                    ),
-                })
-                .collect(),
+        Ok(iter_existing_portrait_files(
+            &context,
+            &context.root,
+            &self.0.portrait_files,
+            false,
+            self.1,
+            &self.2,
         )
+        .await?
+        .into_iter()
+        .map(|(emotion, locked)| Portrait {
+            emotion: emotion.clone(),
+            locked,
+            url: get_url(
+                AssetType::Portrait(&emotion),
+                &context.url_base,
+                self.1,
+                &self.2,
+            ),
+        })
+        .collect())
     }
 
     #[graphql(description = "A single portrait for a given emotion.")]
     async fn emotion(&self, context: &Context, emotion: String) -> FieldResult<Option<Portrait>> {
         Ok(get_existing_portrait_file(
             &context,
+            &context.root,
             &self.0.portrait_files,
             &emotion,
             false,
@@ -406,7 +424,7 @@ impl MonsterFormPortraits {
             locked,
             url: get_url(
                 AssetType::Portrait(&emotion),
-                &context.this_server_url,
+                &context.url_base,
                 self.1,
                 &self.2,
             ),
@@ -423,7 +441,7 @@ impl MonsterFormPortraits {
                 locked: *locked,
                 url: get_url(
                     AssetType::Portrait("Normal"),
-                    &context.this_server_url,
+                    &context.url_base,
                     self.1,
                     &self.2,
                 ),
@@ -439,7 +457,7 @@ impl MonsterFormPortraits {
                     locked: *locked,
                     url: get_url(
                         AssetType::Portrait(emotion),
-                        &context.this_server_url,
+                        &context.url_base,
                         self.1,
                         &self.2,
                     ),
@@ -449,22 +467,27 @@ impl MonsterFormPortraits {
 
     #[graphql(description = "A list of all existing flipped portraits for the emotions.")]
     async fn emotions_flipped(&self, context: &Context) -> FieldResult<Vec<Portrait>> {
-        Ok(
-            iter_existing_portrait_files(&context, &self.0.portrait_files, true, self.1, &self.2)
-                .await?
-                .into_iter()
-                .map(|(emotion, locked)| Portrait {
-                    emotion: emotion.clone(),
-                    locked,
-                    url: get_url(
-                        AssetType::PortraitFlipped(&emotion),
-                        &context.this_server_url,
-                        self.1,
-                        &self.2,
-                    ),
-                })
-                .collect(),
+        Ok(iter_existing_portrait_files(
+            &context,
+            &context.root,
+            &self.0.portrait_files,
+            true,
+            self.1,
+            &self.2,
         )
+        .await?
+        .into_iter()
+        .map(|(emotion, locked)| Portrait {
+            emotion: emotion.clone(),
+            locked,
+            url: get_url(
+                AssetType::PortraitFlipped(&emotion),
+                &context.url_base,
+                self.1,
+                &self.2,
+            ),
+        })
+        .collect())
     }
 
     #[graphql(description = "A single flipped portrait for a given emotion.")]
@@ -475,6 +498,7 @@ impl MonsterFormPortraits {
     ) -> FieldResult<Option<Portrait>> {
         Ok(get_existing_portrait_file(
             &context,
+            &context.root,
             &self.0.portrait_files,
             &emotion,
             true,
@@ -487,7 +511,7 @@ impl MonsterFormPortraits {
             locked,
             url: get_url(
                 AssetType::PortraitFlipped(&emotion),
-                &context.this_server_url,
+                &context.url_base,
                 self.1,
                 &self.2,
             ),
@@ -503,11 +527,49 @@ impl MonsterFormPortraits {
         description = "List of all modifications made to those portraits since its creation."
     )]
     async fn history(&self, context: &Context) -> FieldResult<Vec<MonsterHistory>> {
-        get_local_credits_file(&context, AssetCategory::Portrait, self.1, &self.2)
-            .await??
+        get_local_credits_file(
+            &context,
+            &context.root,
+            AssetCategory::Portrait,
+            self.1,
+            &self.2,
+        )
+        .await??
+        .into_iter()
+        .map(|i| MonsterHistory::try_from_credit_row(context, i))
+        .collect::<Result<Vec<_>, _>>()
+    }
+
+    #[graphql(
+        description = "All distinct colours used in the portraits of this form with their pixel counts, sorted by pixel count."
+    )]
+    async fn palette(&self, context: &Context) -> FieldResult<Vec<PaletteColor>> {
+        Ok(context
+            .cached_may_fail_chain(
+                format!("/portrait_palette|{}/{:?}", self.1, self.2),
+                context.root.commit(),
+                || async {
+                    palette::portrait_palette(&self.0, &context.root.portrait_dir(self.1, &self.2))
+                        .await
+                        .map(CacheBehaviour::Cache)
+                        .map_err(failed_image_processing)
+                },
+            )
+            .await?
             .into_iter()
-            .map(|i| MonsterHistory::try_from_credit_row(context, i))
-            .collect::<Result<Vec<_>, _>>()
+            .map(PaletteColor::from)
+            .collect())
+    }
+
+    #[graphql(
+        description = "The colour mapping from the base (non-shiny) form to the shiny form, derived from the portraits of both forms. If toShiny is false, the mapping from the shiny form to the base form is returned instead. Returns null if one of the two forms doesn't exist."
+    )]
+    async fn palette_mapping(
+        &self,
+        context: &Context,
+        #[graphql(default = true)] to_shiny: bool,
+    ) -> FieldResult<Option<Vec<ColorMapping>>> {
+        palette_mapping(context, AssetCategory::Portrait, self.1, &self.2, to_shiny).await
     }
 
     #[graphql(
@@ -516,11 +578,37 @@ impl MonsterFormPortraits {
     fn history_url(&self, context: &Context) -> Option<String> {
         Some(get_url(
             AssetType::PortraitCreditsTxt,
-            &context.this_server_url,
+            &context.url_base,
             self.1,
             &self.2,
         ))
     }
+
+    #[graphql(
+        description = "Submissions for the portraits of this form that are waiting to be approved."
+    )]
+    fn pending(&self) -> Vec<PendingSubmission> {
+        self.0
+            .portrait_pending
+            .iter()
+            .cloned()
+            .map(PendingSubmission)
+            .collect()
+    }
+
+    #[graphql(
+        description = "Link to the latest submission for the portraits of this form, as recorded by SpriteBot."
+    )]
+    fn link(&self) -> Option<&str> {
+        non_empty(&self.0.portrait_link)
+    }
+
+    #[graphql(
+        description = "Link to the latest recolor submission for the portraits of this form, as recorded by SpriteBot."
+    )]
+    fn recolor_link(&self) -> Option<&str> {
+        non_empty(&self.0.portrait_recolor_link)
+    }
 }
 
 // TODO: Once async works better with references in Juniper, switch back to this:
@@ -528,36 +616,22 @@ impl MonsterFormPortraits {
 pub struct MonsterFormSprites(Arc<Group>, i32, Vec<i32>);
 
 impl MonsterFormSprites {
-    fn process_sprite_action(&self, action: &str, locked: bool, this_server_url: &str) -> Sprite {
+    fn process_sprite_action(&self, action: &str, locked: bool, url_base: &UrlBase) -> Sprite {
         Sprite {
-            anim_url: get_url(
-                AssetType::SpriteAnim(action),
-                this_server_url,
-                self.1,
-                &self.2,
-            ),
-            offsets_url: get_url(
-                AssetType::SpriteOffsets(action),
-                this_server_url,
-                self.1,
-                &self.2,
-            ),
-            shadows_url: get_url(
-                AssetType::SpriteShadows(action),
-                this_server_url,
-                self.1,
-                &self.2,
-            ),
+            anim_url: get_url(AssetType::SpriteAnim(action), url_base, self.1, &self.2),
+            offsets_url: get_url(AssetType::SpriteOffsets(action), url_base, self.1, &self.2),
+            shadows_url: get_url(AssetType::SpriteShadows(action), url_base, self.1, &self.2),
             action: action.to_string(),
             locked,
         }
     }
 
     async fn fetch_xml_and_make_action_map(
+        root: &Path,
         monster_idx: i32,
         path_to_form: &[i32],
     ) -> FieldResult<CacheBehaviour<HashMap<String, String>>> {
-        let xml = AnimDataXml::open_for_form(monster_idx, path_to_form)
+        let xml = AnimDataXml::open_for_form(root, monster_idx, path_to_form)
             .map_err(Self::failed_xml_fetch)?;
         Ok(CacheBehaviour::Cache(xml.get_action_copies()))
     }
@@ -580,9 +654,11 @@ impl MonsterFormSprites {
     /// currently no way to do this truly async as far as I can tell.
     async fn get_action_map(&self, context: &Context) -> FieldResult<HashMap<String, String>> {
         context
-            .cached_may_fail_chain(format!("/monster_actions|{}/{:?}", self.1, self.2), || {
-                Self::fetch_xml_and_make_action_map(self.1, &self.2)
-            })
+            .cached_may_fail_chain(
+                format!("/monster_actions|{}/{:?}", self.1, self.2),
+                context.root.commit(),
+                || Self::fetch_xml_and_make_action_map(context.root.path(), self.1, &self.2),
+            )
             .await
     }
 }
@@ -641,7 +717,7 @@ impl MonsterFormSprites {
         if self.sprites_available() {
             Some(get_url(
                 AssetType::SpriteAnimDataXml,
-                &context.this_server_url,
+                &context.url_base,
                 self.1,
                 &self.2,
             ))
@@ -655,7 +731,7 @@ impl MonsterFormSprites {
         if self.sprites_available() {
             Some(get_url(
                 AssetType::SpriteZip,
-                &context.this_server_url,
+                &context.url_base,
                 self.1,
                 &self.2,
             ))
@@ -669,7 +745,7 @@ impl MonsterFormSprites {
         if self.sprites_available() {
             Some(get_url(
                 AssetType::SpriteRecolorSheet,
-                &context.this_server_url,
+                &context.url_base,
                 self.1,
                 &self.2,
             ))
@@ -684,28 +760,29 @@ impl MonsterFormSprites {
             let action_copy_map = self.get_action_map(context).await?;
             // TODO: needed because of borrow in closure. can this be optimized?
             let action_copy_map_clone = action_copy_map.clone();
-            let mut normal_sprites: HashMap<String, Sprite> =
-                iter_existing_sprite_files(&context, &self.0.sprite_files, self.1, &self.2)
-                    .await?
-                    .into_iter()
-                    .filter_map(|(action, locked)| {
-                        // Copy ofs shouldn't appear here since they shouldn't have any sheets, but
-                        // if they do, we filter them out, since we explicitly add them below.
-                        if action_copy_map_clone.contains_key(&action) {
-                            None
-                        } else {
-                            let action_clone = action.clone();
-                            Some((
-                                action,
-                                self.process_sprite_action(
-                                    &action_clone,
-                                    locked,
-                                    &context.this_server_url,
-                                ),
-                            ))
-                        }
-                    })
-                    .collect();
+            let mut normal_sprites: HashMap<String, Sprite> = iter_existing_sprite_files(
+                &context,
+                &context.root,
+                &self.0.sprite_files,
+                self.1,
+                &self.2,
+            )
+            .await?
+            .into_iter()
+            .filter_map(|(action, locked)| {
+                // Copy ofs shouldn't appear here since they shouldn't have any sheets, but
+                // if they do, we filter them out, since we explicitly add them below.
+                if action_copy_map_clone.contains_key(&action) {
+                    None
+                } else {
+                    let action_clone = action.clone();
+                    Some((
+                        action,
+                        self.process_sprite_action(&action_clone, locked, &context.url_base),
+                    ))
+                }
+            })
+            .collect();
 
             let mut copy_of_sprites: HashMap<String, CopyOf> = action_copy_map
                 .into_iter()
@@ -761,6 +838,7 @@ impl MonsterFormSprites {
                 // Regular sprite
                 Ok(get_existing_sprite_file(
                     &context,
+                    &context.root,
                     &self.0.sprite_files,
                     &action,
                     self.1,
@@ -771,7 +849,7 @@ impl MonsterFormSprites {
                     SpriteUnion::Sprite(self.process_sprite_action(
                         &action,
                         locked,
-                        &context.this_server_url,
+                        &context.url_base,
                     ))
                 }))
             }
@@ -787,11 +865,49 @@ impl MonsterFormSprites {
 
     #[graphql(description = "List of all modifications made to those sprites since its creation.")]
     async fn history(&self, context: &Context) -> FieldResult<Vec<MonsterHistory>> {
-        get_local_credits_file(&context, AssetCategory::Sprite, self.1, &self.2)
-            .await??
+        get_local_credits_file(
+            &context,
+            &context.root,
+            AssetCategory::Sprite,
+            self.1,
+            &self.2,
+        )
+        .await??
+        .into_iter()
+        .map(|i| MonsterHistory::try_from_credit_row(context, i))
+        .collect::<Result<Vec<_>, _>>()
+    }
+
+    #[graphql(
+        description = "All distinct colours used in the sprite sheets of this form with their pixel counts, sorted by pixel count."
+    )]
+    async fn palette(&self, context: &Context) -> FieldResult<Vec<PaletteColor>> {
+        Ok(context
+            .cached_may_fail_chain(
+                format!("/sprite_palette|{}/{:?}", self.1, self.2),
+                context.root.commit(),
+                || async {
+                    palette::sprite_palette(&self.0, &context.root.sprite_dir(self.1, &self.2))
+                        .await
+                        .map(CacheBehaviour::Cache)
+                        .map_err(failed_image_processing)
+                },
+            )
+            .await?
             .into_iter()
-            .map(|i| MonsterHistory::try_from_credit_row(context, i))
-            .collect::<Result<Vec<_>, _>>()
+            .map(PaletteColor::from)
+            .collect())
+    }
+
+    #[graphql(
+        description = "The colour mapping from the base (non-shiny) form to the shiny form, derived from the sprite sheets of both forms. If toShiny is false, the mapping from the shiny form to the base form is returned instead. Returns null if one of the two forms doesn't exist."
+    )]
+    async fn palette_mapping(
+        &self,
+        context: &Context,
+        #[graphql(default = true)] to_shiny: bool,
+    ) -> FieldResult<Option<Vec<ColorMapping>>> {
+        palette_mapping(context, AssetCategory::Sprite, self.1, &self.2, to_shiny).await
     }
 
     #[graphql(
@@ -800,11 +916,283 @@ impl MonsterFormSprites {
     fn history_url(&self, context: &Context) -> Option<String> {
         Some(get_url(
             AssetType::SpriteCreditsTxt,
-            &context.this_server_url,
+            &context.url_base,
             self.1,
             &self.2,
         ))
     }
+
+    #[graphql(
+        description = "Submissions for the sprites of this form that are waiting to be approved."
+    )]
+    fn pending(&self) -> Vec<PendingSubmission> {
+        self.0
+            .sprite_pending
+            .iter()
+            .cloned()
+            .map(PendingSubmission)
+            .collect()
+    }
+
+    #[graphql(
+        description = "Link to the latest submission for the sprites of this form, as recorded by SpriteBot."
+    )]
+    fn link(&self) -> Option<&str> {
+        non_empty(&self.0.sprite_link)
+    }
+
+    #[graphql(
+        description = "Link to the latest recolor submission for the sprites of this form, as recorded by SpriteBot."
+    )]
+    fn recolor_link(&self) -> Option<&str> {
+        non_empty(&self.0.sprite_recolor_link)
+    }
+}
+
+pub struct PendingSubmission(tracker::PendingSubmission);
+
+#[graphql_object(Context = Context)]
+#[graphql(description = "A submission that is waiting to be approved, as recorded by SpriteBot.")]
+impl PendingSubmission {
+    #[graphql(description = "ID of the submission. For SpriteBot this is the Discord message ID.")]
+    fn id(&self) -> &str {
+        &self.0.id
+    }
+
+    #[graphql(
+        description = "The author of the submission. May be null if the author is not listed in the credit names."
+    )]
+    fn credit(&self, context: &Context) -> Option<Credit> {
+        let credit_id = &self.0.credit_id;
+        Credit::new(context.collab.data().credit_names.get(credit_id), credit_id).ok()
+    }
+
+    #[graphql(description = "The raw credit ID of the author of the submission.")]
+    fn credit_id(&self) -> &str {
+        &self.0.credit_id
+    }
+}
+
+fn non_empty(value: &str) -> Option<&str> {
+    if value.is_empty() { None } else { Some(value) }
+}
+
+#[derive(GraphQLObject)]
+#[graphql(description = "An RGBA colour.")]
+pub struct Color {
+    r: i32,
+    g: i32,
+    b: i32,
+    a: i32,
+    #[graphql(description = "The colour as a hex string in the format #RRGGBBAA.")]
+    hex: String,
+}
+
+impl From<palette::Color> for Color {
+    fn from([r, g, b, a]: palette::Color) -> Self {
+        Self {
+            r: r as i32,
+            g: g as i32,
+            b: b as i32,
+            a: a as i32,
+            hex: format!("#{:02X}{:02X}{:02X}{:02X}", r, g, b, a),
+        }
+    }
+}
+
+#[derive(GraphQLObject)]
+#[graphql(description = "A colour of a palette and how many pixels use it.")]
+pub struct PaletteColor {
+    color: Color,
+    #[graphql(description = "Number of pixels using this colour, over all images.")]
+    count: i32,
+}
+
+impl From<(palette::Color, u64)> for PaletteColor {
+    fn from((color, count): (palette::Color, u64)) -> Self {
+        Self {
+            color: color.into(),
+            count: count.min(i32::MAX as u64) as i32,
+        }
+    }
+}
+
+#[derive(GraphQLObject)]
+#[graphql(description = "A mapping of one colour to another.")]
+pub struct ColorMapping {
+    from: Color,
+    to: Color,
+}
+
+#[derive(GraphQLObject)]
+#[graphql(
+    description = "A colour of the base form that was mapped to more than one colour in the shiny form."
+)]
+pub struct ColorCollision {
+    #[graphql(description = "The colour in the base form.")]
+    base: Color,
+    #[graphql(description = "All colours in the shiny form that the base colour was mapped to.")]
+    shiny: Vec<Color>,
+    #[graphql(description = "Emotions or actions in which the collision occurred.")]
+    files: Vec<String>,
+}
+
+#[derive(GraphQLObject)]
+#[graphql(
+    description = "A portrait or sprite sheet of a shiny form that does not have the same shape as the base form."
+)]
+pub struct ShapeMismatch {
+    #[graphql(description = "The emotion or action.")]
+    file: String,
+    #[graphql(description = "A human-readable description of the mismatch.")]
+    reason: String,
+}
+
+#[derive(GraphQLObject)]
+#[graphql(
+    description = "Result of comparing the portraits or sprites of a shiny form with its base form."
+)]
+pub struct PaletteCheckResult {
+    #[graphql(description = "True if there are no colour collisions and no shape mismatches.")]
+    consistent: bool,
+    #[graphql(description = "The emotions or actions that were compared.")]
+    compared: Vec<String>,
+    #[graphql(
+        description = "The derived base -> shiny colour mapping. For colliding colours, the most common mapping is listed."
+    )]
+    mapping: Vec<ColorMapping>,
+    #[graphql(description = "Base colours that were mapped to more than one shiny colour.")]
+    collisions: Vec<ColorCollision>,
+    #[graphql(description = "Images whose dimensions or transparency masks differ.")]
+    shape_mismatches: Vec<ShapeMismatch>,
+}
+
+impl From<palette::PaletteCheckReport> for PaletteCheckResult {
+    fn from(report: palette::PaletteCheckReport) -> Self {
+        Self {
+            consistent: report.is_consistent(),
+            compared: report.compared_files,
+            mapping: report
+                .mapping
+                .into_iter()
+                .map(|(from, to)| ColorMapping {
+                    from: from.into(),
+                    to: to.into(),
+                })
+                .collect(),
+            collisions: report
+                .collisions
+                .into_iter()
+                .map(|c| ColorCollision {
+                    base: c.base.into(),
+                    shiny: c.shiny.into_iter().map(Color::from).collect(),
+                    files: c.files,
+                })
+                .collect(),
+            shape_mismatches: report
+                .shape_mismatches
+                .into_iter()
+                .map(|m| ShapeMismatch {
+                    file: m.file,
+                    reason: m.reason,
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(GraphQLObject)]
+#[graphql(
+    description = "Result of checking whether a shiny form is a strict 1:1 colour remap of its base form."
+)]
+pub struct PaletteCheck {
+    #[graphql(description = "True if both the portraits and the sprites are consistent.")]
+    consistent: bool,
+    #[graphql(description = "The path to the base form that was compared against.")]
+    base_path: String,
+    portraits: PaletteCheckResult,
+    sprites: PaletteCheckResult,
+}
+
+fn failed_image_processing(e: anyhow::Error) -> FieldError {
+    let e_as_str = format!("{:?}", e);
+    FieldError::new(
+        "Internal Server Error: Failed processing images.".to_string(),
+        graphql_value!({ "details": e_as_str }),
+    )
+}
+
+/// Derives the colour mapping between the base form and the shiny form of the given form.
+/// If `to_shiny` is false, the mapping from the shiny to the base form is derived instead.
+async fn palette_mapping(
+    context: &Context,
+    category: AssetCategory,
+    monster_idx: i32,
+    form_path: &[i32],
+    to_shiny: bool,
+) -> FieldResult<Option<Vec<ColorMapping>>> {
+    let base_form_path = force_non_shiny_group(form_path);
+    let shiny_form_path = force_shiny_group(&base_form_path);
+    let tracker = context.collab.data().tracker.clone();
+    let collector = MonsterFormCollector::collect(&tracker, monster_idx)
+        .ok_or_else(|| monster_not_found(monster_idx))?;
+    let (base, shiny) = match (
+        collector.find_form(base_form_path.into_iter().map(FormMatch::Exact)),
+        collector.find_form(shiny_form_path.into_iter().map(FormMatch::Exact)),
+    ) {
+        (Some((base_path, _, base_group)), Some((shiny_path, _, shiny_group))) => {
+            ((base_path, base_group), (shiny_path, shiny_group))
+        }
+        _ => return Ok(None),
+    };
+    let ((from_path, from_group), (to_path, to_group)) = if to_shiny {
+        (base, shiny)
+    } else {
+        (shiny, base)
+    };
+    let report = context
+        .cached_may_fail_chain(
+            format!(
+                "/palette_mapping_{}|{}/{:?}->{:?}",
+                category, monster_idx, from_path, to_path
+            ),
+            context.root.commit(),
+            || async {
+                match category {
+                    AssetCategory::Portrait => {
+                        palette::check_portrait_palette(
+                            from_group,
+                            &context.root.portrait_dir(monster_idx, &from_path),
+                            to_group,
+                            &context.root.portrait_dir(monster_idx, &to_path),
+                        )
+                        .await
+                    }
+                    AssetCategory::Sprite => {
+                        palette::check_sprite_palette(
+                            from_group,
+                            &context.root.sprite_dir(monster_idx, &from_path),
+                            to_group,
+                            &context.root.sprite_dir(monster_idx, &to_path),
+                        )
+                        .await
+                    }
+                }
+                .map(CacheBehaviour::Cache)
+                .map_err(failed_image_processing)
+            },
+        )
+        .await?;
+    Ok(Some(
+        report
+            .mapping
+            .into_iter()
+            .map(|(from, to)| ColorMapping {
+                from: from.into(),
+                to: to.into(),
+            })
+            .collect(),
+    ))
 }
 
 pub struct MonsterForm {
@@ -881,6 +1269,59 @@ impl MonsterForm {
     fn sprites(&self) -> MonsterFormSprites {
         MonsterFormSprites(self.data.clone(), self.id, self.form_id.clone())
     }
+
+    #[graphql(
+        description = "For shiny forms: Compares the portraits and sprites pixel by pixel with the non-shiny base form and reports colour collisions and shape mismatches. Returns null for non-shiny forms."
+    )]
+    async fn palette_check(&self, context: &Context) -> FieldResult<Option<PaletteCheck>> {
+        if !MonsterFormCollector::is_shiny(&self.form_id) {
+            return Ok(None);
+        }
+        let tracker = context.collab.data().tracker.clone();
+        let collector = MonsterFormCollector::collect(&tracker, self.id)
+            .ok_or_else(|| monster_not_found(self.id))?;
+        let (base_form_id, _, base_group) = match collector.find_form(
+            force_non_shiny_group(&self.form_id)
+                .into_iter()
+                .map(FormMatch::Exact),
+        ) {
+            Some(v) => v,
+            None => return Ok(None),
+        };
+        let (portraits, sprites) = context
+            .cached_may_fail_chain(
+                format!("/palette_check|{}/{:?}", self.id, self.form_id),
+                context.root.commit(),
+                || async {
+                    let portraits = palette::check_portrait_palette(
+                        base_group,
+                        &context.root.portrait_dir(self.id, &base_form_id),
+                        &self.data,
+                        &context.root.portrait_dir(self.id, &self.form_id),
+                    )
+                    .await
+                    .map_err(failed_image_processing)?;
+                    let sprites = palette::check_sprite_palette(
+                        base_group,
+                        &context.root.sprite_dir(self.id, &base_form_id),
+                        &self.data,
+                        &context.root.sprite_dir(self.id, &self.form_id),
+                    )
+                    .await
+                    .map_err(failed_image_processing)?;
+                    Ok(CacheBehaviour::Cache((portraits, sprites)))
+                },
+            )
+            .await?;
+        let portraits = PaletteCheckResult::from(portraits);
+        let sprites = PaletteCheckResult::from(sprites);
+        Ok(Some(PaletteCheck {
+            consistent: portraits.consistent && sprites.consistent,
+            base_path: join_monster_and_form(self.id, &base_form_id, '/'),
+            portraits,
+            sprites,
+        }))
+    }
 }
 
 #[derive(Deserialize, Serialize)]
@@ -1122,15 +1563,50 @@ impl From<&CreditNamesRow> for Credit {
 }
 
 pub struct Context {
-    this_server_url: String,
+    url_base: UrlBase,
     collab: Arc<SpriteCollab>,
+    /// The snapshot assets are read from, kept for the whole request.
+    root: Arc<DataRoot>,
+    /// The authenticated user making the request, if any.
+    user: Option<User>,
 }
 
 impl Context {
-    pub fn new(collab: Arc<SpriteCollab>) -> Self {
+    /// If `pinned_urls` is set, URLs of files in the repository are pinned to the commit the
+    /// data of this request is read from.
+    /// This is not possible when serving a data directory.
+    pub fn new(collab: Arc<SpriteCollab>, user: Option<User>, pinned_urls: bool) -> Self {
+        let root = collab.data().root.clone();
+        let dataset = collab.dataset();
         Context {
-            this_server_url: SystemConfig::Address.get_or_none().unwrap_or_default(),
+            url_base: UrlBase {
+                this_srv_url: format!(
+                    "{}{}",
+                    SystemConfig::Address.get_or_none().unwrap_or_default(),
+                    dataset.route_prefix()
+                ),
+                git_assets_url: dataset.get(SystemConfig::GitAssetsUrl),
+                pinned_commit: (pinned_urls && dataset.data_dir().is_none())
+                    .then(|| root.commit().to_string()),
+            },
+            root,
             collab,
+            user,
+        }
+    }
+
+    /// Returns the authenticated user, if they have at least the given role.
+    fn require_role(&self, role: Role) -> FieldResult<&User> {
+        match &self.user {
+            Some(user) if user.has_role(role) => Ok(user),
+            Some(_) => Err(FieldError::new(
+                "You are not allowed to do this.",
+                graphql_value!(None),
+            )),
+            None => Err(FieldError::new(
+                "Authentication required. Pass a token via the Authorization header.",
+                graphql_value!(None),
+            )),
         }
     }
 }
@@ -1142,6 +1618,7 @@ impl ScCache for Context {
     async fn cached_may_fail<S, Fn, Ft, T, E>(
         &self,
         cache_key: S,
+        commit: &str,
         func: Fn,
     ) -> FieldResult<Result<T, E>>
     where
@@ -1152,7 +1629,30 @@ impl ScCache for Context {
         E: Send,
     {
         self.collab
-            .cached_may_fail(cache_key, func)
+            .cached_may_fail(cache_key, commit, func)
+            .await
+            .map_err(|_e| {
+                FieldError::new(
+                    "Internal lookup error.",
+                    graphql_value!({ "reason": "redis lookup failed. try again." }),
+                )
+            })
+    }
+
+    async fn cached_bytes_may_fail<S, Fn, Ft, E>(
+        &self,
+        cache_key: S,
+        commit: &str,
+        func: Fn,
+    ) -> FieldResult<Result<Vec<u8>, E>>
+    where
+        S: AsRef<str> + Into<Key> + Send + Sync,
+        Fn: (FnOnce() -> Ft) + Send,
+        Ft: Future<Output = Result<CacheBehaviour<Vec<u8>>, E>> + Send,
+        E: Send,
+    {
+        self.collab
+            .cached_bytes_may_fail(cache_key, commit, func)
             .await
             .map_err(|_e| {
                 FieldError::new(
@@ -1228,6 +1728,445 @@ impl Meta {
             })
             .await
     }
+
+    #[graphql(
+        description = "Commit the assets are pinned to by an admin. While pinned, the server does not update to newer commits."
+    )]
+    fn pinned_commit(context: &Context) -> Option<String> {
+        context.collab.pinned().map(|pin| pin.commit)
+    }
+
+    #[graphql(
+        description = "Commits whose data could not be loaded or that were rejected by the trust policy. They are skipped until a newer commit arrives."
+    )]
+    fn quarantined_commits(context: &Context) -> Vec<QuarantinedCommit> {
+        context
+            .collab
+            .quarantined()
+            .into_iter()
+            .map(QuarantinedCommit)
+            .collect()
+    }
+}
+
+pub struct QuarantinedCommit(refresh_history::QuarantinedCommit);
+
+#[graphql_object(Context = Context)]
+#[graphql(description = "A commit whose data could not be loaded or that was rejected.")]
+impl QuarantinedCommit {
+    #[graphql(description = "The commit hash.")]
+    fn commit(&self) -> &str {
+        &self.0.commit
+    }
+
+    #[graphql(description = "Date the commit was quarantined.")]
+    fn date(&self) -> DateTime<Utc> {
+        self.0.date
+    }
+
+    #[graphql(description = "Why the commit was quarantined.")]
+    fn error(&self) -> &str {
+        &self.0.error
+    }
+}
+
+pub struct RefreshRecord(refresh_history::RefreshRecord);
+
+#[graphql_object(Context = Context)]
+#[graphql(description = "A data refresh, as recorded in the refresh history.")]
+impl RefreshRecord {
+    #[graphql(description = "Date the refresh was started.")]
+    fn date(&self) -> DateTime<Utc> {
+        self.0.date
+    }
+
+    #[graphql(description = "What was done: `refresh`, `pin`, `rollback`, `unpin` or `commit`.")]
+    fn action(&self) -> &str {
+        &self.0.action
+    }
+
+    #[graphql(
+        description = "Who triggered the refresh: The name of a user, `scheduler` or `git hook`."
+    )]
+    fn triggered_by(&self) -> &str {
+        &self.0.triggered_by
+    }
+
+    #[graphql(description = "The commit served after the refresh. Null if it failed.")]
+    fn commit(&self) -> Option<&str> {
+        self.0.commit.as_deref()
+    }
+
+    #[graphql(description = "How long the refresh took, in milliseconds.")]
+    fn duration_ms(&self) -> i32 {
+        self.0.duration.as_millis().min(i32::MAX as u128) as i32
+    }
+
+    #[graphql(description = "The error, if the refresh failed.")]
+    fn error(&self) -> Option<&str> {
+        self.0.error.as_deref()
+    }
+}
+
+pub struct JobStatus(jobs::JobStatus);
+
+#[graphql_object(Context = Context)]
+#[graphql(description = "A background job that runs periodically.")]
+impl JobStatus {
+    #[graphql(description = "Name of the job: `refresh`, `stats`, `warmup` or `webhooks`.")]
+    fn name(&self) -> &str {
+        self.0.name
+    }
+
+    #[graphql(
+        description = "Seconds between runs, before jitter and backoff. Not set for jobs that only run when triggered."
+    )]
+    fn interval_secs(&self) -> Option<i32> {
+        self.0
+            .interval
+            .map(|interval| interval.as_secs().min(i32::MAX as u64) as i32)
+    }
+
+    #[graphql(description = "Whether the job is running right now.")]
+    fn running(&self) -> bool {
+        self.0.running
+    }
+
+    #[graphql(description = "Date the last run was started.")]
+    fn last_run(&self) -> Option<DateTime<Utc>> {
+        self.0.last_run
+    }
+
+    #[graphql(description = "How long the last finished run took, in milliseconds.")]
+    fn last_duration_ms(&self) -> Option<i32> {
+        self.0
+            .last_duration
+            .map(|v| v.as_millis().min(i32::MAX as u128) as i32)
+    }
+
+    #[graphql(description = "Who triggered the last run: `scheduler`, a user name or an event.")]
+    fn last_triggered_by(&self) -> Option<&str> {
+        self.0.last_triggered_by.as_deref()
+    }
+
+    #[graphql(description = "The error of the last run, if it failed.")]
+    fn last_error(&self) -> Option<&str> {
+        self.0.last_error.as_deref()
+    }
+
+    #[graphql(description = "Number of consecutive failed runs.")]
+    fn failures(&self) -> i32 {
+        self.0.failures.min(i32::MAX as u32) as i32
+    }
+
+    #[graphql(
+        description = "When the job runs next, unless triggered earlier. Null while running."
+    )]
+    fn next_run(&self) -> Option<DateTime<Utc>> {
+        self.0.next_run
+    }
+}
+
+pub struct StatsSnapshot(stats::StatsSnapshot);
+
+#[graphql_object(Context = Context)]
+#[graphql(description = "Statistics about the data at a point in time.")]
+impl StatsSnapshot {
+    #[graphql(description = "Date the snapshot was taken.")]
+    fn date(&self) -> DateTime<Utc> {
+        self.0.date
+    }
+
+    #[graphql(description = "The commit that was served.")]
+    fn commit(&self) -> &str {
+        &self.0.commit
+    }
+
+    #[graphql(description = "Number of monsters.")]
+    fn monsters(&self) -> i32 {
+        self.0.monsters as i32
+    }
+
+    #[graphql(description = "Number of forms, including the base forms of monsters.")]
+    fn forms(&self) -> i32 {
+        self.0.forms as i32
+    }
+
+    #[graphql(description = "Number of portrait files, including flipped portraits.")]
+    fn portraits(&self) -> i32 {
+        self.0.portraits as i32
+    }
+
+    #[graphql(description = "Number of sprite actions.")]
+    fn sprites(&self) -> i32 {
+        self.0.sprites as i32
+    }
+
+    #[graphql(description = "Number of credit entries.")]
+    fn credits(&self) -> i32 {
+        self.0.credits as i32
+    }
+
+    #[graphql(description = "Number of submissions waiting for review.")]
+    fn pending_submissions(&self) -> i32 {
+        self.0.pending_submissions as i32
+    }
+}
+
+fn refresh_error(e: anyhow::Error) -> FieldError {
+    let e_as_str = format!("{}", e);
+    FieldError::new(
+        "Failed refreshing the data.".to_string(),
+        graphql_value!({ "details": e_as_str }),
+    )
+}
+
+#[derive(GraphQLEnum, Clone, Copy)]
+#[graphql(description = "The kind of asset that was submitted.")]
+pub enum SubmissionKind {
+    #[graphql(description = "A ZIP archive containing all sprites of a form.")]
+    Sprites,
+    #[graphql(description = "A single portrait emotion.")]
+    Portrait,
+}
+
+impl From<store::SubmissionKind> for SubmissionKind {
+    fn from(value: store::SubmissionKind) -> Self {
+        match value {
+            store::SubmissionKind::Sprites => SubmissionKind::Sprites,
+            store::SubmissionKind::Portrait => SubmissionKind::Portrait,
+        }
+    }
+}
+
+#[derive(GraphQLEnum, Clone, Copy, Eq, PartialEq)]
+#[graphql(description = "The review state of a submission.")]
+pub enum SubmissionStatus {
+    #[graphql(description = "Waiting to be reviewed.")]
+    Pending,
+    #[graphql(description = "Approved and added to the assets.")]
+    Approved,
+    #[graphql(description = "Rejected by a reviewer.")]
+    Rejected,
+}
+
+impl From<store::SubmissionStatus> for SubmissionStatus {
+    fn from(value: store::SubmissionStatus) -> Self {
+        match value {
+            store::SubmissionStatus::Pending | store::SubmissionStatus::Applying => {
+                SubmissionStatus::Pending
+            }
+            store::SubmissionStatus::Approved => SubmissionStatus::Approved,
+            store::SubmissionStatus::Rejected => SubmissionStatus::Rejected,
+        }
+    }
+}
+
+pub struct Submission(store::Submission);
+
+#[graphql_object(Context = Context)]
+#[graphql(description = "Portraits or sprites submitted to this server for review.")]
+impl Submission {
+    #[graphql(description = "ID of the submission.")]
+    fn id(&self) -> &str {
+        &self.0.id
+    }
+
+    #[graphql(description = "Whether sprites or a portrait were submitted.")]
+    fn kind(&self) -> SubmissionKind {
+        self.0.kind.into()
+    }
+
+    #[graphql(description = "ID of the monster the submission is for.")]
+    fn monster_id(&self) -> i32 {
+        self.0.monster_idx
+    }
+
+    #[graphql(description = "Path to the form the submission is for.")]
+    fn form_path(&self) -> &[i32] {
+        &self.0.form_path
+    }
+
+    #[graphql(description = "The submitted emotion. Only set for portraits.")]
+    fn emotion(&self) -> Option<&str> {
+        self.0.emotion.as_deref()
+    }
+
+    #[graphql(description = "Name of the user that submitted this.")]
+    fn submitter(&self) -> &str {
+        &self.0.submitter
+    }
+
+    #[graphql(
+        description = "The author the submission is credited to. May be null if the author is not yet known to SpriteCollab."
+    )]
+    fn credit(&self, context: &Context) -> Option<Credit> {
+        let credit_id = parse_credit_id(&self.0.credit_id);
+        Credit::new(
+            context.collab.data().credit_names.get(&credit_id),
+            &credit_id,
+        )
+        .ok()
+    }
+
+    #[graphql(description = "The license the submission is released under.")]
+    fn license(&self) -> License {
+        self.0.license.clone().into()
+    }
+
+    #[graphql(description = "Date and time the submission was made.")]
+    fn submitted_date(&self) -> DateTime<Utc> {
+        self.0.submitted_date
+    }
+
+    #[graphql(description = "The review state of the submission.")]
+    fn status(&self) -> SubmissionStatus {
+        self.0.status.into()
+    }
+
+    #[graphql(description = "Name of the user that approved or rejected the submission.")]
+    fn reviewer(&self) -> Option<&str> {
+        self.0.reviewer.as_deref()
+    }
+
+    #[graphql(description = "Date and time the submission was approved or rejected.")]
+    fn reviewed_date(&self) -> Option<DateTime<Utc>> {
+        self.0.reviewed_date
+    }
+
+    #[graphql(description = "The reason given for rejecting the submission.")]
+    fn rejection_reason(&self) -> Option<&str> {
+        self.0.rejection_reason.as_deref()
+    }
+
+    #[graphql(
+        description = "The commit in the assets repository that added the submission, if approved."
+    )]
+    fn commit(&self) -> Option<&str> {
+        self.0.commit.as_deref()
+    }
+
+    #[graphql(description = "Warnings of the validation that ran when the submission was made.")]
+    fn warnings(&self) -> Vec<String> {
+        self.0
+            .validation
+            .warnings
+            .iter()
+            .map(|w| match &w.file {
+                Some(file) => format!("{}: {}", file, w.message),
+                None => w.message.clone(),
+            })
+            .collect()
+    }
+
+    #[graphql(
+        description = "The submitted file (ZIP or PNG), Base64 encoded. Only available to reviewers and the submitter."
+    )]
+    fn data(&self, context: &Context) -> FieldResult<String> {
+        let user = context.require_role(Role::Submitter)?;
+        if !user.has_role(Role::Reviewer) && user.name != self.0.submitter {
+            return Err(FieldError::new(
+                "You are not allowed to do this.",
+                graphql_value!(None),
+            ));
+        }
+        context
+            .collab
+            .submissions()
+            .read_data(&self.0)
+            .map(|data| BASE64_STANDARD.encode(data))
+            .map_err(submission_error)
+    }
+}
+
+fn submission_error(e: anyhow::Error) -> FieldError {
+    let e_as_str = format!("{}", e);
+    FieldError::new(
+        "Failed processing the submission.".to_string(),
+        graphql_value!({ "details": e_as_str }),
+    )
+}
+
+/// Validates and stores a new submission of the authenticated user.
+async fn submit(
+    context: &Context,
+    kind: store::SubmissionKind,
+    monster_idx: i32,
+    form_path: Vec<i32>,
+    emotion: Option<String>,
+    data: String,
+    license: Option<String>,
+) -> FieldResult<Submission> {
+    let user = context.require_role(Role::Submitter)?;
+    let data = BASE64_STANDARD.decode(data).map_err(|e| {
+        let e_as_str = e.to_string();
+        FieldError::new(
+            "The data is not valid Base64.",
+            graphql_value!({ "details": e_as_str }),
+        )
+    })?;
+    let max_size = SystemConfig::max_upload_size();
+    if data.len() > max_size {
+        return Err(FieldError::new(
+            "The submitted file is too large.",
+            graphql_value!({ "max_size": (max_size as i32) }),
+        ));
+    }
+    let data = Bytes::from(data);
+    let validation = validate_submission(
+        &context.collab,
+        kind,
+        monster_idx,
+        &form_path,
+        emotion.clone(),
+        data.clone(),
+    )
+    .await
+    .map_err(submission_error)?
+    .ok_or_else(|| FieldError::new("The form does not exist.", graphql_value!(None)))?;
+    if !validation.valid {
+        let errors = juniper::Value::list(
+            validation
+                .errors
+                .iter()
+                .map(|e| {
+                    juniper::Value::scalar(match &e.file {
+                        Some(file) => format!("{}: {}", file, e.message),
+                        None => e.message.clone(),
+                    })
+                })
+                .collect(),
+        );
+        return Err(FieldError::new(
+            "The submission is invalid.",
+            graphql_value!({ "errors": errors }),
+        ));
+    }
+
+    let submission = store::Submission {
+        id: String::new(),
+        kind,
+        monster_idx,
+        form_path,
+        emotion,
+        submitter: user.name.clone(),
+        credit_id: user.credit_id.clone(),
+        license: license.unwrap_or_else(|| DEFAULT_SUBMISSION_LICENSE.to_string()),
+        submitted_date: Utc::now(),
+        status: store::SubmissionStatus::Pending,
+        reviewer: None,
+        reviewed_date: None,
+        rejection_reason: None,
+        commit: None,
+        validation,
+    };
+    context
+        .collab
+        .submissions()
+        .create(submission, &data)
+        .await
+        .map(Submission)
+        .map_err(submission_error)
 }
 
 // To make our context usable by Juniper, we have to implement a marker trait.
@@ -1252,24 +2191,78 @@ impl Query {
                 graphql_value!({ "max_length": (MAX_QUERY_LEN as i32) }),
             ))
         } else {
-            let tracker = context.collab.data().tracker.clone();
+            let (tracker, commit) = {
+                let data = context.collab.data();
+                (data.tracker.clone(), data.root.commit().to_string())
+            };
             context
-                .cached_may_fail_chain(format!("/search_monster|{}", &monster_name), || async {
-                    let r: FieldResult<Vec<Monster>> =
-                        fuzzy_find_tracker(&tracker, &monster_name, context, |idx| Monster {
-                            id: idx as i32,
-                        })
-                        .await;
-                    match r {
-                        Ok(v) if !v.is_empty() => Ok(CacheBehaviour::Cache(v)),
-                        Ok(v) => Ok(CacheBehaviour::NoCache(v)),
-                        Err(e) => Err(e),
-                    }
-                })
+                .cached_may_fail_chain(
+                    format!("/search_monster|{}", &monster_name),
+                    &commit,
+                    || async {
+                        let r: FieldResult<Vec<Monster>> =
+                            fuzzy_find_tracker(&tracker, &commit, &monster_name, context, |idx| {
+                                Monster { id: idx as i32 }
+                            })
+                            .await;
+                        match r {
+                            Ok(v) if !v.is_empty() => Ok(CacheBehaviour::Cache(v)),
+                            Ok(v) => Ok(CacheBehaviour::NoCache(v)),
+                            Err(e) => Err(e),
+                        }
+                    },
+                )
                 .await
         }
     }
 
+    #[graphql(description = "Recent data refreshes, newest first. Requires the admin role.")]
+    fn refresh_history(
+        context: &Context,
+        #[graphql(description = "Maximum number of records to return.")] limit: Option<i32>,
+    ) -> FieldResult<Vec<RefreshRecord>> {
+        context.require_role(Role::Admin)?;
+        Ok(context
+            .collab
+            .history()
+            .into_iter()
+            .take(limit.map(|v| v.max(0) as usize).unwrap_or(usize::MAX))
+            .map(RefreshRecord)
+            .collect())
+    }
+
+    #[graphql(
+        description = "Background jobs of the server and their status. Requires the admin role."
+    )]
+    fn jobs(context: &Context) -> FieldResult<Vec<JobStatus>> {
+        context.require_role(Role::Admin)?;
+        Ok(context
+            .collab
+            .jobs()
+            .status()
+            .into_iter()
+            .map(JobStatus)
+            .collect())
+    }
+
+    #[graphql(
+        description = "Periodic snapshots of statistics about the data, newest first. Requires the admin role."
+    )]
+    fn stats_history(
+        context: &Context,
+        #[graphql(description = "Maximum number of snapshots to return.")] limit: Option<i32>,
+    ) -> FieldResult<Vec<StatsSnapshot>> {
+        context.require_role(Role::Admin)?;
+        Ok(context
+            .collab
+            .stats()
+            .list()
+            .into_iter()
+            .take(limit.map(|v| v.max(0) as usize).unwrap_or(usize::MAX))
+            .map(StatsSnapshot)
+            .collect())
+    }
+
     #[graphql(description = "Retrieve a list of monsters.")]
     fn monster(
         context: &Context,
@@ -1301,16 +2294,17 @@ impl Query {
                 graphql_value!({ "max_length": (MAX_QUERY_LEN as i32) }),
             ))
         } else {
+            let commit = context.collab.data().root.commit().to_string();
             context
-                .cached(format!("/search_credit|{}", &query), || async {
-                    let r: Vec<Credit> = context
-                        .collab
-                        .data()
+                .cached(format!("/search_credit|{}", &query), &commit, || async {
+                    let data = context.collab.data();
+                    let r: Vec<Credit> = data
                         .credit_names
                         .fuzzy_find(&query)
                         .map(Credit::from)
                         .collect();
-                    if !r.is_empty() {
+                    // Don't cache results of newer data under the old commit.
+                    if !r.is_empty() && data.root.commit() == commit {
                         CacheBehaviour::Cache(r)
                     } else {
                         CacheBehaviour::NoCache(r)
@@ -1335,4 +2329,268 @@ impl Query {
     fn config(context: &Context) -> FieldResult<Config> {
         Ok(Config::from(&context.collab.data().sprite_config))
     }
+
+    #[graphql(
+        description = "All forms that have portrait or sprite submissions waiting to be approved, as recorded by SpriteBot."
+    )]
+    fn pending_forms(context: &Context) -> Vec<MonsterForm> {
+        let tracker = context.collab.data().tracker.clone();
+        let mut forms = Vec::new();
+        for monster_idx in tracker.keys() {
+            let monster_idx = **monster_idx as i32;
+            if let Some(collector) = MonsterFormCollector::collect(&tracker, monster_idx) {
+                let pending = collector.map(move |(form_id, name_path, group)| {
+                    (!group.portrait_pending.is_empty() || !group.sprite_pending.is_empty()).then(
+                        || MonsterForm {
+                            id: monster_idx,
+                            form_id,
+                            name_path,
+                            data: Arc::new(group.clone()),
+                        },
+                    )
+                });
+                forms.extend(pending.flatten());
+            }
+        }
+        forms
+    }
+
+    #[graphql(
+        description = "Submissions made to this server. Reviewers see all submissions, other users only their own. Requires authentication."
+    )]
+    fn submissions(
+        context: &Context,
+        #[graphql(description = "Only return submissions with this status.")] status: Option<
+            SubmissionStatus,
+        >,
+    ) -> FieldResult<Vec<Submission>> {
+        let user = context.require_role(Role::Submitter)?;
+        Ok(context
+            .collab
+            .submissions()
+            .list()
+            .map_err(submission_error)?
+            .into_iter()
+            .filter(|s| user.has_role(Role::Reviewer) || s.submitter == user.name)
+            .filter(|s| status.is_none_or(|status| status == s.status.into()))
+            .map(Submission)
+            .collect())
+    }
+}
+
+pub struct Mutation;
+
+#[graphql_object(Context = Context)]
+impl Mutation {
+    #[graphql(
+        description = "Submit a portrait for review. The portrait is validated first and rejected if it is invalid. Requires authentication."
+    )]
+    async fn submit_portrait(
+        context: &Context,
+        #[graphql(description = "Monster ID.")] monster_id: i32,
+        #[graphql(description = "Form path. Empty for the default form.")] form_path: Vec<i32>,
+        #[graphql(description = "Name of the emotion. Flipped emotions end with `^`.")]
+        emotion: String,
+        #[graphql(description = "The portrait PNG, Base64 encoded.")] data: String,
+        #[graphql(description = "License of the portrait. Defaults to `CC_BY-NC_4`.")]
+        license: Option<String>,
+    ) -> FieldResult<Submission> {
+        submit(
+            context,
+            store::SubmissionKind::Portrait,
+            monster_id,
+            form_path,
+            Some(emotion),
+            data,
+            license,
+        )
+        .await
+    }
+
+    #[graphql(
+        description = "Submit all sprites of a form for review. They are validated first and rejected if they are invalid. Requires authentication."
+    )]
+    async fn submit_sprites(
+        context: &Context,
+        #[graphql(description = "Monster ID.")] monster_id: i32,
+        #[graphql(description = "Form path. Empty for the default form.")] form_path: Vec<i32>,
+        #[graphql(
+            description = "A ZIP archive with the AnimData.xml and all sprite sheets, Base64 encoded."
+        )]
+        data: String,
+        #[graphql(description = "License of the sprites. Defaults to `CC_BY-NC_4`.")]
+        license: Option<String>,
+    ) -> FieldResult<Submission> {
+        submit(
+            context,
+            store::SubmissionKind::Sprites,
+            monster_id,
+            form_path,
+            None,
+            data,
+            license,
+        )
+        .await
+    }
+
+    #[graphql(
+        description = "Approve a pending submission. Its files are added to the assets repository and committed. Requires the reviewer role."
+    )]
+    async fn approve_submission(context: &Context, id: String) -> FieldResult<Submission> {
+        let reviewer = context.require_role(Role::Reviewer)?;
+        let store = context.collab.submissions();
+        let _guard = store.lock().await;
+        let mut submission = store.get_pending(&id).map_err(submission_error)?;
+        let data = store.read_data(&submission).map_err(submission_error)?;
+
+        let mut subject = format!(
+            "Add {} for {}",
+            submission.kind.dir_name(),
+            join_monster_and_form(submission.monster_idx, &submission.form_path, '/')
+        );
+        if let Some(emotion) = &submission.emotion {
+            subject = format!("{} ({})", subject, emotion);
+        }
+        let message = format!(
+            "{}\n\nSubmitted by {} ({}), approved by {}.\n{}",
+            subject,
+            submission.submitter,
+            submission.credit_id,
+            reviewer.name,
+            submission.commit_trailer()
+        );
+
+        // If an earlier approval committed the files but failed to record it, only the record
+        // is completed, so the files and credits aren't added twice.
+        let existing_commit = if submission.status == store::SubmissionStatus::Applying {
+            context
+                .collab
+                .find_commit_with_line(&submission.commit_trailer())
+                .await
+                .map_err(submission_error)?
+        } else {
+            None
+        };
+        let commit = match existing_commit {
+            Some(commit) => commit,
+            None => {
+                submission.status = store::SubmissionStatus::Applying;
+                store.save(&submission).map_err(submission_error)?;
+                let to_apply = submission.clone();
+                match context
+                    .collab
+                    .modify_checkout(&message, &reviewer.name, move |repo_path| {
+                        apply_submission(repo_path, &to_apply, &data)
+                    })
+                    .await
+                {
+                    Ok(commit) => commit,
+                    Err(e) => {
+                        // The change was discarded, the submission can be reviewed again.
+                        submission.status = store::SubmissionStatus::Pending;
+                        if let Err(save_e) = store.save(&submission) {
+                            warn!("Failed resetting submission {}: {}", submission.id, save_e);
+                        }
+                        return Err(submission_error(e));
+                    }
+                }
+            }
+        };
+
+        submission.status = store::SubmissionStatus::Approved;
+        submission.reviewer = Some(reviewer.name.clone());
+        submission.reviewed_date = Some(Utc::now());
+        submission.commit = Some(commit);
+        store.save(&submission).map_err(submission_error)?;
+        Ok(Submission(submission))
+    }
+
+    #[graphql(description = "Reject a pending submission. Requires the reviewer role.")]
+    async fn reject_submission(
+        context: &Context,
+        id: String,
+        #[graphql(description = "Why the submission was rejected. Shown to the submitter.")]
+        reason: String,
+    ) -> FieldResult<Submission> {
+        let reviewer = context.require_role(Role::Reviewer)?;
+        let store = context.collab.submissions();
+        let _guard = store.lock().await;
+        let mut submission = store.get_pending(&id).map_err(submission_error)?;
+        if submission.status == store::SubmissionStatus::Applying {
+            return Err(submission_error(anyhow::anyhow!(
+                "The approval of submission {} was interrupted. Approve it again to complete it.",
+                id
+            )));
+        }
+        submission.status = store::SubmissionStatus::Rejected;
+        submission.reviewer = Some(reviewer.name.clone());
+        submission.reviewed_date = Some(Utc::now());
+        submission.rejection_reason = Some(reason);
+        store.save(&submission).map_err(submission_error)?;
+        Ok(Submission(submission))
+    }
+
+    #[graphql(
+        description = "Refresh the data right away. If the data is pinned, the pinned commit is reloaded. Requires the admin role."
+    )]
+    async fn refresh(context: &Context) -> FieldResult<Meta> {
+        let admin = context.require_role(Role::Admin)?;
+        context
+            .collab
+            .refresh_now(&admin.name)
+            .await
+            .map_err(refresh_error)?;
+        Ok(Meta)
+    }
+
+    #[graphql(
+        description = "Pin the data to a commit. Until unpinned, the server does not update to newer commits. Requires the admin role."
+    )]
+    async fn pin_commit(
+        context: &Context,
+        #[graphql(description = "A commit hash or any other Git revision.")] commit: String,
+    ) -> FieldResult<Meta> {
+        let admin = context.require_role(Role::Admin)?;
+        context
+            .collab
+            .pin(&commit, &admin.name)
+            .await
+            .map_err(refresh_error)?;
+        Ok(Meta)
+    }
+
+    #[graphql(
+        description = "Remove the pin and update to the newest commit. Requires the admin role."
+    )]
+    async fn unpin(context: &Context) -> FieldResult<Meta> {
+        let admin = context.require_role(Role::Admin)?;
+        context
+            .collab
+            .unpin(&admin.name)
+            .await
+            .map_err(refresh_error)?;
+        Ok(Meta)
+    }
+
+    #[graphql(
+        description = "Pin the data to the commit the given number of commits before the one currently served. Requires the admin role."
+    )]
+    async fn rollback(
+        context: &Context,
+        #[graphql(description = "Number of commits to go back.")] commits: i32,
+    ) -> FieldResult<Meta> {
+        let admin = context.require_role(Role::Admin)?;
+        if commits < 1 {
+            return Err(FieldError::new(
+                "Must roll back at least one commit.",
+                graphql_value!(None),
+            ));
+        }
+        context
+            .collab
+            .rollback(commits as u32, &admin.name)
+            .await
+            .map_err(refresh_error)?;
+        Ok(Meta)
+    }
 }
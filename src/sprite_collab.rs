@@ -6,29 +6,41 @@ use std::future::Future;
 use std::ops::Deref;
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, RwLock, RwLockReadGuard};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use anyhow::{Error, anyhow};
 use async_trait::async_trait;
 use chrono::{DateTime, FixedOffset, TimeZone, Utc};
-use fred::prelude::{ClientLike, KeysInterface, ReconnectPolicy};
 use fred::types::Key;
 use git2::build::CheckoutBuilder;
-use git2::{Repository, ResetType};
+use git2::{IndexAddOption, Oid, PushOptions, Repository, ResetType, Signature};
 use log::{debug, error, info, warn};
 use serde::Serialize;
 use serde::de::DeserializeOwned;
-use tokio::fs::{create_dir_all, remove_dir_all};
+use tokio::fs::create_dir_all;
 use tokio::sync::Mutex;
 use tokio::time::timeout;
 
-use crate::cache::{CacheBehaviour, ScCache};
+use crate::cache::{CacheBackend, CacheBehaviour, ScCache, key_category};
 use crate::config::Config;
+use crate::config::Dataset;
+use crate::data_dir::content_hash;
+use crate::data_root::{DataRoot, remove_stale_trees};
 use crate::datafiles::credit_names::{CreditNames, read_credit_names};
 use crate::datafiles::group_id::GroupId;
 use crate::datafiles::sprite_config::{SpriteConfig, read_sprite_config};
-use crate::datafiles::tracker::{Group, MapImpl, Tracker, read_tracker};
+use crate::datafiles::tracker::{Group, MapImpl, Tracker, diff_trackers, read_tracker};
 use crate::datafiles::{read_and_report_error, try_read_in_anim_data_xml};
+use crate::disk_cache::DiskCache;
+use crate::git_remote;
+use crate::git_remote::{fetch_tracked_branch, remote_callbacks, tracked_branch};
+use crate::jobs::{Jobs, WARMUP_JOB, WEBHOOKS_JOB};
+use crate::refresh_history::{Pin, Quarantine, QuarantinedCommit, RefreshHistory, RefreshRecord};
+use crate::stats::StatsHistory;
+use crate::submissions::store::SubmissionStore;
+use crate::trust::verify_new_commits;
+use crate::warmup::WarmupScope;
+use crate::webhooks::Webhooks;
 
 const GIT_REPO_DIR: &str = "spritecollab";
 
@@ -38,11 +50,25 @@ enum State {
     Ready,
 }
 
+/// How the repository is updated before the data is read.
+#[derive(Clone, Debug)]
+pub enum RefreshMode {
+    /// Read what is currently checked out.
+    Local,
+    /// Fetch and check out the newest commit of the tracked branch.
+    Update,
+    /// Check out the given revision (commit hash or any other revision Git understands),
+    /// fetching first if it is not known locally.
+    Checkout(String),
+}
+
 #[derive(Eq, PartialEq)]
 pub struct SpriteCollabData {
     pub sprite_config: SpriteConfig,
     pub tracker: Arc<Tracker>,
     pub credit_names: CreditNames,
+    /// The snapshot the data was read from and assets are served from.
+    pub root: Arc<DataRoot>,
 }
 
 impl SpriteCollabData {
@@ -50,12 +76,14 @@ impl SpriteCollabData {
         sprite_config: SpriteConfig,
         mut tracker: Tracker,
         credit_names: CreditNames,
+        root: Arc<DataRoot>,
     ) -> SpriteCollabData {
         Self::sort_tracker_by_sprite_config(&mut tracker, &sprite_config);
         Self {
             sprite_config,
             tracker: Arc::new(tracker),
             credit_names,
+            root,
         }
     }
 }
@@ -116,79 +144,416 @@ pub struct SpriteCollab {
     state: Mutex<State>,
     meta: Mutex<RefCell<Meta>>,
     current_data: RwLock<SpriteCollabData>,
-    redis: fred::clients::Client,
+    dataset: Dataset,
+    cache: Arc<CacheBackend>,
+    submissions: SubmissionStore,
+    webhooks: Arc<Webhooks>,
+    pin: std::sync::Mutex<Option<Pin>>,
+    history: RefreshHistory,
+    quarantine: Arc<Quarantine>,
+    stats: StatsHistory,
+    disk_cache: DiskCache,
+    jobs: Jobs,
+    warmup: std::sync::Mutex<Option<WarmupScope>>,
 }
 
 impl SpriteCollab {
-    pub async fn new((redis_url, redis_port): (String, u16)) -> Arc<Self> {
-        let config =
-            fred::prelude::Config::from_url(&format!("redis://{}:{}", redis_url, redis_port))
-                .expect("Invalid Redis config.");
-        let policy = ReconnectPolicy::new_linear(10, 10000, 1000);
-        let client = fred::clients::Client::new(config, None, None, Some(policy));
-        client.connect();
-        client
-            .wait_for_connect()
-            .await
-            .expect("Failed to connect to Redis.");
-        let _: Option<()> = client.flushall(false).await.ok();
-        info!("Connected to Redis.");
+    /// Loads the data. Fails if neither the newest data nor any of the
+    /// `SCSRV_MAX_ROLLBACK` commits before it can be read.
+    pub async fn new(dataset: Dataset, cache: Arc<CacheBackend>) -> Result<Arc<Self>, Error> {
+        if let Some(name) = dataset.name() {
+            info!("Loading dataset {}...", name);
+        }
+        create_dir_all(dataset.workdir()).await.map_err(|e| {
+            anyhow!(
+                "Failed creating the workdir {}: {}",
+                dataset.workdir().display(),
+                e
+            )
+        })?;
 
         let meta = Mutex::new(RefCell::new(Meta::new()));
+        // Only commits of the repository can be pinned.
+        let pin = Pin::load(&dataset).filter(|_| dataset.data_dir().is_none());
+        let mode = refresh_mode(pin.as_ref());
+        if let Some(pin) = &pin {
+            info!("Data is pinned to commit {}.", pin.commit);
+        }
+
+        let quarantine = Arc::new(Quarantine::load(&dataset));
+        remove_stale_trees(&dataset);
 
         // First try an ordinary data update.
-        let current_data = match refresh_data(&meta).await {
-            Some(v) => RwLock::new(v),
-            None => {
+        debug!("Refreshing data...");
+        let current_data = match refresh_data_internal(&dataset, &meta, &mode, &quarantine, None)
+            .await
+        {
+            Ok(v) => RwLock::new(v),
+            Err(e) if dataset.data_dir().is_some() => {
+                return Err(anyhow!("Failed reading the data directory: {}", e));
+            }
+            Err(e) => {
                 // Try going back in time in the repo and updating.
                 error!(
-                    "Failed getting the newest data. Checking out old data until data processing works."
+                    "Failed getting the newest data: {}. Checking out old data until data processing works.",
+                    e
                 );
-                let repo_path = PathBuf::from(Config::Workdir.get()).join(GIT_REPO_DIR);
-                loop {
-                    let new_commit = try_checkout_previous_commit(&repo_path)
-                        .expect("Failed checking out old commit.");
-                    warn!("Checked out old commit: {}", new_commit);
-                    if let Ok(value) = refresh_data_internal(&meta, false).await {
-                        break RwLock::new(value);
-                    }
+                let repo_path = repo_path(&dataset);
+                if let Some(head) = head_commit(&repo_path) {
+                    quarantine.add(&head, &e.to_string());
                 }
+                RwLock::new(
+                    checkout_previous_valid_commit(&dataset, &meta, &repo_path, &quarantine)
+                        .await?,
+                )
             }
         };
 
-        Arc::new(Self {
+        let sprite_collab = Arc::new(Self {
             state: Mutex::new(State::Ready),
             current_data,
-            redis: client,
+            cache,
             meta,
-        })
+            submissions: SubmissionStore::new(&dataset),
+            webhooks: Arc::new(Webhooks::new(&dataset)),
+            stats: StatsHistory::load(&dataset),
+            disk_cache: DiskCache::load(&dataset),
+            history: RefreshHistory::load(&dataset),
+            dataset,
+            pin: std::sync::Mutex::new(pin),
+            quarantine,
+            jobs: Jobs::new(),
+            warmup: std::sync::Mutex::new(None),
+        });
+        sprite_collab.remove_stale_cache().await;
+        Ok(sprite_collab)
+    }
+
+    /// Removes the cached values of this dataset computed from other data than the current,
+    /// eg. by a previous run. Values of other datasets and applications are left alone.
+    async fn remove_stale_cache(&self) {
+        let commit = self.data().root.commit().to_string();
+        match self
+            .cache
+            .delete_prefix(
+                &self.dataset.cache_namespace(),
+                Some(&self.cache_prefix(&commit)),
+            )
+            .await
+        {
+            Ok(0) => {}
+            Ok(count) => info!("Removed {} stale cached values.", count),
+            Err(e) => warn!("Failed removing stale cached values: {:?}", e),
+        }
     }
 
-    /// Refreshes the data. Does nothing if already refreshing.
-    pub async fn refresh(slf: Arc<Self>) {
-        let state_lock_result = timeout(Duration::from_secs(360), slf.state.lock()).await;
-        match state_lock_result {
-            Ok(mut state_lock) => {
-                if state_lock.deref() == &State::Refreshing {
-                    return;
+    /// Refreshes the data right away, unless it is pinned to a commit. Returns the commit that is
+    /// served afterwards.
+    pub async fn refresh_now(&self, triggered_by: &str) -> Result<String, Error> {
+        let mode = refresh_mode(self.pin.lock().unwrap().as_ref());
+        self.refresh_with(mode, "refresh", triggered_by).await
+    }
+
+    /// Updates the repository according to `mode` and installs the new data. If reading the data
+    /// fails, the previously served commit is checked out again. The refresh is recorded in the
+    /// history. Returns the commit that is served afterwards.
+    pub async fn refresh_with(
+        &self,
+        mode: RefreshMode,
+        action: &str,
+        triggered_by: &str,
+    ) -> Result<String, Error> {
+        let date = Utc::now();
+        let start = Instant::now();
+        let result = self.refresh_with_do(&mode).await;
+        self.history.record(RefreshRecord {
+            date,
+            action: action.to_string(),
+            triggered_by: triggered_by.to_string(),
+            commit: result.as_ref().ok().cloned(),
+            duration: start.elapsed(),
+            error: result.as_ref().err().map(ToString::to_string),
+        });
+        result
+    }
+
+    async fn refresh_with_do(&self, mode: &RefreshMode) -> Result<String, Error> {
+        let mut state_lock = timeout(Duration::from_secs(360), self.state.lock())
+            .await
+            .map_err(|_| anyhow!("Timed out waiting for the running data refresh to finish."))?;
+        *state_lock = State::Refreshing;
+        let old_commit = self.meta.lock().await.borrow().assets_commit.clone();
+        debug!("Refreshing data ({:?})...", mode);
+        let current_root = self.data().root.clone();
+        let result = refresh_data_internal(
+            &self.dataset,
+            &self.meta,
+            mode,
+            &self.quarantine,
+            Some(current_root),
+        )
+        .await;
+        *state_lock = State::Ready;
+        match result {
+            Ok(new_data) => {
+                self.install_data(new_data, &old_commit).await;
+                let new_commit = self.meta.lock().await.borrow().assets_commit.clone();
+                self.quarantine.remove(&new_commit);
+                Ok(new_commit)
+            }
+            Err(e) if self.dataset.data_dir().is_some() => Err(e),
+            Err(e) => {
+                let repo_path = repo_path(&self.dataset);
+                // Don't try the commit again, until a newer one arrives.
+                if let Some(head) = head_commit(&repo_path)
+                    && head != old_commit
+                {
+                    self.quarantine.add(&head, &e.to_string());
                 }
-                if let Some(new_data) = refresh_data(&slf.meta).await {
-                    let changed;
-                    {
-                        let mut lock_data = slf.current_data.write().unwrap();
-                        changed = lock_data.deref() == &new_data;
-                        *lock_data = new_data;
-                        *state_lock = State::Ready;
-                    }
-                    if changed {
-                        let _: Option<()> = slf.redis.flushall(false).await.ok();
-                    }
+                if let Ok(old_commit) = Oid::from_str(&old_commit)
+                    && let Err(reset_e) = reset_repo(&repo_path, old_commit)
+                {
+                    warn!(
+                        "Failed to check out the previous commit after a failed refresh: {}",
+                        reset_e
+                    );
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Pins the data to the given revision. Until unpinned, refreshes keep it checked out.
+    pub async fn pin(&self, rev: &str, triggered_by: &str) -> Result<String, Error> {
+        self.pin_rev(rev.to_string(), "pin", triggered_by).await
+    }
+
+    /// Pins the data to the commit `commits` commits before the currently served one.
+    pub async fn rollback(&self, commits: u32, triggered_by: &str) -> Result<String, Error> {
+        let current = self.meta.lock().await.borrow().assets_commit.clone();
+        self.pin_rev(format!("{}~{}", current, commits), "rollback", triggered_by)
+            .await
+    }
+
+    /// Removes the pin and updates to the newest commit.
+    pub async fn unpin(&self, triggered_by: &str) -> Result<String, Error> {
+        Pin::remove(&self.dataset)?;
+        *self.pin.lock().unwrap() = None;
+        self.refresh_with(RefreshMode::Update, "unpin", triggered_by)
+            .await
+    }
+
+    async fn pin_rev(
+        &self,
+        rev: String,
+        action: &str,
+        triggered_by: &str,
+    ) -> Result<String, Error> {
+        let commit = self
+            .refresh_with(RefreshMode::Checkout(rev), action, triggered_by)
+            .await?;
+        let pin = Pin {
+            commit: commit.clone(),
+            pinned_by: triggered_by.to_string(),
+            date: Utc::now(),
+        };
+        pin.save(&self.dataset)?;
+        *self.pin.lock().unwrap() = Some(pin);
+        Ok(commit)
+    }
+
+    pub fn pinned(&self) -> Option<Pin> {
+        self.pin.lock().unwrap().clone()
+    }
+
+    pub fn history(&self) -> Vec<RefreshRecord> {
+        self.history.list()
+    }
+
+    pub fn quarantined(&self) -> Vec<QuarantinedCommit> {
+        self.quarantine.list()
+    }
+
+    /// Changes the checked out repository and commits the changes. `f` writes the changes and
+    /// returns the changed paths, relative to the repository. It runs on the blocking thread pool.
+    /// Afterwards the data is reloaded and, if configured, the commit is pushed. If any of these
+    /// steps fail, the commit is discarded again. Returns the ID of the new commit.
+    pub async fn modify_checkout<F>(
+        &self,
+        message: &str,
+        triggered_by: &str,
+        f: F,
+    ) -> Result<String, Error>
+    where
+        F: FnOnce(&Path) -> Result<Vec<PathBuf>, Error> + Send + 'static,
+    {
+        let date = Utc::now();
+        let start = Instant::now();
+        let result = self.modify_checkout_do(message, f).await;
+        self.history.record(RefreshRecord {
+            date,
+            action: "commit".to_string(),
+            triggered_by: triggered_by.to_string(),
+            commit: result.as_ref().ok().cloned(),
+            duration: start.elapsed(),
+            error: result.as_ref().err().map(ToString::to_string),
+        });
+        result
+    }
+
+    async fn modify_checkout_do<F>(&self, message: &str, f: F) -> Result<String, Error>
+    where
+        F: FnOnce(&Path) -> Result<Vec<PathBuf>, Error> + Send + 'static,
+    {
+        if self.dataset.data_dir().is_some() {
+            return Err(anyhow!(
+                "Changing the data is not supported with a data directory."
+            ));
+        }
+        // Make sure no refresh is running at the same time.
+        let _state_lock = timeout(Duration::from_secs(360), self.state.lock())
+            .await
+            .map_err(|_| anyhow!("Timed out waiting for the data refresh to finish."))?;
+        let repo_path = repo_path(&self.dataset);
+        let previous_meta = self.meta.lock().await.borrow().clone();
+        let old_commit = previous_meta.assets_commit.clone();
+        let previous_commit = Repository::open(&repo_path)?.head()?.peel_to_commit()?.id();
+
+        let result = async {
+            let dataset = self.dataset.clone();
+            let path = repo_path.clone();
+            let message = message.to_string();
+            let commit = tokio::task::spawn_blocking(move || {
+                let paths = f(&path)?;
+                commit_paths(&dataset, &path, &paths, &message)
+            })
+            .await??;
+            let new_data = refresh_data_internal(
+                &self.dataset,
+                &self.meta,
+                &RefreshMode::Local,
+                &self.quarantine,
+                None,
+            )
+            .await?;
+            if self.dataset.push_submissions() {
+                let dataset = self.dataset.clone();
+                let path = repo_path.clone();
+                tokio::task::spawn_blocking(move || push_head(&dataset, &path)).await??;
+            }
+            Ok::<_, Error>((commit, new_data))
+        }
+        .await;
+
+        match result {
+            Ok((commit, new_data)) => {
+                self.install_data(new_data, &old_commit).await;
+                Ok(commit)
+            }
+            Err(e) => {
+                if let Err(reset_e) = reset_repo(&repo_path, previous_commit) {
+                    error!(
+                        "Failed to reset the repository after a failed change: {}",
+                        reset_e
+                    );
                 }
+                *self.meta.lock().await.borrow_mut() = previous_meta;
+                Err(e)
             }
-            Err(_) => warn!("BUG: State lock could not be acquired in SpriteCollab::refresh!"),
         }
     }
 
+    /// Searches the history of the checkout for a commit whose message contains `line`.
+    pub async fn find_commit_with_line(&self, line: &str) -> Result<Option<String>, Error> {
+        let repo_path = repo_path(&self.dataset);
+        let line = line.to_string();
+        tokio::task::spawn_blocking(move || {
+            let repo = Repository::open(&repo_path)?;
+            let mut revwalk = repo.revwalk()?;
+            revwalk.push_head()?;
+            for oid in revwalk {
+                let commit = repo.find_commit(oid?)?;
+                if commit
+                    .message()
+                    .is_some_and(|message| message.lines().any(|l| l == line))
+                {
+                    return Ok(Some(commit.id().to_string()));
+                }
+            }
+            Ok(None)
+        })
+        .await?
+    }
+
+    /// Replaces the current data. If the data or commit changed, the cached values of the old
+    /// data are removed, webhooks are sent and the changed forms are warmed up.
+    async fn install_data(&self, new_data: SpriteCollabData, old_commit: &str) {
+        let new_commit = self.meta.lock().await.borrow().assets_commit.clone();
+        let old_tracker;
+        let new_tracker = new_data.tracker.clone();
+        let changed;
+        let old_root;
+        {
+            let mut lock_data = self.current_data.write().unwrap();
+            changed = lock_data.deref() != &new_data || old_commit != new_commit;
+            old_tracker = lock_data.tracker.clone();
+            old_root = lock_data.root.clone();
+            *lock_data = new_data;
+        }
+        if changed {
+            let prefix = self.cache_prefix(old_root.commit());
+            if let Err(e) = self.cache.delete_prefix(&prefix, None).await {
+                warn!("Failed removing the cached values of the old data: {:?}", e);
+            }
+            let changes = diff_trackers(&old_tracker, &new_tracker);
+            self.webhooks
+                .data_refreshed(old_commit, &new_commit, &changes)
+                .await;
+            self.jobs.trigger(WEBHOOKS_JOB, "data refresh");
+            self.queue_warmup(WarmupScope::Forms(changes), "data refresh");
+        }
+    }
+
+    /// Renders the assets of the forms in the scope in the background.
+    pub fn queue_warmup(&self, scope: WarmupScope, triggered_by: &str) {
+        if self.dataset.warmup_workers() == 0 {
+            return;
+        }
+        {
+            let mut warmup = self.warmup.lock().unwrap();
+            *warmup = Some(match warmup.take() {
+                Some(queued) => queued.merge(scope),
+                None => scope,
+            });
+        }
+        self.jobs.trigger(WARMUP_JOB, triggered_by);
+    }
+
+    /// Takes the forms queued for the cache warmup.
+    pub fn take_warmup(&self) -> Option<WarmupScope> {
+        self.warmup.lock().unwrap().take()
+    }
+
+    pub fn dataset(&self) -> &Dataset {
+        &self.dataset
+    }
+
+    pub fn webhooks(&self) -> Arc<Webhooks> {
+        self.webhooks.clone()
+    }
+
+    pub fn jobs(&self) -> &Jobs {
+        &self.jobs
+    }
+
+    pub fn stats(&self) -> &StatsHistory {
+        &self.stats
+    }
+
+    pub fn submissions(&self) -> &SubmissionStore {
+        &self.submissions
+    }
+
     pub fn data(&self) -> RwLockReadGuard<'_, SpriteCollabData> {
         self.current_data.read().unwrap()
     }
@@ -208,6 +573,7 @@ impl ScCache for SpriteCollab {
     async fn cached_may_fail<S, Fn, Ft, T, E>(
         &self,
         cache_key: S,
+        commit: &str,
         func: Fn,
     ) -> Result<Result<T, E>, Self::Error>
     where
@@ -217,60 +583,184 @@ impl ScCache for SpriteCollab {
         T: DeserializeOwned + Serialize + Send + Sync,
         E: Send,
     {
-        let red_val: Option<String> = self.redis.get(cache_key.as_ref()).await?;
-        if let Some(red_val) = red_val {
-            Ok(Ok(serde_json::from_str(&red_val)?))
-        } else {
-            match func().await {
-                Ok(CacheBehaviour::Cache(v)) => {
-                    let save_string = serde_json::to_string(&v);
-                    match save_string {
-                        Ok(save_string) => {
-                            let r: Result<(), fred::prelude::Error> = self
-                                .redis
-                                .set(cache_key.as_ref(), save_string, None, None, false)
-                                .await;
-                            if let Err(err) = r {
-                                warn!(
-                                    "Failed writing cache entry for '{}' to Redis (stage 2): {:?}",
-                                    cache_key.as_ref(),
-                                    err
-                                );
-                            }
-                        }
-                        Err(err) => {
+        self.cached_encoded(
+            cache_key.as_ref(),
+            commit,
+            func,
+            CacheBackend::encode_json,
+            CacheBackend::decode_json,
+        )
+        .await
+    }
+
+    async fn cached_bytes_may_fail<S, Fn, Ft, E>(
+        &self,
+        cache_key: S,
+        commit: &str,
+        func: Fn,
+    ) -> Result<Result<Vec<u8>, E>, Self::Error>
+    where
+        S: AsRef<str> + Into<Key> + Send + Sync,
+        Fn: (FnOnce() -> Ft) + Send,
+        Ft: Future<Output = Result<CacheBehaviour<Vec<u8>>, E>> + Send,
+        E: Send,
+    {
+        let cache_key = cache_key.as_ref();
+        self.cached_encoded(
+            cache_key,
+            commit,
+            || async {
+                if let Some(value) = self.disk_cache.get(commit, cache_key).await {
+                    return Ok(CacheBehaviour::Cache(value));
+                }
+                let result = func().await;
+                if let Ok(CacheBehaviour::Cache(value)) = &result {
+                    self.disk_cache.put(commit, cache_key, value).await;
+                }
+                result
+            },
+            |cache, value| cache.encode_bytes(value),
+            CacheBackend::decode_bytes,
+        )
+        .await
+    }
+}
+
+impl SpriteCollab {
+    /// Keys of values computed from the data of `commit`.
+    fn cache_prefix(&self, commit: &str) -> String {
+        format!("{}{}|", self.dataset.cache_namespace(), commit)
+    }
+
+    async fn cached_encoded<Fn, Ft, T, E>(
+        &self,
+        cache_key: &str,
+        commit: &str,
+        func: Fn,
+        encode: fn(&CacheBackend, &T) -> Result<Vec<u8>, Error>,
+        decode: fn(&[u8]) -> Result<T, Error>,
+    ) -> Result<Result<T, E>, Error>
+    where
+        Fn: (FnOnce() -> Ft) + Send,
+        Ft: Future<Output = Result<CacheBehaviour<T>, E>> + Send,
+        T: Send + Sync,
+        E: Send,
+    {
+        let key = format!("{}{}", self.cache_prefix(commit), cache_key);
+        if let Some(cached) = self.cache.get(&key).await? {
+            return Ok(Ok(decode(&cached)?));
+        }
+        // Only one caller computes the value, the others get it from the cache once it's done.
+        // If it couldn't be computed or isn't cached, the next caller tries.
+        let lock = self.cache.lock(&key).await;
+        let result = self
+            .compute_cached(&key, cache_key, func, encode, decode)
+            .await;
+        self.cache.unlock(lock).await;
+        result
+    }
+
+    /// Looks the value up again, and computes and caches it on miss. Called with the lock of
+    /// the key held.
+    async fn compute_cached<Fn, Ft, T, E>(
+        &self,
+        key: &str,
+        cache_key: &str,
+        func: Fn,
+        encode: fn(&CacheBackend, &T) -> Result<Vec<u8>, Error>,
+        decode: fn(&[u8]) -> Result<T, Error>,
+    ) -> Result<Result<T, E>, Error>
+    where
+        Fn: (FnOnce() -> Ft) + Send,
+        Ft: Future<Output = Result<CacheBehaviour<T>, E>> + Send,
+        T: Send + Sync,
+        E: Send,
+    {
+        if let Some(cached) = self.cache.get(key).await? {
+            return Ok(Ok(decode(&cached)?));
+        }
+        match func().await {
+            Ok(CacheBehaviour::Cache(v)) => {
+                match encode(&self.cache, &v) {
+                    Ok(encoded) => {
+                        let r = self.cache.set(key, key_category(cache_key), encoded).await;
+                        if let Err(err) = r {
                             warn!(
-                                "Failed writing cache entry for '{}' to Redis (stage 1): {:?}",
-                                cache_key.as_ref(),
-                                err
+                                "Failed writing cache entry for '{}' to Redis (stage 2): {:?}",
+                                cache_key, err
                             );
                         }
                     }
-                    Ok(Ok(v))
+                    Err(err) => {
+                        warn!(
+                            "Failed writing cache entry for '{}' to Redis (stage 1): {:?}",
+                            cache_key, err
+                        );
+                    }
                 }
-                Ok(CacheBehaviour::NoCache(v)) => Ok(Ok(v)),
-                Err(e) => Ok(Err(e)),
+                Ok(Ok(v))
             }
+            Ok(CacheBehaviour::NoCache(v)) => Ok(Ok(v)),
+            Err(e) => Ok(Err(e)),
         }
     }
 }
 
-async fn refresh_data(meta: &Mutex<RefCell<Meta>>) -> Option<SpriteCollabData> {
-    debug!("Refreshing data...");
-    match refresh_data_internal(meta, true).await {
-        Ok(v) => Some(v),
-        Err(e) => {
-            error!("Error refreshing data: {}. Gave up.", e);
-            None
+/// Path to the clone of the SpriteCollab repository of the dataset.
+pub fn repo_path(dataset: &Dataset) -> PathBuf {
+    dataset.workdir().join(GIT_REPO_DIR)
+}
+
+/// The refresh mode for scheduled refreshes: Update, unless pinned.
+fn refresh_mode(pin: Option<&Pin>) -> RefreshMode {
+    match pin {
+        Some(pin) => RefreshMode::Checkout(pin.commit.clone()),
+        None => RefreshMode::Update,
+    }
+}
+
+/// Goes back in the history of the checkout until the data can be read, for at most
+/// `SCSRV_MAX_ROLLBACK` commits. Commits that fail are quarantined, quarantined commits are skipped.
+async fn checkout_previous_valid_commit(
+    dataset: &Dataset,
+    meta: &Mutex<RefCell<Meta>>,
+    repo_path: &Path,
+    quarantine: &Arc<Quarantine>,
+) -> Result<SpriteCollabData, Error> {
+    let max_rollback = dataset.max_rollback();
+    for _ in 0..max_rollback {
+        let new_commit = try_checkout_previous_commit(repo_path)?;
+        if quarantine.contains(&new_commit) {
+            debug!("Skipping quarantined commit {}.", new_commit);
+            continue;
+        }
+        warn!("Checked out old commit: {}", new_commit);
+        if let Err(e) = verify_head(dataset, repo_path) {
+            quarantine.add(&new_commit, &e.to_string());
+            continue;
+        }
+        match refresh_data_internal(dataset, meta, &RefreshMode::Local, quarantine, None).await {
+            Ok(value) => return Ok(value),
+            Err(e) => quarantine.add(&new_commit, &e.to_string()),
         }
     }
+    Err(anyhow!(
+        "Found no readable data in the last {} commits (SCSRV_MAX_ROLLBACK).",
+        max_rollback
+    ))
 }
 
+/// Updates the repository according to `mode` and reads the data from a snapshot of the checked
+/// out commit. `current_root` is reused if it already is a snapshot of that commit. If a data
+/// directory is configured, the data is read from it instead.
 async fn refresh_data_internal(
+    dataset: &Dataset,
     meta: &Mutex<RefCell<Meta>>,
-    update: bool,
+    mode: &RefreshMode,
+    quarantine: &Arc<Quarantine>,
+    current_root: Option<Arc<DataRoot>>,
 ) -> Result<SpriteCollabData, Error> {
-    match refresh_data_internal_do(meta, update).await {
+    match refresh_data_internal_do(dataset, meta, mode, quarantine, current_root).await {
         Ok(v) => Ok(v),
         Err(e) => {
             // Update at least the scan time
@@ -283,51 +773,117 @@ async fn refresh_data_internal(
 }
 
 async fn refresh_data_internal_do(
+    dataset: &Dataset,
     meta: &Mutex<RefCell<Meta>>,
-    update: bool,
+    mode: &RefreshMode,
+    quarantine: &Arc<Quarantine>,
+    current_root: Option<Arc<DataRoot>>,
 ) -> Result<SpriteCollabData, Error> {
-    let repo_path = PathBuf::from(Config::Workdir.get()).join(GIT_REPO_DIR);
+    let (root, new_meta) = match dataset.data_dir() {
+        Some(data_dir) => read_data_dir(data_dir, mode, current_root).await?,
+        None => update_repo(dataset, mode, quarantine, current_root).await?,
+    };
+
+    let root_path = root.path();
+    let scd = SpriteCollabData::new(
+        read_and_report_error(&root_path.join("sprite_config.json"), read_sprite_config).await?,
+        read_and_report_error(&root_path.join("tracker.json"), read_tracker).await?,
+        read_and_report_error(&root_path.join("credit_names.txt"), read_credit_names).await?,
+        root.clone(),
+    );
+
+    // Also try to recursively read in all AnimData.xml files, for validation.
+    try_read_in_anim_data_xml(root_path, &scd.tracker).await?;
+
+    // Update metadata
+    let meta_acq = meta.lock().await;
+    let mut meta_brw = meta_acq.try_borrow_mut()?;
+    *meta_brw = new_meta;
+
+    Ok(scd)
+}
+
+/// Updates the repository according to `mode` and creates a snapshot of the checked out commit,
+/// unless `current_root` already is one. Git and the snapshot block, so this runs on the blocking
+/// thread pool.
+async fn update_repo(
+    dataset: &Dataset,
+    mode: &RefreshMode,
+    quarantine: &Arc<Quarantine>,
+    current_root: Option<Arc<DataRoot>>,
+) -> Result<(Arc<DataRoot>, Meta), Error> {
+    let dataset = dataset.clone();
+    let mode = mode.clone();
+    let quarantine = quarantine.clone();
+    tokio::task::spawn_blocking(move || {
+        update_repo_blocking(&dataset, &mode, &quarantine, current_root)
+    })
+    .await?
+}
+
+fn update_repo_blocking(
+    dataset: &Dataset,
+    mode: &RefreshMode,
+    quarantine: &Quarantine,
+    current_root: Option<Arc<DataRoot>>,
+) -> Result<(Arc<DataRoot>, Meta), Error> {
+    let repo_path = repo_path(dataset);
+    let previous_head = head_commit(&repo_path).and_then(|c| Oid::from_str(&c).ok());
     let repo;
     if repo_path.exists() {
-        if update {
-            match try_update_repo(&repo_path) {
+        match mode {
+            RefreshMode::Update => match try_update_repo(dataset, &repo_path, quarantine) {
                 Ok(v) => repo = Some(v),
                 Err(clone_e) => {
+                    // Cloning again would lose them.
+                    if let Some(head) = unpushed_head(dataset, &repo_path) {
+                        return Err(anyhow!(
+                            "Failed to update repo: {}. Not cloning it again, HEAD {} has commits the remote lacks, eg. approved submissions that weren't pushed yet. Push or remove them manually.",
+                            clone_e,
+                            head
+                        ));
+                    }
                     // If this fails, throw the repo away (if applicable) and clone it new.
                     warn!(
                         "Failed to update repo, deleting and cloning it again: {}",
                         clone_e
                     );
-                    if let Err(e) = remove_dir_all(&repo_path).await {
+                    if let Err(e) = std::fs::remove_dir_all(&repo_path) {
                         warn!("Failed to delete repo directory: {}", e);
                     }
-                    repo = Some(create_repo(&repo_path, &Config::GitRepo.get())?);
+                    repo = Some(git_remote::clone(dataset, &repo_path)?);
                 }
+            },
+            RefreshMode::Checkout(rev) => {
+                repo = Some(checkout_rev(dataset, &repo_path, rev)?);
             }
-        } else {
-            if !repo_path.join(".git").exists() {
-                return Err(anyhow!("Missing .git directory"));
+            RefreshMode::Local => {
+                if !repo_path.join(".git").exists() {
+                    return Err(anyhow!("Missing .git directory"));
+                }
+                repo = Some(Repository::open(&repo_path)?);
             }
-            repo = Some(Repository::open(&repo_path)?);
         }
     } else {
-        create_dir_all(&repo_path).await?;
-        repo = Some(create_repo(&repo_path, &Config::GitRepo.get())?);
+        std::fs::create_dir_all(&repo_path)?;
+        let cloned = git_remote::clone(dataset, &repo_path)?;
+        repo = Some(match mode {
+            RefreshMode::Checkout(rev) => checkout_rev(dataset, &repo_path, rev)?,
+            _ => cloned,
+        });
     }
 
-    let scd = SpriteCollabData::new(
-        read_and_report_error(&repo_path.join("sprite_config.json"), read_sprite_config).await?,
-        read_and_report_error(&repo_path.join("tracker.json"), read_tracker).await?,
-        read_and_report_error(&repo_path.join("credit_names.txt"), read_credit_names).await?,
-    );
-
-    // Also try to recursively read in all AnimData.xml files, for validation.
-    try_read_in_anim_data_xml(&scd.tracker).await?;
-
-    // Update metadata
-    let meta_acq = meta.lock().await;
-    let mut meta_brw = meta_acq.try_borrow_mut()?;
-    let commit = repo.as_ref().unwrap().head()?.peel_to_commit()?;
+    let repo = repo.as_ref().unwrap();
+    let commit = repo.head()?.peel_to_commit()?;
+    if let RefreshMode::Update = mode {
+        // The previous head may be gone if the repository was cloned again.
+        let previous = previous_head.filter(|c| repo.find_commit(*c).is_ok());
+        verify_new_commits(dataset, repo, previous, commit.id())?;
+    }
+    let root = match current_root {
+        Some(root) if root.commit() == commit.id().to_string() => root,
+        _ => Arc::new(DataRoot::create(dataset, repo, &commit)?),
+    };
     let commit_time_raw = commit.time();
     let commit_time = FixedOffset::east_opt(commit_time_raw.offset_minutes() * 60)
         .unwrap()
@@ -337,19 +893,53 @@ async fn refresh_data_internal_do(
                 .naive_utc(),
         )
         .unwrap();
-
-    *meta_brw = Meta {
+    let new_meta = Meta {
         assets_commit: commit.id().to_string(),
         assets_update_date: Utc.from_utc_datetime(&commit_time.naive_utc()),
         update_checked_date: Utc::now(),
     };
+    Ok((root, new_meta))
+}
 
-    Ok(scd)
+/// Hashes the content of the data directory. `current_root` is reused if the content didn't
+/// change.
+async fn read_data_dir(
+    data_dir: PathBuf,
+    mode: &RefreshMode,
+    current_root: Option<Arc<DataRoot>>,
+) -> Result<(Arc<DataRoot>, Meta), Error> {
+    if let RefreshMode::Checkout(_) = mode {
+        return Err(anyhow!(
+            "Checking out commits is not supported with a data directory."
+        ));
+    }
+    let path = data_dir.clone();
+    let content = tokio::task::spawn_blocking(move || content_hash(&path)).await??;
+    let root = match current_root {
+        Some(root) if root.commit() == content.hash => root,
+        _ => Arc::new(DataRoot::external(data_dir, content.hash.clone())),
+    };
+    let new_meta = Meta {
+        assets_commit: content.hash,
+        assets_update_date: content.modified,
+        update_checked_date: Utc::now(),
+    };
+    Ok((root, new_meta))
 }
 
 fn try_checkout_previous_commit(path: &Path) -> Result<String, Error> {
     let repo = Repository::open(path)?;
-    let reference = repo.head()?.peel_to_commit()?.parent(0)?;
+    let head = repo.head()?.peel_to_commit()?;
+    let reference = match head.parent(0) {
+        Ok(parent) => parent,
+        Err(_) if repo.is_shallow() => {
+            return Err(anyhow!(
+                "Can't check out the commit before {}, it is not part of the shallow clone. Increase SCSRV_GIT_DEPTH or remove the repository in the workdir to clone it again.",
+                head.id()
+            ));
+        }
+        Err(e) => return Err(e.into()),
+    };
     let name = reference.id().to_string();
     repo.reset(
         reference.as_object(),
@@ -359,22 +949,161 @@ fn try_checkout_previous_commit(path: &Path) -> Result<String, Error> {
     Ok(name)
 }
 
-fn try_update_repo(path: &Path) -> Result<Repository, Error> {
+/// Checks the signature of the commit currently checked out, if a trust policy is set.
+fn verify_head(dataset: &Dataset, path: &Path) -> Result<(), Error> {
+    let repo = Repository::open(path)?;
+    let head = repo.head()?.peel_to_commit()?.id();
+    verify_new_commits(dataset, &repo, None, head)
+}
+
+/// Returns the commit currently checked out, if any.
+fn head_commit(path: &Path) -> Option<String> {
+    let repo = Repository::open(path).ok()?;
+    let commit = repo.head().ok()?.peel_to_commit().ok()?;
+    Some(commit.id().to_string())
+}
+
+/// Returns HEAD if it has commits that neither the remote-tracking branch nor the last fetch
+/// contain, as far as known locally. `None` if the repository can't be read.
+fn unpushed_head(dataset: &Dataset, path: &Path) -> Option<Oid> {
+    let repo = Repository::open(path).ok()?;
+    let head = repo.head().ok()?.peel_to_commit().ok()?.id();
+    let remote_refs = [
+        format!("refs/remotes/origin/{}", tracked_branch(dataset)),
+        "FETCH_HEAD".to_string(),
+    ];
+    let pushed = remote_refs.iter().any(|name| {
+        repo.find_reference(name)
+            .and_then(|r| r.peel_to_commit())
+            .is_ok_and(|remote| {
+                remote.id() == head || repo.graph_descendant_of(remote.id(), head).unwrap_or(false)
+            })
+    });
+    (!pushed).then_some(head)
+}
+
+fn try_update_repo(
+    dataset: &Dataset,
+    path: &Path,
+    quarantine: &Quarantine,
+) -> Result<Repository, Error> {
     if !path.join(".git").exists() {
         return Err(anyhow!("Missing .git directory"));
     }
     let repo = Repository::open(path)?;
-    let mut remote = repo.find_remote("origin")?;
-    remote.fetch(&["master"], None, None)?;
+    fetch_tracked_branch(dataset, &repo)?;
     let reference = repo.find_reference("FETCH_HEAD")?;
+    let fetched = reference.peel_to_commit()?.id();
+    if quarantine.contains(&fetched.to_string()) {
+        debug!("Newest commit {} is quarantined, not updating.", fetched);
+        return Ok(Repository::open(path)?);
+    }
+    if let Some(head) = repo.head().ok().and_then(|h| h.peel_to_commit().ok()) {
+        let head = head.id();
+        if head != fetched {
+            if repo.graph_descendant_of(head, fetched)? {
+                // Approved submissions that were not pushed (yet). Keep them.
+                debug!("Local commits are ahead of the remote, not updating.");
+                return Ok(Repository::open(path)?);
+            } else if !repo.graph_descendant_of(fetched, head)? {
+                return Err(anyhow!(
+                    "Local commits diverged from the remote. Local HEAD is {}, remote is {}.",
+                    head,
+                    fetched
+                ));
+            }
+        }
+    }
     repo.set_head(reference.name().unwrap())?;
     repo.checkout_head(Some(CheckoutBuilder::default().force()))?;
     Ok(Repository::open(path)?) // libgit2's borrowing code is a bit dumb
 }
 
-fn create_repo(path: &Path, clone_url: &str) -> Result<Repository, Error> {
-    info!("Cloning SpriteCollab repo...");
-    let repo = Repository::clone(clone_url, path)?;
-    info!("Cloning SpriteCollab repo. Done!");
-    Ok(repo)
+/// Checks out the given revision (detached), fetching the tracked branch first if the revision
+/// is not known.
+fn checkout_rev(dataset: &Dataset, path: &Path, rev: &str) -> Result<Repository, Error> {
+    if !path.join(".git").exists() {
+        return Err(anyhow!("Missing .git directory"));
+    }
+    let repo = Repository::open(path)?;
+    let commit = match repo.revparse_single(rev) {
+        Ok(object) => object.peel_to_commit()?,
+        Err(_) => {
+            fetch_tracked_branch(dataset, &repo)?;
+            repo.revparse_single(rev)?.peel_to_commit()?
+        }
+    };
+    repo.set_head_detached(commit.id())?;
+    repo.checkout_head(Some(CheckoutBuilder::default().force()))?;
+    Ok(Repository::open(path)?)
+}
+
+fn reset_repo(path: &Path, commit: Oid) -> Result<(), Error> {
+    let repo = Repository::open(path)?;
+    let commit = repo.find_commit(commit)?;
+    repo.reset(
+        commit.as_object(),
+        ResetType::Hard,
+        Some(CheckoutBuilder::default().force().remove_untracked(true)),
+    )?;
+    Ok(())
+}
+
+/// Commits the given paths (relative to the repository) on top of HEAD.
+fn commit_paths(
+    dataset: &Dataset,
+    path: &Path,
+    paths: &[PathBuf],
+    message: &str,
+) -> Result<String, Error> {
+    let repo = Repository::open(path)?;
+    let mut index = repo.index()?;
+    index.add_all(paths, IndexAddOption::DEFAULT, None)?;
+    // Also stages removed files.
+    index.update_all(paths, None)?;
+    index.write()?;
+    let tree = repo.find_tree(index.write_tree()?)?;
+    let parent = repo.head()?.peel_to_commit()?;
+    let signature = Signature::now(
+        &dataset.get(Config::GitAuthorName),
+        &dataset.get(Config::GitAuthorEmail),
+    )?;
+    let commit = repo.commit(
+        Some("HEAD"),
+        &signature,
+        &signature,
+        message,
+        &tree,
+        &[&parent],
+    )?;
+    Ok(commit.to_string())
+}
+
+/// Pushes HEAD to the tracked branch of `origin`.
+fn push_head(dataset: &Dataset, path: &Path) -> Result<(), Error> {
+    let repo = Repository::open(path)?;
+    let head = repo.head()?.peel_to_commit()?.id();
+    let branch_ref = format!("refs/heads/{}", tracked_branch(dataset));
+    repo.reference(&branch_ref, head, true, "push")?;
+    let mut remote = repo.find_remote("origin")?;
+    let mut rejection = None;
+    {
+        let mut callbacks = remote_callbacks(dataset);
+        callbacks.push_update_reference(|refname, status| {
+            if let Some(status) = status {
+                rejection = Some(format!("{}: {}", refname, status));
+            }
+            Ok(())
+        });
+        let mut options = PushOptions::new();
+        options.remote_callbacks(callbacks);
+        remote.push(
+            &[format!("{}:{}", branch_ref, branch_ref)],
+            Some(&mut options),
+        )?;
+    }
+    match rejection {
+        Some(reason) => Err(anyhow!("The remote rejected the push: {}", reason)),
+        None => Ok(()),
+    }
 }
@@ -0,0 +1,138 @@
+//! Periodic snapshots of statistics about the data of a dataset, persisted in its workdir.
+
+use std::collections::VecDeque;
+use std::fs::{read, rename, write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::Error;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use log::error;
+use serde::{Deserialize, Serialize};
+
+use crate::config::Dataset;
+use crate::datafiles::tracker::Group;
+use crate::jobs::{Job, STATS_JOB};
+use crate::sprite_collab::SpriteCollab;
+
+const STATS_FILE: &str = "stats.json";
+/// Number of snapshots kept.
+const HISTORY_SIZE: usize = 1000;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StatsSnapshot {
+    pub date: DateTime<Utc>,
+    /// The commit that was served.
+    pub commit: String,
+    pub monsters: usize,
+    pub forms: usize,
+    /// Number of portrait files, including flipped portraits.
+    pub portraits: usize,
+    /// Number of sprite actions.
+    pub sprites: usize,
+    pub credits: usize,
+    pub pending_submissions: usize,
+}
+
+impl StatsSnapshot {
+    pub async fn take(sprite_collab: &SpriteCollab) -> Result<Self, Error> {
+        let commit = sprite_collab
+            .with_meta(|meta| meta.map(|meta| meta.assets_commit.clone()))
+            .await?;
+        let pending_submissions = sprite_collab
+            .submissions()
+            .list()?
+            .into_iter()
+            .filter(|s| s.is_pending())
+            .count();
+        let data = sprite_collab.data();
+        let (mut forms, mut portraits, mut sprites) = (0, 0, 0);
+        let mut groups: Vec<&Group> = data.tracker.values().collect();
+        while let Some(group) = groups.pop() {
+            forms += 1;
+            portraits += group.portrait_files.len();
+            sprites += group.sprite_files.len();
+            groups.extend(group.subgroups.values());
+        }
+        Ok(Self {
+            date: Utc::now(),
+            commit,
+            monsters: data.tracker.len(),
+            forms,
+            portraits,
+            sprites,
+            credits: data.credit_names.iter().count(),
+            pending_submissions,
+        })
+    }
+}
+
+pub struct StatsHistory {
+    path: PathBuf,
+    snapshots: Mutex<VecDeque<StatsSnapshot>>,
+}
+
+impl StatsHistory {
+    pub fn load(dataset: &Dataset) -> Self {
+        let path = dataset.workdir().join(STATS_FILE);
+        let snapshots = if path.exists() {
+            read(&path)
+                .map_err(Error::from)
+                .and_then(|content| Ok(serde_json::from_slice(&content)?))
+                .unwrap_or_else(|e| {
+                    error!("Failed reading the statistics, discarding them: {}", e);
+                    VecDeque::new()
+                })
+        } else {
+            VecDeque::new()
+        };
+        Self {
+            path,
+            snapshots: Mutex::new(snapshots),
+        }
+    }
+
+    pub fn record(&self, snapshot: StatsSnapshot) -> Result<(), Error> {
+        let mut snapshots = self.snapshots.lock().unwrap();
+        if snapshots.len() >= HISTORY_SIZE {
+            snapshots.pop_front();
+        }
+        snapshots.push_back(snapshot);
+        let tmp_path = self.path.with_extension("json.tmp");
+        write(&tmp_path, serde_json::to_vec(&*snapshots)?)?;
+        rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+
+    /// Returns the snapshots, newest first.
+    pub fn list(&self) -> Vec<StatsSnapshot> {
+        self.snapshots
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .cloned()
+            .collect()
+    }
+}
+
+/// Records a statistics snapshot.
+pub struct StatsJob(pub Arc<SpriteCollab>);
+
+#[async_trait]
+impl Job for StatsJob {
+    fn name(&self) -> &'static str {
+        STATS_JOB
+    }
+
+    fn interval(&self) -> Option<Duration> {
+        Some(self.0.dataset().stats_interval())
+    }
+
+    async fn run(&self, _triggered_by: &str) -> Result<(), Error> {
+        let snapshot = StatsSnapshot::take(&self.0).await?;
+        self.0.stats().record(snapshot)
+    }
+}
@@ -0,0 +1,243 @@
+//! Writes approved submissions into the SpriteCollab checkout, the same way SpriteBot does:
+//! The files are added to the form's directory, a line is added to its `credits.txt`, the
+//! form's entry in `tracker.json` is updated and new authors are added to `credit_names.txt`.
+
+use std::fs::{OpenOptions, create_dir_all, read, read_dir, read_to_string, remove_file, write};
+use std::io::{Cursor, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::anyhow;
+use chrono::Utc;
+use serde::Serialize;
+use serde_json::ser::PrettyFormatter;
+use serde_json::{Map, Value};
+
+use crate::assets::util::join_monster_and_form;
+use crate::datafiles::anim_data_xml::AnimDataXml;
+use crate::datafiles::local_credits_file::LocalCreditRow;
+use crate::datafiles::parse_credit_id;
+use crate::submissions::store::{Submission, SubmissionKind};
+use crate::submissions::validation::{read_zip, referenced_files};
+
+const TRACKER_FILE: &str = "tracker.json";
+const CREDITS_FILE: &str = "credits.txt";
+const CREDIT_NAMES_FILE: &str = "credit_names.txt";
+
+/// Writes the submission into the checkout at `repo_path`. Returns the paths (relative to the
+/// repository) that were changed.
+pub fn apply_submission(
+    repo_path: &Path,
+    submission: &Submission,
+    data: &[u8],
+) -> Result<Vec<PathBuf>, anyhow::Error> {
+    let rel_dir = PathBuf::from(submission.kind.dir_name()).join(join_monster_and_form(
+        submission.monster_idx,
+        &submission.form_path,
+        '/',
+    ));
+    let dir = repo_path.join(&rel_dir);
+    create_dir_all(&dir)?;
+
+    let (items, tracker_files) = match submission.kind {
+        SubmissionKind::Portrait => {
+            let emotion = submission
+                .emotion
+                .as_deref()
+                .ok_or_else(|| anyhow!("Portrait submission without emotion."))?;
+            write(dir.join(format!("{}.png", emotion)), data)?;
+            (vec![emotion.to_string()], vec![emotion.to_string()])
+        }
+        SubmissionKind::Sprites => {
+            let files = read_zip(data)?;
+            let xml = AnimDataXml::from_reader(Cursor::new(
+                files
+                    .get("AnimData.xml")
+                    .ok_or_else(|| anyhow!("AnimData.xml is missing."))?,
+            ))?;
+            // The submission replaces all existing sprites. Subdirectories contain other forms.
+            for entry in read_dir(&dir)? {
+                let entry = entry?;
+                if entry.file_type()?.is_file() && entry.file_name() != CREDITS_FILE {
+                    remove_file(entry.path())?;
+                }
+            }
+            // Like the validation report says, files AnimData.xml doesn't use are ignored.
+            for file_name in referenced_files(&xml) {
+                if let Some(content) = files.get(&file_name) {
+                    write(dir.join(&file_name), content)?;
+                }
+            }
+            let actions: Vec<String> = xml.anims.anim.iter().map(|a| a.name.clone()).collect();
+            let items = xml
+                .anims
+                .anim
+                .iter()
+                .filter(|a| a.copy_of.is_none())
+                .map(|a| a.name.clone())
+                .collect();
+            (items, actions)
+        }
+    };
+
+    append_credits(&dir.join(CREDITS_FILE), submission, items)?;
+    update_tracker(&repo_path.join(TRACKER_FILE), submission, tracker_files)?;
+    add_credit_name(&repo_path.join(CREDIT_NAMES_FILE), submission)?;
+
+    Ok(vec![
+        rel_dir,
+        PathBuf::from(TRACKER_FILE),
+        PathBuf::from(CREDIT_NAMES_FILE),
+    ])
+}
+
+fn append_credits(
+    path: &Path,
+    submission: &Submission,
+    items: Vec<String>,
+) -> Result<(), anyhow::Error> {
+    let row = LocalCreditRow {
+        date: Utc::now(),
+        credit_id: submission.credit_id.clone(),
+        obsolete: false,
+        license: submission.license.clone(),
+        items,
+    };
+    let needs_line_break = path.exists() && {
+        let existing = read_to_string(path)?;
+        !existing.is_empty() && !existing.ends_with('\n')
+    };
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    if needs_line_break {
+        file.write_all(b"\n")?;
+    }
+    file.write_all(format!("{}\n", row.to_line()).as_bytes())?;
+    Ok(())
+}
+
+/// Adds the credit ID of the submission to the credit names, if it is not listed yet.
+/// The submitter's name is used as the name.
+fn add_credit_name(path: &Path, submission: &Submission) -> Result<(), anyhow::Error> {
+    let content = read_to_string(path)?;
+    let mut lines = content.lines();
+    let header: Vec<&str> = lines
+        .next()
+        .ok_or_else(|| anyhow!("Invalid credit names file."))?
+        .split('\t')
+        .collect();
+    let id_column = header
+        .iter()
+        .position(|column| *column == "Discord")
+        .ok_or_else(|| anyhow!("Invalid credit names file."))?;
+    let credit_id = parse_credit_id(&submission.credit_id);
+    let exists = lines.any(|line| {
+        line.split('\t')
+            .nth(id_column)
+            .map(|id| parse_credit_id(id) == credit_id)
+            .unwrap_or_default()
+    });
+    if exists {
+        return Ok(());
+    }
+    let row = header
+        .iter()
+        .map(|column| match *column {
+            "Discord" => submission.credit_id.as_str(),
+            "Name" => submission.submitter.as_str(),
+            _ => "",
+        })
+        .collect::<Vec<_>>()
+        .join("\t");
+    let mut file = OpenOptions::new().append(true).open(path)?;
+    if !content.ends_with('\n') {
+        file.write_all(b"\n")?;
+    }
+    file.write_all(format!("{}\n", row).as_bytes())?;
+    Ok(())
+}
+
+/// Updates the files, modification date and credits of the form in the tracker.
+/// The file is edited as plain JSON, so that fields this server doesn't know are kept.
+fn update_tracker(
+    path: &Path,
+    submission: &Submission,
+    files: Vec<String>,
+) -> Result<(), anyhow::Error> {
+    let mut tracker: Value = serde_json::from_slice(&read(path)?)?;
+    let group = find_group_mut(&mut tracker, submission.monster_idx, &submission.form_path)
+        .ok_or_else(|| anyhow!("The form does not exist in the tracker."))?;
+    let prefix = submission.kind.dir_name();
+
+    let tracker_files = group
+        .get_mut(&format!("{}_files", prefix))
+        .and_then(Value::as_object_mut)
+        .ok_or_else(|| anyhow!("Invalid tracker entry."))?;
+    match submission.kind {
+        SubmissionKind::Portrait => {
+            for file in files {
+                tracker_files.entry(file).or_insert(Value::Bool(false));
+            }
+        }
+        SubmissionKind::Sprites => {
+            // Keep the lock state of actions that already existed.
+            let old = std::mem::take(tracker_files);
+            for file in files {
+                let locked = old.get(&file).cloned().unwrap_or(Value::Bool(false));
+                tracker_files.insert(file, locked);
+            }
+        }
+    }
+
+    group.insert(
+        format!("{}_modified", prefix),
+        Value::String(Utc::now().format("%Y-%m-%d %H:%M:%S%.6f").to_string()),
+    );
+
+    if let Some(credit) = group
+        .get_mut(&format!("{}_credit", prefix))
+        .and_then(Value::as_object_mut)
+    {
+        let primary = credit
+            .get("primary")
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+        if primary.is_empty() {
+            credit.insert(
+                "primary".to_string(),
+                Value::String(submission.credit_id.clone()),
+            );
+        } else if primary != submission.credit_id
+            && let Some(secondary) = credit.get_mut("secondary").and_then(Value::as_array_mut)
+        {
+            let credit_id = Value::String(submission.credit_id.clone());
+            if !secondary.contains(&credit_id) {
+                secondary.push(credit_id);
+            }
+        }
+    }
+
+    // SpriteBot writes the tracker with an indentation of four spaces.
+    let mut out = Vec::new();
+    let mut serializer =
+        serde_json::Serializer::with_formatter(&mut out, PrettyFormatter::with_indent(b"    "));
+    tracker.serialize(&mut serializer)?;
+    write(path, out)?;
+    Ok(())
+}
+
+fn find_group_mut<'a>(
+    tracker: &'a mut Value,
+    monster_idx: i32,
+    form_path: &[i32],
+) -> Option<&'a mut Map<String, Value>> {
+    let mut group = find_child_mut(tracker.as_object_mut()?, monster_idx)?;
+    for form_idx in form_path {
+        group = find_child_mut(group.get_mut("subgroups")?.as_object_mut()?, *form_idx)?;
+    }
+    Some(group)
+}
+
+fn find_child_mut(map: &mut Map<String, Value>, idx: i32) -> Option<&mut Map<String, Value>> {
+    map.iter_mut()
+        .find(|(key, _)| key.parse::<i32>().ok() == Some(idx))
+        .and_then(|(_, value)| value.as_object_mut())
+}
@@ -0,0 +1,153 @@
+//! Handling of candidate sprite and portrait submissions.
+
+use std::sync::Arc;
+
+use anyhow::Error;
+use http_body_util::Full;
+use hyper::body::{Bytes, Incoming};
+use hyper::{Method, Request, Response, StatusCode};
+use route_recognizer::Router;
+
+use crate::SpriteCollab;
+use crate::assets::util::force_non_shiny_group;
+use crate::assets::{AssetBody, make_box_body, make_err_response, read_body};
+use crate::config::Config;
+use crate::datafiles::tracker::{FormMatch, MonsterFormCollector};
+use crate::submissions::store::SubmissionKind;
+use crate::submissions::validation::{ValidationReport, validate_portrait, validate_sprite_zip};
+
+pub mod apply;
+pub mod store;
+pub mod validation;
+
+/// Matches the validation endpoints:
+///
+/// - `POST /validate/sprite/<monster>[/<form>...]` with a sprite ZIP as the body.
+/// - `POST /validate/portrait/<monster>[/<form>...]/<emotion>` with a portrait PNG as the body.
+///   Flipped portraits use the emotion name followed by `^`.
+///
+/// Responds with a JSON [`ValidationReport`].
+pub async fn match_and_process_validation_path(
+    req: Request<Incoming>,
+    sprite_collab: Arc<SpriteCollab>,
+) -> Option<Response<AssetBody>> {
+    if req.method() != Method::POST {
+        return None;
+    }
+    let path = req.uri().path().to_string();
+    let (kind, monster_idx, form_path, emotion) = match_validation_url(&path)?;
+    let body = match read_body(req.into_body()).await {
+        Ok(v) => v,
+        Err(response) => return Some(response),
+    };
+    match validate_submission(&sprite_collab, kind, monster_idx, &form_path, emotion, body).await {
+        Ok(report) => Some(make_report_response(&report?, &path)),
+        Err(e) => Some(make_err_response(e, &path).map(make_box_body)),
+    }
+}
+
+/// Validates a submission for the given form. Returns `None` if the form doesn't exist.
+/// Shiny forms are checked against the palette of their base form. Decoding the files runs on
+/// the blocking thread pool.
+pub async fn validate_submission(
+    sprite_collab: &SpriteCollab,
+    kind: SubmissionKind,
+    monster_idx: i32,
+    form_path: &[i32],
+    emotion: Option<String>,
+    data: Bytes,
+) -> Result<Option<ValidationReport>, Error> {
+    let sprite_config;
+    let tracker;
+    let root;
+    {
+        let data = sprite_collab.data();
+        sprite_config = data.sprite_config.clone();
+        tracker = data.tracker.clone();
+        root = data.root.clone();
+    }
+    let Some(collector) = MonsterFormCollector::collect(&tracker, monster_idx) else {
+        return Ok(None);
+    };
+    let Some((form_path, _, _)) =
+        collector.find_form(form_path.iter().copied().map(FormMatch::Exact))
+    else {
+        return Ok(None);
+    };
+    let base_form_path = if MonsterFormCollector::is_shiny(&form_path) {
+        collector
+            .find_form(
+                force_non_shiny_group(&form_path)
+                    .into_iter()
+                    .map(FormMatch::Exact),
+            )
+            .map(|(base_path, _, _)| base_path)
+    } else {
+        None
+    };
+
+    let report = tokio::task::spawn_blocking(move || match kind {
+        SubmissionKind::Sprites => validate_sprite_zip(
+            &data,
+            &sprite_config,
+            &root.sprite_dir(monster_idx, &form_path),
+            base_form_path
+                .map(|p| root.sprite_dir(monster_idx, &p))
+                .as_deref(),
+        ),
+        SubmissionKind::Portrait => validate_portrait(
+            &data,
+            emotion.as_deref().unwrap_or_default(),
+            &sprite_config,
+            &root.portrait_dir(monster_idx, &form_path),
+            base_form_path
+                .map(|p| root.portrait_dir(monster_idx, &p))
+                .as_deref(),
+        ),
+    })
+    .await?;
+    Ok(Some(report))
+}
+
+fn make_report_response(report: &ValidationReport, request_path: &str) -> Response<AssetBody> {
+    match serde_json::to_vec(report) {
+        Ok(json) => Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "application/json")
+            .header("Access-Control-Allow-Origin", Config::cors_origin())
+            .body(make_box_body(Full::new(Bytes::from(json))))
+            .unwrap_or_else(|e| make_err_response(e, request_path).map(make_box_body)),
+        Err(e) => make_err_response(e, request_path).map(make_box_body),
+    }
+}
+
+/// Returns the submission kind, monster ID, form path and (for portraits) emotion.
+fn match_validation_url(path: &str) -> Option<(SubmissionKind, i32, Vec<i32>, Option<String>)> {
+    let mut router = Router::new();
+    router.add("/validate/sprite/*formpath", SubmissionKind::Sprites);
+    router.add(
+        "/validate/portrait/*formpath/:emotion",
+        SubmissionKind::Portrait,
+    );
+    let m = router.recognize(path).ok()?;
+
+    let mut form_path = m
+        .params()
+        .find("formpath")?
+        .split('/')
+        .filter(|x| !x.is_empty())
+        .map(|x| x.parse::<i32>())
+        .collect::<Result<Vec<i32>, _>>()
+        .ok()?;
+    if form_path.is_empty() {
+        return None;
+    }
+    let monster_idx = form_path.remove(0);
+    // Only `^` needs to be decoded, emotion names don't contain any other special characters.
+    let emotion = m.params().find("emotion").map(|e| {
+        e.trim_end_matches(".png")
+            .replace("%5E", "^")
+            .replace("%5e", "^")
+    });
+    Some((**m.handler(), monster_idx, form_path, emotion))
+}
@@ -0,0 +1,214 @@
+//! On-disk storage of submissions.
+//!
+//! Each submission is stored in its own directory `<workdir>/submissions/<id>`, containing
+//! `meta.json` with the [`Submission`] and the submitted file (`sprites.zip` or `portrait.png`).
+
+use std::fs::{create_dir_all, read, read_dir, write};
+use std::path::PathBuf;
+
+use anyhow::anyhow;
+use chrono::{DateTime, Utc};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Mutex, MutexGuard};
+
+use crate::config::Dataset;
+use crate::submissions::validation::ValidationReport;
+
+const SUBMISSIONS_DIR: &str = "submissions";
+const META_FILE: &str = "meta.json";
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SubmissionKind {
+    Sprites,
+    Portrait,
+}
+
+impl SubmissionKind {
+    /// Name of the asset directory in the SpriteCollab repository and prefix of the
+    /// corresponding tracker fields.
+    pub fn dir_name(&self) -> &'static str {
+        match self {
+            SubmissionKind::Sprites => "sprite",
+            SubmissionKind::Portrait => "portrait",
+        }
+    }
+
+    fn data_file_name(&self) -> &'static str {
+        match self {
+            SubmissionKind::Sprites => "sprites.zip",
+            SubmissionKind::Portrait => "portrait.png",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SubmissionStatus {
+    Pending,
+    /// Approved, the files are being committed. If this is still the state later, the approval
+    /// was interrupted and the commit may or may not exist.
+    Applying,
+    Approved,
+    Rejected,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Submission {
+    pub id: String,
+    pub kind: SubmissionKind,
+    pub monster_idx: i32,
+    pub form_path: Vec<i32>,
+    /// Only set for portraits.
+    pub emotion: Option<String>,
+    /// Name of the user that submitted this.
+    pub submitter: String,
+    /// Credit ID the submission is credited to once approved.
+    pub credit_id: String,
+    pub license: String,
+    pub submitted_date: DateTime<Utc>,
+    pub status: SubmissionStatus,
+    /// Name of the user that approved or rejected this.
+    pub reviewer: Option<String>,
+    pub reviewed_date: Option<DateTime<Utc>>,
+    pub rejection_reason: Option<String>,
+    /// The commit in the SpriteCollab repository that added the approved submission.
+    pub commit: Option<String>,
+    /// The validation result at the time of submission.
+    pub validation: ValidationReport,
+}
+
+impl Submission {
+    /// Whether the submission still waits for a review, from the point of view of users.
+    pub fn is_pending(&self) -> bool {
+        matches!(
+            self.status,
+            SubmissionStatus::Pending | SubmissionStatus::Applying
+        )
+    }
+
+    /// The line in the message of the commit that adds the submission, to find it again.
+    pub fn commit_trailer(&self) -> String {
+        format!("Submission: {}", self.id)
+    }
+}
+
+pub struct SubmissionStore {
+    dir: PathBuf,
+    // Serializes creating and reviewing submissions.
+    lock: Mutex<()>,
+}
+
+impl SubmissionStore {
+    pub fn new(dataset: &Dataset) -> Self {
+        Self {
+            dir: dataset.workdir().join(SUBMISSIONS_DIR),
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Stores a new submission. The ID and submission date are assigned here.
+    pub async fn create(
+        &self,
+        mut submission: Submission,
+        data: &[u8],
+    ) -> Result<Submission, anyhow::Error> {
+        let _guard = self.lock.lock().await;
+        let now = Utc::now();
+        let id_base = now.format("%Y%m%d-%H%M%S%6f").to_string();
+        let mut id = id_base.clone();
+        let mut counter = 1;
+        while self.dir.join(&id).exists() {
+            id = format!("{}-{}", id_base, counter);
+            counter += 1;
+        }
+        submission.id = id;
+        submission.submitted_date = now;
+
+        let path = self.dir.join(&submission.id);
+        create_dir_all(&path)?;
+        write(path.join(submission.kind.data_file_name()), data)?;
+        write(
+            path.join(META_FILE),
+            serde_json::to_vec_pretty(&submission)?,
+        )?;
+        Ok(submission)
+    }
+
+    /// Returns all submissions, oldest first. Unreadable submissions are skipped.
+    pub fn list(&self) -> Result<Vec<Submission>, anyhow::Error> {
+        if !self.dir.exists() {
+            return Ok(Vec::new());
+        }
+        let mut submissions = Vec::new();
+        for entry in read_dir(&self.dir)? {
+            let entry = entry?;
+            let id = entry.file_name().to_string_lossy().to_string();
+            match self.get(&id) {
+                Ok(Some(submission)) => submissions.push(submission),
+                Ok(None) => {}
+                Err(e) => warn!("Failed reading submission {}: {}", id, e),
+            }
+        }
+        submissions.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(submissions)
+    }
+
+    pub fn get(&self, id: &str) -> Result<Option<Submission>, anyhow::Error> {
+        if !is_valid_id(id) {
+            return Ok(None);
+        }
+        let path = self.dir.join(id).join(META_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_slice(&read(path)?)?))
+    }
+
+    /// Returns the submitted file.
+    pub fn read_data(&self, submission: &Submission) -> Result<Vec<u8>, anyhow::Error> {
+        if !is_valid_id(&submission.id) {
+            return Err(anyhow!("Invalid submission ID."));
+        }
+        Ok(read(
+            self.dir
+                .join(&submission.id)
+                .join(submission.kind.data_file_name()),
+        )?)
+    }
+
+    /// Acquires the lock that must be held while reviewing a submission, to prevent it from
+    /// being reviewed twice.
+    pub async fn lock(&self) -> MutexGuard<'_, ()> {
+        self.lock.lock().await
+    }
+
+    /// Returns the submission, if it exists and was not reviewed yet. This includes
+    /// submissions whose approval was interrupted.
+    pub fn get_pending(&self, id: &str) -> Result<Submission, anyhow::Error> {
+        let submission = self
+            .get(id)?
+            .ok_or_else(|| anyhow!("Submission {} does not exist.", id))?;
+        if !submission.is_pending() {
+            return Err(anyhow!("Submission {} was already reviewed.", id));
+        }
+        Ok(submission)
+    }
+
+    /// Writes the updated metadata of an existing submission.
+    pub fn save(&self, submission: &Submission) -> Result<(), anyhow::Error> {
+        if !is_valid_id(&submission.id) {
+            return Err(anyhow!("Invalid submission ID."));
+        }
+        write(
+            self.dir.join(&submission.id).join(META_FILE),
+            serde_json::to_vec_pretty(submission)?,
+        )?;
+        Ok(())
+    }
+}
+
+fn is_valid_id(id: &str) -> bool {
+    !id.is_empty() && id.chars().all(|c| c.is_ascii_digit() || c == '-')
+}
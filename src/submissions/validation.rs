@@ -0,0 +1,512 @@
+//! Validation of candidate sprite and portrait submissions. Runs the same checks that are
+//! applied to the data in the repository, without writing anything.
+
+use std::collections::BTreeMap;
+use std::io::{Cursor, Read};
+use std::path::Path;
+
+use image::{DynamicImage, GenericImageView};
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use zip::ZipArchive;
+
+use crate::assets::palette::PaletteComparison;
+use crate::assets::sprite_sheets::check_offsets_sheet;
+use crate::datafiles::anim_data_xml::AnimDataXml;
+use crate::datafiles::sprite_config::SpriteConfig;
+
+/// Maximum total uncompressed size of all files in a submitted sprite ZIP.
+const MAX_UNCOMPRESSED_ZIP_SIZE: u64 = 200 * 1024 * 1024;
+/// Number of rows (directions) a sprite sheet may have, besides 1.
+const SPRITE_DIRECTIONS: u32 = 8;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ValidationMessage {
+    /// The file the message applies to, if it applies to a specific file.
+    pub file: Option<String>,
+    pub message: String,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiffStatus {
+    Added,
+    Changed,
+    Unchanged,
+    Removed,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FileDiff {
+    pub file: String,
+    pub status: DiffStatus,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ValidationReport {
+    pub valid: bool,
+    pub errors: Vec<ValidationMessage>,
+    pub warnings: Vec<ValidationMessage>,
+    /// Differences to the assets currently stored for the form.
+    pub diff: Vec<FileDiff>,
+}
+
+impl ValidationReport {
+    fn error<S: ToString>(&mut self, file: Option<&str>, message: S) {
+        self.errors.push(ValidationMessage {
+            file: file.map(ToString::to_string),
+            message: message.to_string(),
+        });
+    }
+
+    fn warning<S: ToString>(&mut self, file: Option<&str>, message: S) {
+        self.warnings.push(ValidationMessage {
+            file: file.map(ToString::to_string),
+            message: message.to_string(),
+        });
+    }
+
+    fn finish(mut self) -> Self {
+        self.valid = self.errors.is_empty();
+        self
+    }
+}
+
+/// Validates a sprite ZIP in the format `make_sprite_zip` produces.
+///
+/// `sprite_path` is the directory of the currently stored sprites of the form, `base_sprite_path`
+/// the directory of the sprites of the non-shiny form, if the form is a shiny.
+pub fn validate_sprite_zip(
+    zip_bytes: &[u8],
+    sprite_config: &SpriteConfig,
+    sprite_path: &Path,
+    base_sprite_path: Option<&Path>,
+) -> ValidationReport {
+    let mut report = ValidationReport::default();
+
+    let files = match read_zip(zip_bytes) {
+        Ok(files) => files,
+        Err(e) => {
+            report.error(None, format!("Failed to read ZIP archive: {}", e));
+            return report.finish();
+        }
+    };
+
+    let xml = match files.get("AnimData.xml") {
+        Some(content) => match AnimDataXml::from_reader(Cursor::new(content)) {
+            Ok(xml) => xml,
+            Err(e) => {
+                report.error(Some("AnimData.xml"), format!("Failed to parse: {}", e));
+                return report.finish();
+            }
+        },
+        None => {
+            report.error(None, "AnimData.xml is missing.");
+            return report.finish();
+        }
+    };
+
+    let referenced_files = referenced_files(&xml);
+    let mut palette_comparison = PaletteComparison::default();
+
+    for anim in &xml.anims.anim {
+        if !sprite_config.actions.contains(&anim.name) {
+            report.warning(
+                Some("AnimData.xml"),
+                format!("Unknown action '{}'.", anim.name),
+            );
+        }
+        if let Some(copy_of) = &anim.copy_of {
+            if !xml.anims.anim.iter().any(|a| &a.name == copy_of) {
+                report.error(
+                    Some("AnimData.xml"),
+                    format!(
+                        "Action '{}' is a copy of '{}', which does not exist.",
+                        anim.name, copy_of
+                    ),
+                );
+            }
+            continue;
+        }
+        let (frame_width, frame_height) = match (anim.frame_width, anim.frame_height) {
+            (Some(w), Some(h)) if w > 0 && h > 0 => (w as u32, h as u32),
+            _ => {
+                report.error(
+                    Some("AnimData.xml"),
+                    format!("FrameWidth or FrameHeight missing for {}.", anim.name),
+                );
+                continue;
+            }
+        };
+
+        let anim_file = format!("{}-Anim.png", anim.name);
+        let offsets_file = format!("{}-Offsets.png", anim.name);
+        let shadow_file = format!("{}-Shadow.png", anim.name);
+
+        let Some(anim_img) = load_png(&mut report, &files, &anim_file) else {
+            continue;
+        };
+        let (width, height) = anim_img.dimensions();
+        if width % frame_width != 0 || height % frame_height != 0 {
+            report.error(
+                Some(&anim_file),
+                format!(
+                    "Sheet size {}x{} is not a multiple of the frame size {}x{}.",
+                    width, height, frame_width, frame_height
+                ),
+            );
+            continue;
+        }
+        let rows = height / frame_height;
+        if rows != 1 && rows != SPRITE_DIRECTIONS {
+            report.error(
+                Some(&anim_file),
+                format!(
+                    "Sheet has {} rows of frames, expected 1 or {}.",
+                    rows, SPRITE_DIRECTIONS
+                ),
+            );
+        }
+        let frames = width / frame_width;
+        match anim.durations.as_ref().and_then(|d| d.duration.as_ref()) {
+            Some(durations) if durations.len() as u32 != frames => report.error(
+                Some(&anim_file),
+                format!(
+                    "Sheet has {} frames per row, but AnimData.xml lists {} durations.",
+                    frames,
+                    durations.len()
+                ),
+            ),
+            None => report.error(
+                Some("AnimData.xml"),
+                format!("Durations missing for {}.", anim.name),
+            ),
+            _ => {}
+        }
+
+        for other_file in [&offsets_file, &shadow_file] {
+            if let Some(other_img) = load_png(&mut report, &files, other_file) {
+                if other_img.dimensions() != (width, height) {
+                    report.error(
+                        Some(other_file),
+                        format!(
+                            "Sheet size {}x{} does not match the size of {} ({}x{}).",
+                            other_img.width(),
+                            other_img.height(),
+                            anim_file,
+                            width,
+                            height
+                        ),
+                    );
+                } else if other_file == &offsets_file
+                    && let Err(e) = check_offsets_sheet(&other_img, frame_width, frame_height)
+                {
+                    report.error(Some(other_file), format!("Invalid offsets: {}", e));
+                }
+            }
+        }
+
+        if let Some(base_sprite_path) = base_sprite_path {
+            let base_anim_path = base_sprite_path.join(&anim_file);
+            if let Ok(base_img) = image::open(&base_anim_path) {
+                palette_comparison.compare(&anim_file, &base_img.to_rgba8(), &anim_img.to_rgba8());
+            }
+        }
+    }
+
+    for file in files.keys() {
+        if !referenced_files.contains(file) {
+            report.warning(
+                Some(file),
+                "File is not used by AnimData.xml and is ignored.",
+            );
+        }
+    }
+
+    add_palette_errors(&mut report, palette_comparison);
+    report.diff = diff_against_stored(&files, sprite_path);
+    report.finish()
+}
+
+/// Validates a single portrait for an emotion (flipped portraits end with `^`).
+///
+/// `portrait_path` is the directory of the currently stored portraits of the form,
+/// `base_portrait_path` the directory of the portraits of the non-shiny form, if the form
+/// is a shiny.
+pub fn validate_portrait(
+    png_bytes: &[u8],
+    emotion: &str,
+    sprite_config: &SpriteConfig,
+    portrait_path: &Path,
+    base_portrait_path: Option<&Path>,
+) -> ValidationReport {
+    let mut report = ValidationReport::default();
+    let file_name = format!("{}.png", emotion);
+
+    if !sprite_config
+        .emotions
+        .iter()
+        .any(|e| e == emotion.trim_end_matches('^'))
+    {
+        report.error(Some(&file_name), format!("Unknown emotion '{}'.", emotion));
+    }
+
+    let img = match image::load_from_memory(png_bytes) {
+        Ok(img) => img,
+        Err(e) => {
+            report.error(Some(&file_name), format!("Failed to read image: {}", e));
+            return report.finish();
+        }
+    };
+    let size = sprite_config.portrait_size as u32;
+    if img.dimensions() != (size, size) {
+        report.error(
+            Some(&file_name),
+            format!(
+                "Portrait size is {}x{}, expected {}x{}.",
+                img.width(),
+                img.height(),
+                size,
+                size
+            ),
+        );
+    }
+
+    if let Some(base_portrait_path) = base_portrait_path {
+        let mut palette_comparison = PaletteComparison::default();
+        if let Ok(base_img) = image::open(base_portrait_path.join(&file_name)) {
+            palette_comparison.compare(&file_name, &base_img.to_rgba8(), &img.to_rgba8());
+        } else {
+            report.warning(
+                Some(&file_name),
+                "The base form has no portrait for this emotion, palette was not checked.",
+            );
+        }
+        add_palette_errors(&mut report, palette_comparison);
+    }
+
+    let stored_path = portrait_path.join(&file_name);
+    report.diff = vec![FileDiff {
+        status: if !stored_path.exists() {
+            DiffStatus::Added
+        } else if file_equals(&file_name, png_bytes, &stored_path) {
+            DiffStatus::Unchanged
+        } else {
+            DiffStatus::Changed
+        },
+        file: file_name,
+    }];
+    report.finish()
+}
+
+/// The files of a sprite submission that are used: `AnimData.xml` and the sheets of all actions
+/// that aren't copies of other actions.
+pub fn referenced_files(xml: &AnimDataXml) -> Vec<String> {
+    let mut files = vec!["AnimData.xml".to_string()];
+    for anim in xml.anims.anim.iter().filter(|a| a.copy_of.is_none()) {
+        files.extend([
+            format!("{}-Anim.png", anim.name),
+            format!("{}-Offsets.png", anim.name),
+            format!("{}-Shadow.png", anim.name),
+        ]);
+    }
+    files
+}
+
+/// Reads all files of a ZIP archive into memory. Directories inside the archive are ignored.
+pub fn read_zip(zip_bytes: &[u8]) -> Result<IndexMap<String, Vec<u8>>, anyhow::Error> {
+    read_zip_limited(zip_bytes, MAX_UNCOMPRESSED_ZIP_SIZE)
+}
+
+/// Like [`read_zip`], with at most `max_size` uncompressed bytes. The sizes in the archive are
+/// not trusted, only the bytes actually read count.
+fn read_zip_limited(
+    zip_bytes: &[u8],
+    max_size: u64,
+) -> Result<IndexMap<String, Vec<u8>>, anyhow::Error> {
+    let mut archive = ZipArchive::new(Cursor::new(zip_bytes))?;
+    let mut files = IndexMap::with_capacity(archive.len());
+    let mut total_size = 0;
+    for i in 0..archive.len() {
+        let file = archive.by_index(i)?;
+        if file.is_dir() {
+            continue;
+        }
+        let name = file
+            .name()
+            .rsplit('/')
+            .next()
+            .unwrap_or_default()
+            .to_string();
+        if files.contains_key(&name) {
+            return Err(anyhow::anyhow!(
+                "The archive contains more than one file named {}.",
+                name
+            ));
+        }
+        let remaining = max_size - total_size;
+        let mut content = Vec::with_capacity(file.size().min(remaining) as usize);
+        file.take(remaining + 1).read_to_end(&mut content)?;
+        total_size += content.len() as u64;
+        if total_size > max_size {
+            return Err(anyhow::anyhow!(
+                "The uncompressed files are larger than {} bytes.",
+                max_size
+            ));
+        }
+        files.insert(name, content);
+    }
+    Ok(files)
+}
+
+fn load_png(
+    report: &mut ValidationReport,
+    files: &IndexMap<String, Vec<u8>>,
+    file: &str,
+) -> Option<DynamicImage> {
+    match files.get(file) {
+        Some(content) => match image::load_from_memory(content) {
+            Ok(img) => Some(img),
+            Err(e) => {
+                report.error(Some(file), format!("Failed to read image: {}", e));
+                None
+            }
+        },
+        None => {
+            report.error(Some(file), "File is missing.");
+            None
+        }
+    }
+}
+
+fn add_palette_errors(report: &mut ValidationReport, comparison: PaletteComparison) {
+    let palette_report = comparison.finish();
+    for mismatch in palette_report.shape_mismatches {
+        report.error(
+            Some(&mismatch.file),
+            format!("Shape does not match the base form: {}", mismatch.reason),
+        );
+    }
+    for collision in palette_report.collisions {
+        report.error(
+            Some(&collision.files.join(", ")),
+            format!(
+                "Colour {:?} of the base form is mapped to {} different colours: {:?}",
+                collision.base,
+                collision.shiny.len(),
+                collision.shiny
+            ),
+        );
+    }
+}
+
+fn diff_against_stored(files: &IndexMap<String, Vec<u8>>, stored_path: &Path) -> Vec<FileDiff> {
+    let mut diff = BTreeMap::new();
+    for (file, content) in files {
+        let path = stored_path.join(file);
+        let status = if !path.exists() {
+            DiffStatus::Added
+        } else if file_equals(file, content, &path) {
+            DiffStatus::Unchanged
+        } else {
+            DiffStatus::Changed
+        };
+        diff.insert(file.clone(), status);
+    }
+    if let Ok(entries) = std::fs::read_dir(stored_path) {
+        for entry in entries.flatten() {
+            let file = entry.file_name().to_string_lossy().to_string();
+            if file != "credits.txt" && !diff.contains_key(&file) {
+                diff.insert(file, DiffStatus::Removed);
+            }
+        }
+    }
+    diff.into_iter()
+        .map(|(file, status)| FileDiff { file, status })
+        .collect()
+}
+
+/// Compares a submitted file with a stored file. Images are compared by their pixels, since
+/// the encoding may differ.
+fn file_equals(file_name: &str, content: &[u8], stored_path: &Path) -> bool {
+    if file_name.ends_with(".png") {
+        match (image::load_from_memory(content), image::open(stored_path)) {
+            (Ok(a), Ok(b)) => a.to_rgba8() == b.to_rgba8(),
+            _ => false,
+        }
+    } else {
+        std::fs::read(stored_path)
+            .map(|stored| stored == content)
+            .unwrap_or(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use zip::ZipWriter;
+    use zip::write::SimpleFileOptions;
+
+    use super::*;
+
+    fn make_zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, content) in files {
+            zip.start_file(*name, SimpleFileOptions::default()).unwrap();
+            zip.write_all(content).unwrap();
+        }
+        zip.finish().unwrap().into_inner()
+    }
+
+    /// Sets the uncompressed size of all entries in the headers of a ZIP to `size`.
+    fn fake_sizes(zip: &mut [u8], size: u32) {
+        for i in 0..zip.len() - 4 {
+            let offset = match &zip[i..i + 4] {
+                [0x50, 0x4b, 0x03, 0x04] => 22,
+                [0x50, 0x4b, 0x01, 0x02] => 24,
+                _ => continue,
+            };
+            zip[i + offset..i + offset + 4].copy_from_slice(&size.to_le_bytes());
+        }
+    }
+
+    #[test]
+    fn reads_files_without_directories() {
+        let zip = make_zip(&[("sprites/AnimData.xml", b"xml"), ("Idle-Anim.png", b"png")]);
+        let files = read_zip_limited(&zip, 100).unwrap();
+        assert_eq!(
+            files.keys().collect::<Vec<_>>(),
+            ["AnimData.xml", "Idle-Anim.png"]
+        );
+        assert_eq!(files["AnimData.xml"], b"xml");
+    }
+
+    #[test]
+    fn accepts_files_up_to_the_limit() {
+        let zip = make_zip(&[("a.png", &[0; 60]), ("b.png", &[0; 40])]);
+        assert!(read_zip_limited(&zip, 100).is_ok());
+    }
+
+    #[test]
+    fn rejects_files_over_the_limit() {
+        let zip = make_zip(&[("a.png", &[0; 60]), ("b.png", &[0; 41])]);
+        let error = read_zip_limited(&zip, 100).unwrap_err();
+        assert!(error.to_string().contains("larger than 100 bytes"));
+    }
+
+    #[test]
+    fn limit_ignores_declared_sizes() {
+        let mut zip = make_zip(&[("a.png", &[0; 1000])]);
+        fake_sizes(&mut zip, 1);
+        let error = read_zip_limited(&zip, 100).unwrap_err();
+        assert!(error.to_string().contains("larger than 100 bytes"));
+    }
+
+    #[test]
+    fn rejects_duplicate_names() {
+        let zip = make_zip(&[("a/Idle-Anim.png", b"a"), ("b/Idle-Anim.png", b"b")]);
+        let error = read_zip_limited(&zip, 100).unwrap_err();
+        assert!(error.to_string().contains("Idle-Anim.png"));
+    }
+}
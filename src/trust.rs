@@ -0,0 +1,154 @@
+//! Optional policy that only accepts new commits of the tracked branch if they are signed by a
+//! trusted key.
+//!
+//! - `SCSRV_TRUST_POLICY`: `off` (default), `tip` (only the newest commit must be signed) or `all`
+//!   (all new commits must be signed).
+//! - `SCSRV_TRUSTED_SSH_KEYS`: File with trusted SSH public keys, one per line in the
+//!   `authorized_keys` format.
+//! - `SCSRV_TRUSTED_GPG_KEYRING`: Keyring with trusted GPG keys (`gpg --export > keyring.gpg`).
+//!   GPG signatures are checked with `gpgv`, which must be installed.
+
+use std::fs::{read_to_string, write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{Error, anyhow};
+use git2::{Oid, Repository};
+use log::{debug, warn};
+use ssh_key::{PublicKey, SshSig};
+
+use crate::config::{Config, Dataset};
+
+/// Namespace Git uses for SSH signatures.
+const SSH_NAMESPACE: &str = "git";
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TrustPolicy {
+    Off,
+    Tip,
+    All,
+}
+
+impl TrustPolicy {
+    pub fn from_config(dataset: &Dataset) -> Self {
+        match dataset.get_or_none(Config::TrustPolicy).as_deref() {
+            None | Some("off") => Self::Off,
+            Some("tip") => Self::Tip,
+            Some("all") => Self::All,
+            Some(v) => panic!("Invalid SCSRV_TRUST_POLICY: {}", v),
+        }
+    }
+}
+
+struct Keyring {
+    ssh_keys: Vec<PublicKey>,
+    gpg_keyring: Option<PathBuf>,
+}
+
+impl Keyring {
+    fn load(dataset: &Dataset) -> Result<Self, Error> {
+        let ssh_keys = match dataset.get_or_none(Config::TrustedSshKeys) {
+            Some(path) => read_to_string(&path)
+                .map_err(|e| anyhow!("Failed reading {}: {}", path, e))?
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(PublicKey::from_openssh)
+                .collect::<Result<_, _>>()?,
+            None => Vec::new(),
+        };
+        // gpgv resolves relative paths against its home directory.
+        let gpg_keyring = dataset
+            .get_or_none(Config::TrustedGpgKeyring)
+            .map(|path| Path::new(&path).canonicalize())
+            .transpose()?;
+        if ssh_keys.is_empty() && gpg_keyring.is_none() {
+            return Err(anyhow!(
+                "A trust policy is set, but no trusted keys are configured."
+            ));
+        }
+        Ok(Self {
+            ssh_keys,
+            gpg_keyring,
+        })
+    }
+
+    fn verify(&self, repo: &Repository, commit: Oid) -> Result<(), Error> {
+        let (signature, signed_data) = repo
+            .extract_signature(&commit, None)
+            .map_err(|_| anyhow!("Commit {} is not signed.", commit))?;
+        let valid = if signature.starts_with(b"-----BEGIN SSH SIGNATURE-----") {
+            let signature = SshSig::from_pem(&*signature)?;
+            self.ssh_keys
+                .iter()
+                .any(|key| key.verify(SSH_NAMESPACE, &signed_data, &signature).is_ok())
+        } else if signature.starts_with(b"-----BEGIN PGP SIGNATURE-----") {
+            match &self.gpg_keyring {
+                Some(keyring) => verify_gpg(keyring, &signature, &signed_data)?,
+                None => false,
+            }
+        } else {
+            return Err(anyhow!(
+                "Commit {} has an unknown signature format.",
+                commit
+            ));
+        };
+        if valid {
+            debug!("Commit {} has a trusted signature.", commit);
+            Ok(())
+        } else {
+            Err(anyhow!("Commit {} is not signed by a trusted key.", commit))
+        }
+    }
+}
+
+/// Checks the new commits between `previous` (the last trusted commit) and `head` according to
+/// the configured policy. Without `previous`, only `head` is checked.
+pub fn verify_new_commits(
+    dataset: &Dataset,
+    repo: &Repository,
+    previous: Option<Oid>,
+    head: Oid,
+) -> Result<(), Error> {
+    let policy = TrustPolicy::from_config(dataset);
+    if policy == TrustPolicy::Off || previous == Some(head) {
+        return Ok(());
+    }
+    let keyring = Keyring::load(dataset)?;
+    let result = match (policy, previous) {
+        (TrustPolicy::All, Some(previous)) => {
+            let mut walk = repo.revwalk()?;
+            walk.push(head)?;
+            walk.hide(previous)?;
+            walk.try_for_each(|commit| keyring.verify(repo, commit?))
+        }
+        _ => keyring.verify(repo, head),
+    };
+    if let Err(e) = &result {
+        warn!("Rejected untrusted commit {}: {}", head, e);
+    }
+    result.map_err(|e| anyhow!("Untrusted commit: {}", e))
+}
+
+/// Verifies a detached GPG signature with `gpgv`.
+fn verify_gpg(keyring: &Path, signature: &[u8], data: &[u8]) -> Result<bool, Error> {
+    let dir = tempfile::tempdir()?;
+    let signature_path = dir.path().join("commit.sig");
+    let data_path = dir.path().join("commit");
+    write(&signature_path, signature)?;
+    write(&data_path, data)?;
+    let output = Command::new("gpgv")
+        .arg("--keyring")
+        .arg(keyring)
+        .arg(&signature_path)
+        .arg(&data_path)
+        .output()
+        .map_err(|e| anyhow!("Failed running gpgv: {}", e))?;
+    if !output.status.success() {
+        debug!(
+            "gpgv rejected the signature: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    Ok(output.status.success())
+}
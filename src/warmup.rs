@@ -0,0 +1,254 @@
+//! Cache warmup: Renders sheets and ZIP archives ahead of time after the data changed, so the
+//! first visitors don't have to wait for them.
+//!
+//! After a refresh, the assets of the changed forms are rendered, on startup the assets of all
+//! forms. Forms are rendered in the order of `SCSRV_WARMUP_PRIORITY`, by
+//! `SCSRV_WARMUP_WORKERS` workers at a time.
+
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{Error, anyhow};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use log::{debug, info, warn};
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+use crate::assets::rendered::{RenderData, RenderedAsset};
+use crate::datafiles::group_id::GroupId;
+use crate::datafiles::tracker::{ChangeKind, FormChange, Group, MonsterFormCollector, Tracker};
+use crate::jobs::{Job, WARMUP_JOB};
+use crate::sprite_collab::SpriteCollab;
+
+/// The forms to warm up.
+pub enum WarmupScope {
+    All,
+    Forms(Vec<FormChange>),
+}
+
+impl WarmupScope {
+    /// Combines two scopes into one that includes both.
+    pub fn merge(self, other: WarmupScope) -> WarmupScope {
+        match (self, other) {
+            (WarmupScope::Forms(mut forms), WarmupScope::Forms(other)) => {
+                forms.extend(other);
+                WarmupScope::Forms(forms)
+            }
+            _ => WarmupScope::All,
+        }
+    }
+}
+
+/// A criterion for the order in which forms are warmed up.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum WarmupPriority {
+    /// Canon forms first.
+    Canon,
+    /// Base forms of monsters first, then forms by how deep they are nested.
+    Base,
+    /// Most recently modified forms first.
+    Recent,
+}
+
+impl WarmupPriority {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "canon" => Some(WarmupPriority::Canon),
+            "base" => Some(WarmupPriority::Base),
+            "recent" => Some(WarmupPriority::Recent),
+            _ => None,
+        }
+    }
+
+    fn compare(&self, a: &WarmupForm, b: &WarmupForm) -> Ordering {
+        match self {
+            WarmupPriority::Canon => b.canon.cmp(&a.canon),
+            WarmupPriority::Base => a.form_path.len().cmp(&b.form_path.len()),
+            WarmupPriority::Recent => b.modified.cmp(&a.modified),
+        }
+    }
+}
+
+struct WarmupForm {
+    monster_idx: i32,
+    form_path: Vec<i32>,
+    canon: bool,
+    modified: Option<DateTime<Utc>>,
+    portraits: bool,
+    sprites: bool,
+}
+
+impl WarmupForm {
+    fn new(monster_idx: i32, form_path: Vec<i32>, group: &Group) -> Self {
+        Self {
+            monster_idx,
+            form_path,
+            canon: group.canon,
+            modified: group.portrait_modified.max(group.sprite_modified),
+            portraits: !group.portrait_files.is_empty(),
+            sprites: !group.sprite_files.is_empty(),
+        }
+    }
+}
+
+/// Renders the assets of the forms queued with [`SpriteCollab::queue_warmup`].
+pub struct WarmupJob(pub Arc<SpriteCollab>);
+
+#[async_trait]
+impl Job for WarmupJob {
+    fn name(&self) -> &'static str {
+        WARMUP_JOB
+    }
+
+    fn interval(&self) -> Option<Duration> {
+        None
+    }
+
+    async fn run(&self, _triggered_by: &str) -> Result<(), Error> {
+        let sprite_collab = &self.0;
+        let Some(scope) = sprite_collab.take_warmup() else {
+            return Ok(());
+        };
+        let dataset = sprite_collab.dataset();
+        let render_data = Arc::new(RenderData::new(sprite_collab));
+        let mut forms = collect_forms(&render_data.tracker, scope);
+        let priorities = dataset.warmup_priority();
+        forms.sort_by(|a, b| {
+            priorities
+                .iter()
+                .map(|p| p.compare(a, b))
+                .find(|o| o.is_ne())
+                .unwrap_or_else(|| {
+                    (a.monster_idx, &a.form_path).cmp(&(b.monster_idx, &b.form_path))
+                })
+        });
+
+        // The forms the assets are rendered from, in order and without duplicates.
+        let mut seen = HashSet::new();
+        let mut tasks = Vec::new();
+        for form in forms {
+            let Some(collector) =
+                MonsterFormCollector::collect(&render_data.tracker, form.monster_idx)
+            else {
+                continue;
+            };
+            for asset in dataset.warmup_assets() {
+                let wanted = match asset {
+                    RenderedAsset::PortraitSheet | RenderedAsset::PortraitRecolorSheet => {
+                        form.portraits
+                    }
+                    RenderedAsset::SpriteRecolorSheet | RenderedAsset::SpriteZip => form.sprites,
+                };
+                if !wanted {
+                    continue;
+                }
+                if let Some((form_path, _, _)) = asset.find_form(&collector, form.form_path.clone())
+                    && seen.insert((asset, form.monster_idx, form_path.clone()))
+                {
+                    tasks.push((asset, form.monster_idx, form_path));
+                }
+            }
+        }
+
+        info!("Warming up the cache with {} assets...", tasks.len());
+        let start = Instant::now();
+        let total = tasks.len();
+        let workers = Arc::new(Semaphore::new(dataset.warmup_workers()));
+        let mut running = JoinSet::new();
+        for (asset, monster_idx, form_path) in tasks {
+            let permit = workers.clone().acquire_owned().await?;
+            if sprite_collab.jobs().is_stopping() {
+                debug!("Stopping cache warmup, the server shuts down.");
+                break;
+            }
+            let sprite_collab = sprite_collab.clone();
+            let render_data = render_data.clone();
+            running.spawn(async move {
+                let _permit = permit;
+                let result = match MonsterFormCollector::collect(&render_data.tracker, monster_idx)
+                    .and_then(|collector| {
+                        asset
+                            .find_form(&collector, form_path.clone())
+                            .map(|(_, _, group)| group.clone())
+                    }) {
+                    Some(group) => render_data
+                        .render(&sprite_collab, asset, monster_idx, &form_path, &group)
+                        .await
+                        .and_then(|r| r),
+                    None => Err(anyhow!("Form not found.")),
+                };
+                if let Err(e) = &result {
+                    warn!(
+                        "Failed warming up {} of {}/{:?}: {}",
+                        asset.name(),
+                        monster_idx,
+                        form_path,
+                        e
+                    );
+                }
+                result.is_ok()
+            });
+        }
+        let mut failed = 0;
+        while let Some(result) = running.join_next().await {
+            if !result.unwrap_or(false) {
+                failed += 1;
+            }
+        }
+        info!(
+            "Warmed up the cache in {:.1}s.",
+            start.elapsed().as_secs_f32()
+        );
+        if failed > 0 {
+            return Err(anyhow!("Failed rendering {} of {} assets.", failed, total));
+        }
+        Ok(())
+    }
+}
+
+/// The forms in the scope that still exist.
+fn collect_forms(tracker: &Tracker, scope: WarmupScope) -> Vec<WarmupForm> {
+    let mut forms = Vec::new();
+    match scope {
+        WarmupScope::All => {
+            let mut groups: Vec<(i32, Vec<i32>, &Group)> = tracker
+                .iter()
+                .map(|(idx, group)| (**idx as i32, Vec::new(), group))
+                .collect();
+            while let Some((monster_idx, form_path, group)) = groups.pop() {
+                for (sub_idx, subgroup) in group.subgroups.iter() {
+                    let mut sub_path = form_path.clone();
+                    sub_path.push(**sub_idx as i32);
+                    groups.push((monster_idx, sub_path, subgroup));
+                }
+                forms.push(WarmupForm::new(monster_idx, form_path, group));
+            }
+        }
+        WarmupScope::Forms(changes) => {
+            for change in changes {
+                if change.change == ChangeKind::Removed {
+                    continue;
+                }
+                let Some(group) = group_at(tracker, change.monster_id, &change.form_path) else {
+                    continue;
+                };
+                let mut form = WarmupForm::new(change.monster_id, change.form_path, group);
+                form.portraits &= change.portraits;
+                form.sprites &= change.sprites;
+                forms.push(form);
+            }
+        }
+    }
+    forms
+}
+
+fn group_at<'a>(tracker: &'a Tracker, monster_idx: i32, form_path: &[i32]) -> Option<&'a Group> {
+    let mut group = tracker.get(&GroupId(monster_idx as i64))?;
+    for idx in form_path {
+        group = group.subgroups.get(&GroupId(*idx as i64))?;
+    }
+    Some(group)
+}
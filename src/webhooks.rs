@@ -0,0 +1,437 @@
+//! Outbound webhooks, sent whenever new data is installed.
+//!
+//! Deliveries are kept in a queue that is persisted to the workdir, so they survive restarts.
+//! Deliveries are sent by the `webhooks` job. Failed deliveries are retried with exponential
+//! backoff.
+//!
+//! If `SCSRV_WEBHOOK_SECRET` is set, the body is signed with HMAC-SHA256 and the signature is sent
+//! as `X-SpriteCollab-Signature-256: sha256=<hex>`.
+
+use std::fs::read;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use log::{debug, error, warn};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::config::{Config, Dataset};
+use crate::datafiles::tracker::FormChange;
+use crate::jobs::{Job, WEBHOOKS_JOB};
+
+const QUEUE_FILE: &str = "webhook_queue.json";
+const EVENT_DATA_REFRESHED: &str = "data_refreshed";
+const SIGNATURE_HEADER: &str = "X-SpriteCollab-Signature-256";
+/// Deliveries are dropped after this many failed attempts.
+const MAX_ATTEMPTS: u32 = 12;
+const BASE_BACKOFF_SECS: i64 = 10;
+const MAX_BACKOFF_SECS: i64 = 60 * 60;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Serialize)]
+struct DataRefreshedPayload<'a> {
+    event: &'static str,
+    /// Name of the dataset, if several datasets are served.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dataset: Option<&'a str>,
+    old_commit: &'a str,
+    new_commit: &'a str,
+    /// Forms whose tracker entries changed.
+    changed_forms: &'a [FormChange],
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Delivery {
+    id: String,
+    url: String,
+    event: String,
+    body: String,
+    attempts: u32,
+    next_attempt: DateTime<Utc>,
+}
+
+pub struct Webhooks {
+    dataset: Option<String>,
+    urls: Vec<String>,
+    secret: Option<String>,
+    queue_path: PathBuf,
+    queue: Mutex<Vec<Delivery>>,
+    /// Held while the queue is written, so an older state never overwrites a newer one.
+    persisting: tokio::sync::Mutex<()>,
+    client: reqwest::Client,
+}
+
+impl Webhooks {
+    /// Reads the configured webhook URLs (`SCSRV_WEBHOOK_URLS`, comma separated) and the
+    /// persisted queue.
+    pub fn new(dataset: &Dataset) -> Self {
+        let urls = dataset
+            .get_or_none(Config::WebhookUrls)
+            .map(|urls| {
+                urls.split(',')
+                    .map(str::trim)
+                    .filter(|url| !url.is_empty())
+                    .map(ToString::to_string)
+                    .collect()
+            })
+            .unwrap_or_default();
+        Self::with_settings(
+            dataset.name().map(ToString::to_string),
+            urls,
+            dataset.get_or_none(Config::WebhookSecret),
+            dataset.workdir().join(QUEUE_FILE),
+        )
+    }
+
+    fn with_settings(
+        dataset: Option<String>,
+        urls: Vec<String>,
+        secret: Option<String>,
+        queue_path: PathBuf,
+    ) -> Self {
+        let queue = if queue_path.exists() {
+            read(&queue_path)
+                .map_err(anyhow::Error::from)
+                .and_then(|content| Ok(serde_json::from_slice(&content)?))
+                .unwrap_or_else(|e| {
+                    error!("Failed reading webhook queue, discarding it: {}", e);
+                    Vec::new()
+                })
+        } else {
+            Vec::new()
+        };
+        Self {
+            urls,
+            dataset,
+            secret,
+            queue_path,
+            queue: Mutex::new(queue),
+            persisting: tokio::sync::Mutex::new(()),
+            client: reqwest::Client::builder()
+                .timeout(REQUEST_TIMEOUT)
+                .user_agent(concat!("spritecollab-srv/", env!("CARGO_PKG_VERSION")))
+                .build()
+                .expect("Failed to create HTTP client."),
+        }
+    }
+
+    /// Queues a notification about newly installed data for all configured URLs.
+    pub async fn data_refreshed(
+        &self,
+        old_commit: &str,
+        new_commit: &str,
+        changed_forms: &[FormChange],
+    ) {
+        if self.urls.is_empty() {
+            return;
+        }
+        let body = match serde_json::to_string(&DataRefreshedPayload {
+            event: EVENT_DATA_REFRESHED,
+            dataset: self.dataset.as_deref(),
+            old_commit,
+            new_commit,
+            changed_forms,
+        }) {
+            Ok(body) => body,
+            Err(e) => {
+                error!("Failed serializing webhook payload: {}", e);
+                return;
+            }
+        };
+        let now = Utc::now();
+        {
+            let mut queue = self.queue.lock().unwrap();
+            for (idx, url) in self.urls.iter().enumerate() {
+                queue.push(Delivery {
+                    id: format!("{}-{}", now.format("%Y%m%d-%H%M%S%6f"), idx),
+                    url: url.clone(),
+                    event: EVENT_DATA_REFRESHED.to_string(),
+                    body: body.clone(),
+                    attempts: 0,
+                    next_attempt: now,
+                });
+            }
+        }
+        self.persist().await;
+    }
+
+    /// Whether there is anything to deliver, now or in the future.
+    pub fn is_active(&self) -> bool {
+        !self.urls.is_empty() || !self.queue.lock().unwrap().is_empty()
+    }
+
+    /// Delivers all queued webhooks that are due. Failed deliveries are logged and retried with
+    /// their own backoff, so they don't delay the others.
+    async fn deliver_due(&self) -> Result<(), anyhow::Error> {
+        let now = Utc::now();
+        let due: Vec<Delivery> = self
+            .queue
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|d| d.next_attempt <= now)
+            .cloned()
+            .collect();
+        for delivery in due {
+            let result = self.send(&delivery).await;
+            {
+                let mut queue = self.queue.lock().unwrap();
+                match result {
+                    Ok(()) => {
+                        debug!("Delivered webhook {} to {}.", delivery.id, delivery.url);
+                        queue.retain(|d| d.id != delivery.id || d.url != delivery.url);
+                    }
+                    Err(e) if delivery.attempts + 1 >= MAX_ATTEMPTS => {
+                        error!(
+                            "Failed delivering webhook {} to {}, giving up: {}",
+                            delivery.id, delivery.url, e
+                        );
+                        queue.retain(|d| d.id != delivery.id || d.url != delivery.url);
+                    }
+                    Err(e) => {
+                        warn!(
+                            "Failed delivering webhook {} to {}, retrying: {}",
+                            delivery.id, delivery.url, e
+                        );
+                        if let Some(d) = queue
+                            .iter_mut()
+                            .find(|d| d.id == delivery.id && d.url == delivery.url)
+                        {
+                            d.attempts += 1;
+                            d.next_attempt = Utc::now() + backoff(d.attempts);
+                        }
+                    }
+                }
+            }
+            self.persist().await;
+        }
+        Ok(())
+    }
+
+    async fn send(&self, delivery: &Delivery) -> Result<(), anyhow::Error> {
+        let mut request = self
+            .client
+            .post(&delivery.url)
+            .header("Content-Type", "application/json")
+            .header("X-SpriteCollab-Event", &delivery.event)
+            .header("X-SpriteCollab-Delivery", &delivery.id)
+            .body(delivery.body.clone());
+        if let Some(secret) = &self.secret {
+            request = request.header(
+                SIGNATURE_HEADER,
+                sign(secret.as_bytes(), delivery.body.as_bytes()),
+            );
+        }
+        let response = request.send().await?;
+        if !response.status().is_success() {
+            return Err(anyhow!("Server responded with {}", response.status()));
+        }
+        Ok(())
+    }
+
+    async fn persist(&self) {
+        let _persisting = self.persisting.lock().await;
+        if let Err(e) = self.write_queue().await {
+            error!("Failed writing webhook queue: {}", e);
+        }
+    }
+
+    async fn write_queue(&self) -> Result<(), anyhow::Error> {
+        let content = serde_json::to_vec(&*self.queue.lock().unwrap())?;
+        let tmp_path = self.queue_path.with_extension("json.tmp");
+        tokio::fs::write(&tmp_path, content).await?;
+        tokio::fs::rename(&tmp_path, &self.queue_path).await?;
+        Ok(())
+    }
+}
+
+/// Delivers queued webhooks. Also triggered whenever new data is installed.
+pub struct WebhookJob(pub Arc<Webhooks>);
+
+#[async_trait]
+impl Job for WebhookJob {
+    fn name(&self) -> &'static str {
+        WEBHOOKS_JOB
+    }
+
+    fn interval(&self) -> Option<Duration> {
+        Some(Duration::from_secs(BASE_BACKOFF_SECS as u64))
+    }
+
+    async fn run(&self, _triggered_by: &str) -> Result<(), anyhow::Error> {
+        self.0.deliver_due().await
+    }
+}
+
+/// Signs the body with HMAC-SHA256, formatted as `sha256=<hex>`.
+pub fn sign(secret: &[u8], body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any size");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+fn backoff(attempts: u32) -> chrono::Duration {
+    let secs = BASE_BACKOFF_SECS
+        .saturating_mul(1 << attempts.min(16))
+        .min(MAX_BACKOFF_SECS);
+    chrono::Duration::seconds(secs)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use http_body_util::{BodyExt, Full};
+    use hyper::body::{Bytes, Incoming};
+    use hyper::header::HeaderMap;
+    use hyper::server::conn::http1;
+    use hyper::service::service_fn;
+    use hyper::{Request, Response, StatusCode};
+    use hyper_util::rt::TokioIo;
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    const SECRET: &str = "It's a Secret to Everybody";
+
+    type Received = Arc<Mutex<Vec<(HeaderMap, Bytes)>>>;
+
+    /// Starts an HTTP server that records the requests and answers all of them with `status`.
+    /// Returns its URL.
+    async fn stand_in(status: StatusCode) -> (String, Received) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let received = Received::default();
+        let recorded = received.clone();
+        tokio::spawn(async move {
+            loop {
+                let (conn, _) = listener.accept().await.unwrap();
+                let recorded = recorded.clone();
+                let service = service_fn(move |req: Request<Incoming>| {
+                    let recorded = recorded.clone();
+                    async move {
+                        let headers = req.headers().clone();
+                        let body = req.into_body().collect().await.unwrap().to_bytes();
+                        recorded.lock().unwrap().push((headers, body));
+                        let mut response = Response::new(Full::new(Bytes::new()));
+                        *response.status_mut() = status;
+                        Ok::<_, Infallible>(response)
+                    }
+                });
+                tokio::spawn(http1::Builder::new().serve_connection(TokioIo::new(conn), service));
+            }
+        });
+        (url, received)
+    }
+
+    fn webhooks(url: &str, dir: &tempfile::TempDir) -> Webhooks {
+        Webhooks::with_settings(
+            Some("test".to_string()),
+            vec![url.to_string()],
+            Some(SECRET.to_string()),
+            dir.path().join(QUEUE_FILE),
+        )
+    }
+
+    #[tokio::test]
+    async fn signs_the_body() {
+        let (url, received) = stand_in(StatusCode::OK).await;
+        let dir = tempfile::tempdir().unwrap();
+        let webhooks = webhooks(&url, &dir);
+        webhooks.data_refreshed("old", "new", &[]).await;
+        webhooks.deliver_due().await.unwrap();
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 1);
+        let (headers, body) = &received[0];
+        let payload: serde_json::Value = serde_json::from_slice(body).unwrap();
+        assert_eq!(payload["event"], EVENT_DATA_REFRESHED);
+        assert_eq!(payload["dataset"], "test");
+        assert_eq!(payload["new_commit"], "new");
+        assert_eq!(
+            headers[SIGNATURE_HEADER].to_str().unwrap(),
+            sign(SECRET.as_bytes(), body)
+        );
+        assert_eq!(headers["X-SpriteCollab-Event"], EVENT_DATA_REFRESHED);
+        assert!(webhooks.queue.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn signature_matches_known_value() {
+        assert_eq!(
+            sign(SECRET.as_bytes(), b"Hello, World!"),
+            "sha256=757107ea0eb2509fc211221cce984b8a37570b6d7586c22c46f4379c8b043e17"
+        );
+    }
+
+    #[tokio::test]
+    async fn queue_survives_reload() {
+        let dir = tempfile::tempdir().unwrap();
+        let url = "http://127.0.0.1:1/hook";
+        webhooks(url, &dir).data_refreshed("old", "new", &[]).await;
+
+        let reloaded = webhooks(url, &dir);
+        let queue = reloaded.queue.lock().unwrap();
+        assert_eq!(queue.len(), 1);
+        assert_eq!(queue[0].url, url);
+        assert_eq!(queue[0].attempts, 0);
+        assert!(queue[0].body.contains("\"old_commit\":\"old\""));
+    }
+
+    #[tokio::test]
+    async fn corrupt_queue_is_discarded() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join(QUEUE_FILE), "not json").unwrap();
+        let webhooks = webhooks("http://127.0.0.1:1/hook", &dir);
+        assert!(webhooks.queue.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn retries_with_backoff_until_giving_up() {
+        let (url, received) = stand_in(StatusCode::INTERNAL_SERVER_ERROR).await;
+        let dir = tempfile::tempdir().unwrap();
+        let webhooks = webhooks(&url, &dir);
+        webhooks.data_refreshed("old", "new", &[]).await;
+
+        for attempt in 1..MAX_ATTEMPTS {
+            webhooks.deliver_due().await.unwrap();
+            {
+                let queue = webhooks.queue.lock().unwrap();
+                assert_eq!(queue[0].attempts, attempt);
+                assert!(
+                    queue[0].next_attempt > Utc::now() + backoff(attempt) - Duration::from_secs(5)
+                );
+            }
+            // The retry isn't due yet.
+            webhooks.deliver_due().await.unwrap();
+            assert_eq!(received.lock().unwrap().len(), attempt as usize);
+            webhooks.queue.lock().unwrap()[0].next_attempt = Utc::now();
+        }
+        // The retry state is persisted as well.
+        assert_eq!(
+            Webhooks::with_settings(None, Vec::new(), None, dir.path().join(QUEUE_FILE))
+                .queue
+                .lock()
+                .unwrap()[0]
+                .attempts,
+            MAX_ATTEMPTS - 1
+        );
+
+        webhooks.deliver_due().await.unwrap();
+        assert_eq!(received.lock().unwrap().len(), MAX_ATTEMPTS as usize);
+        assert!(webhooks.queue.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn backoff_grows_up_to_the_maximum() {
+        assert_eq!(backoff(0).num_seconds(), BASE_BACKOFF_SECS);
+        assert_eq!(backoff(1).num_seconds(), BASE_BACKOFF_SECS * 2);
+        assert_eq!(backoff(3).num_seconds(), BASE_BACKOFF_SECS * 8);
+        assert_eq!(backoff(MAX_ATTEMPTS).num_seconds(), MAX_BACKOFF_SECS);
+    }
+}